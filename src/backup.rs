@@ -0,0 +1,81 @@
+// Backups of the documents of an index, saved before it's deleted. They have the bulk API format of
+// `BulkFileSink`, so that they can be loaded again with the restore command, or with curl:
+//   {"index":{"_index":"xmas-tree-recycling","_id":"ef89fd..."}}
+//   {"dataset_id":"collecte-des-sapins-de-noel","record_id":"ef89fd...",...}
+use crate::index;
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use elasticsearch::Elasticsearch;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+
+// Write all documents of `index` to a new 'backup-<index>-<timestamp>.ndjson' file in `dir`, and return its
+// path. Returns None if the index doesn't exist.
+#[instrument(name = "backup", skip(es_client))]
+pub async fn backup_index(es_client: &Elasticsearch, index: &str, dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    if !index::index_exists(es_client, index).await? {
+        return Ok(None);
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Cannot create backup directory {}", dir.display()))?;
+    let path = dir.join(format!("backup-{}-{}.ndjson", index, Utc::now().format("%Y%m%d-%H%M%S")));
+    let file = File::create(&path).with_context(|| format!("Cannot create backup file {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+
+    let written = async {
+        let count = index::scroll(es_client, index, json!(true), |id, source| {
+            serde_json::to_writer(&mut out, &json!({ "index": { "_index": index, "_id": id } }))?;
+            writeln!(out)?;
+            serde_json::to_writer(&mut out, &source)?;
+            writeln!(out)?;
+            Ok(())
+        }).await?;
+        out.flush()?;
+        Ok::<_, anyhow::Error>(count)
+    }.await;
+
+    match written {
+        Ok(count) => {
+            info!("Saved the {} documents of index {} to {}", count, index, path.display());
+            Ok(Some(path))
+        }
+        Err(err) => {
+            // An incomplete backup could be mistaken for a good one
+            let _ = std::fs::remove_file(&path);
+            Err(err.context(format!("Cannot back up index {}, it was left untouched", index)))
+        }
+    }
+}
+
+// The id and source of the documents of a backup file
+pub fn read_backup(path: &Path) -> anyhow::Result<Vec<(String, Value)>> {
+    let file = File::open(path).with_context(|| format!("Cannot open backup file {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines().enumerate();
+    let invalid = |line: usize, reason: String| {
+        anyhow!("Invalid backup file {}, line {}: {}", path.display(), line + 1, reason)
+    };
+
+    let mut documents = Vec::new();
+    while let Some((number, action)) = lines.next() {
+        let action = action.with_context(|| format!("Cannot read backup file {}", path.display()))?;
+        if action.trim().is_empty() {
+            continue;
+        }
+        let action: Value = serde_json::from_str(&action).map_err(|err| invalid(number, err.to_string()))?;
+        let id = action["index"]["_id"].as_str()
+            .ok_or_else(|| invalid(number, "expected an index action with an _id".to_string()))?
+            .to_string();
+
+        let (number, source) = lines.next().ok_or_else(|| invalid(number, "the action has no document".to_string()))?;
+        let source = source.with_context(|| format!("Cannot read backup file {}", path.display()))?;
+        let source: Value = serde_json::from_str(&source).map_err(|err| invalid(number, err.to_string()))?;
+        if !source.is_object() {
+            return Err(invalid(number, "expected a document".to_string()));
+        }
+        documents.push((id, source));
+    }
+    Ok(documents)
+}
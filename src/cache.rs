@@ -0,0 +1,90 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
+use tracing::{debug, warn};
+
+// A local copy of the source data along with the HTTP validators needed to know if it's still current
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedData {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+// Source data cache, with one entry per source URL and index. Each entry is a body file and a metadata file.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Cache {
+        Cache { dir }
+    }
+
+    // `$XDG_CACHE_HOME/toulouse-xmas`, or `~/.cache/toulouse-xmas`
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|dir| dir.join("toulouse-xmas"))
+    }
+
+    // The cached data for `url` loaded into `index`. A broken entry is ignored, the data will be downloaded again.
+    pub fn get(&self, url: &str, index: &str) -> Option<CachedData> {
+        let (body_path, metadata_path) = self.paths(url, index);
+        if !metadata_path.exists() {
+            return None;
+        }
+
+        let read = || -> anyhow::Result<CachedData> {
+            let metadata: Metadata = serde_json::from_slice(&std::fs::read(&metadata_path)?)?;
+            Ok(CachedData {
+                etag: metadata.etag,
+                last_modified: metadata.last_modified,
+                body: std::fs::read(&body_path)?,
+            })
+        };
+        match read() {
+            Ok(data) => Some(data),
+            Err(err) => {
+                warn!("Ignoring cache entry {}: {}", metadata_path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, url: &str, index: &str, data: &CachedData) -> anyhow::Result<()> {
+        let (body_path, metadata_path) = self.paths(url, index);
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {}", self.dir.display()))?;
+
+        let metadata = Metadata {
+            url: url.to_string(),
+            etag: data.etag.clone(),
+            last_modified: data.last_modified.clone(),
+        };
+        // Write the metadata last, so that an interrupted write leaves no entry
+        if metadata_path.exists() {
+            std::fs::remove_file(&metadata_path)?;
+        }
+        std::fs::write(&body_path, &data.body)?;
+        std::fs::write(&metadata_path, serde_json::to_vec_pretty(&metadata)?)?;
+        debug!("Cached source data in {}", body_path.display());
+        Ok(())
+    }
+
+    fn paths(&self, url: &str, index: &str) -> (PathBuf, PathBuf) {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{}\n{}", index, url));
+        let key: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        (self.dir.join(format!("{}.json", key)), self.dir.join(format!("{}.meta.json", key)))
+    }
+}
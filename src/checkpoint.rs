@@ -0,0 +1,210 @@
+// The places that a run stored in its target index, saved after each bulk request so that a run that failed or
+// was interrupted can be resumed with --resume: it adds the places that weren't stored, or that changed since, to
+// the same index instead of starting over.
+use crate::index::BatchOutcome;
+use crate::sink::Sink;
+use crate::transform::IndexedPlace;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+pub const FILE: &str = ".ingest-checkpoint.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub run_id: String,
+    pub dataset: String,
+    // The index or alias that the run loads, and the index the documents go to, which is another one with --alias
+    // and --staged
+    pub index: String,
+    pub target_index: String,
+    pub source_url: String,
+    pub updated_at: DateTime<Utc>,
+    // Content hashes of the stored places, by record id
+    pub stored: BTreeMap<String, String>,
+}
+
+impl Checkpoint {
+    pub fn new(run_id: &str, dataset: &str, index: &str, target_index: &str, source_url: &str) -> Checkpoint {
+        Checkpoint {
+            run_id: run_id.to_string(),
+            dataset: dataset.to_string(),
+            index: index.to_string(),
+            target_index: target_index.to_string(),
+            source_url: source_url.to_string(),
+            updated_at: Utc::now(),
+            stored: BTreeMap::new(),
+        }
+    }
+
+    // None if there's no checkpoint at `path`
+    pub fn load(path: &Path) -> anyhow::Result<Option<Checkpoint>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("Cannot read checkpoint {}", path.display())),
+        };
+        let checkpoint = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid checkpoint {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    // Resuming with other data, or into another index, would mix the documents of different runs
+    pub fn check(&self, dataset: &str, index: &str) -> anyhow::Result<()> {
+        if self.dataset != dataset || self.index != index {
+            return Err(anyhow!(
+                "The checkpoint is for dataset {} in index {}, not dataset {} in index {}. Load the data from the \
+                start without --resume, or resume with --dataset {} --index {}.",
+                self.dataset, self.index, dataset, index, self.dataset, self.index
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_source(&self, source_url: &str) -> anyhow::Result<()> {
+        if self.source_url != source_url {
+            return Err(anyhow!(
+                "The checkpoint is for source data {}, not {}. Load the data from the start without --resume.",
+                self.source_url, source_url
+            ));
+        }
+        Ok(())
+    }
+
+    // Remove from `places` those that were stored with the same contents, and return how many there were
+    pub fn skip_stored(&self, places: &mut Vec<IndexedPlace>) -> usize {
+        let count = places.len();
+        places.retain(|place| self.stored.get(&place.record_id) != Some(&place.content_hash));
+        count - places.len()
+    }
+
+    // Written to a temporary file first, so that an interrupted write doesn't lose the previous checkpoint
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+// Once the run succeeded. It's fine if it was never saved.
+pub fn remove(path: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Cannot remove checkpoint {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Records the places that `sink` stored in a checkpoint, saved to `path` after each batch
+pub struct CheckpointSink<'a> {
+    sink: &'a dyn Sink,
+    path: &'a Path,
+    // None once saving it failed, e.g. in a read-only directory: the run goes on without it
+    checkpoint: Mutex<Option<Checkpoint>>,
+}
+
+impl<'a> CheckpointSink<'a> {
+    pub fn new(sink: &'a dyn Sink, path: &'a Path, checkpoint: Checkpoint) -> CheckpointSink<'a> {
+        CheckpointSink { sink, path, checkpoint: Mutex::new(Some(checkpoint)) }
+    }
+}
+
+#[async_trait]
+impl Sink for CheckpointSink<'_> {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        self.sink.prepare().await
+    }
+
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        let hashes: Vec<(String, String)> = batch.iter()
+            .map(|place| (place.record_id.clone(), place.content_hash.clone()))
+            .collect();
+        let outcome = self.sink.send(batch).await?;
+
+        let mut checkpoint = self.checkpoint.lock().unwrap();
+        if let Some(saved) = checkpoint.as_mut() {
+            let failed: HashSet<&str> = outcome.failures.iter().map(|failure| failure.record_id.as_str()).collect();
+            saved.stored.extend(hashes.into_iter().filter(|(id, _)| !failed.contains(id.as_str())));
+            saved.updated_at = Utc::now();
+            if let Err(err) = saved.save(self.path) {
+                warn!("Cannot save checkpoint {}, the run can't be resumed: {}", self.path.display(), err);
+                *checkpoint = None;
+            }
+        }
+        Ok(outcome)
+    }
+
+    fn operation_len(&self, place: &IndexedPlace) -> usize {
+        self.sink.operation_len(place)
+    }
+
+    async fn finish(&self) -> anyhow::Result<()> {
+        self.sink.finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        let mut checkpoint = Checkpoint::new(
+            "test-run", "collecte-des-sapins-de-noel", "xmas-tree-recycling", "xmas-tree-recycling-20240108",
+            "file:places.json",
+        );
+        checkpoint.stored.insert("a1".to_string(), "f70444c0".to_string());
+        checkpoint.stored.insert("b2".to_string(), "2a2d8bd3".to_string());
+        checkpoint
+    }
+
+    #[test]
+    fn checkpoints_are_loaded_as_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+
+        let saved = checkpoint();
+        saved.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&saved).unwrap());
+        // Without the temporary file
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, [FILE]);
+
+        remove(&path).unwrap();
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+        remove(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_checkpoints_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        std::fs::write(&path, r#"{"run_id": "test-run"}"#).unwrap();
+        let message = format!("{:#}", Checkpoint::load(&path).unwrap_err());
+        assert!(message.starts_with(&format!("Invalid checkpoint {}", path.display())), "{}", message);
+    }
+
+    #[test]
+    fn checkpoints_are_only_resumed_with_the_same_data_and_index() {
+        let checkpoint = checkpoint();
+        checkpoint.check("collecte-des-sapins-de-noel", "xmas-tree-recycling").unwrap();
+        checkpoint.check_source("file:places.json").unwrap();
+
+        let message = checkpoint.check("collecte-des-sapins-de-noel", "sapins").unwrap_err().to_string();
+        assert!(message.starts_with(
+            "The checkpoint is for dataset collecte-des-sapins-de-noel in index xmas-tree-recycling, not dataset \
+            collecte-des-sapins-de-noel in index sapins."
+        ), "{}", message);
+        assert!(checkpoint.check("sapins", "xmas-tree-recycling").is_err());
+        let message = checkpoint.check_source("file:partial.json").unwrap_err().to_string();
+        assert!(message.starts_with("The checkpoint is for source data file:places.json, not file:partial.json."));
+    }
+}
@@ -0,0 +1,507 @@
+use anyhow::{anyhow, Context};
+use elasticsearch::auth::Credentials;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::transport::{
+    CloudConnectionPool, Connection, ConnectionPool, SingleNodeConnectionPool, TransportBuilder,
+};
+use elasticsearch::Elasticsearch;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::Deserialize;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+// The kind of server we talk to. OpenSearch accepts the same requests as Elasticsearch 7.10 for everything
+// that we use, but has no Elastic Cloud ids or Elasticsearch API keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    #[default]
+    Elasticsearch,
+    OpenSearch,
+}
+
+impl std::str::FromStr for Engine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Engine> {
+        match s.to_lowercase().as_str() {
+            "elasticsearch" => Ok(Engine::Elasticsearch),
+            "opensearch" => Ok(Engine::OpenSearch),
+            _ => Err(anyhow!("expecting 'elasticsearch' or 'opensearch'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Engine::Elasticsearch => "Elasticsearch",
+            Engine::OpenSearch => "OpenSearch",
+        })
+    }
+}
+
+const NODE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const NODE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Sends requests to each node in turn, skipping the nodes that don't accept connections. Nodes are checked
+// when the pool is created and then every few seconds in a background thread, so a request sent just as a
+// node goes down still fails with a connection error, which is retried on the next node for requests that
+// are retried.
+#[derive(Debug, Clone)]
+pub struct RoundRobinConnectionPool {
+    urls: Vec<Url>,
+    connections: Vec<Connection>,
+    // Shared by the clones of the pool made by the transport
+    next: Arc<AtomicUsize>,
+    alive: Arc<Vec<AtomicBool>>,
+}
+
+impl RoundRobinConnectionPool {
+    pub fn new(urls: Vec<Url>) -> RoundRobinConnectionPool {
+        let pool = RoundRobinConnectionPool {
+            connections: urls.iter().cloned().map(Connection::new).collect(),
+            next: Arc::new(AtomicUsize::new(0)),
+            alive: Arc::new(urls.iter().map(|_| AtomicBool::new(true)).collect()),
+            urls,
+        };
+        pool.check_nodes();
+
+        let monitor = pool.clone();
+        std::thread::spawn(move || {
+            // Stop once the client is dropped, which leaves only this pool
+            while Arc::strong_count(&monitor.alive) > 1 {
+                std::thread::sleep(NODE_CHECK_INTERVAL);
+                monitor.check_nodes();
+            }
+        });
+        pool
+    }
+
+    // Check which nodes accept connections, logging the nodes that go down or come back
+    fn check_nodes(&self) {
+        let results: Vec<bool> = std::thread::scope(|scope| {
+            let checks: Vec<_> = self.urls.iter()
+                .map(|url| scope.spawn(move || is_reachable(url)))
+                .collect();
+            checks.into_iter().map(|check| check.join().unwrap_or(false)).collect()
+        });
+
+        for ((url, alive), is_alive) in self.urls.iter().zip(self.alive.iter()).zip(results) {
+            if alive.swap(is_alive, Ordering::Relaxed) != is_alive {
+                if is_alive {
+                    info!("Node {} is back", url);
+                } else {
+                    warn!("Node {} doesn't accept connections, sending requests to other nodes", url);
+                }
+            }
+        }
+    }
+}
+
+fn is_reachable(url: &Url) -> bool {
+    url.socket_addrs(|| Some(9200)).unwrap_or_default().iter()
+        .any(|address| TcpStream::connect_timeout(address, NODE_CHECK_TIMEOUT).is_ok())
+}
+
+impl ConnectionPool for RoundRobinConnectionPool {
+    fn next(&self) -> &Connection {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.connections.len();
+        // If all nodes are down, keep going round so that the requests fail and are retried
+        let i = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|i| self.alive[*i].load(Ordering::Relaxed))
+            .unwrap_or(start % count);
+        &self.connections[i]
+    }
+}
+
+// How to connect to Elasticsearch: either the URLs of one or more nodes (possibly with a login and password)
+// or an Elastic Cloud id
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub urls: Vec<String>,
+    pub cloud_id: Option<String>,
+    pub api_key: Option<String>,
+    // Takes precedence over a login in the URLs
+    pub login: Option<Login>,
+    // Maximum duration of requests
+    pub timeout: Option<Duration>,
+    pub engine: Engine,
+    // Proxy URL, possibly with a login and password. Proxies of the environment are used if it's not set.
+    pub proxy: Option<String>,
+    pub certificates: CertificateCheck,
+}
+
+// A login and password, that aren't part of the URLs. The password doesn't show up in debug output.
+#[derive(Clone, PartialEq)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Login").field("username", &self.username).field("password", &"<redacted>").finish()
+    }
+}
+
+// How the TLS certificates of servers are checked
+#[derive(Debug, Clone, Default)]
+pub enum CertificateCheck {
+    // Signed by a CA trusted by the operating system
+    #[default]
+    Default,
+    // Also trust the CA certificates of this PEM file, for clusters with a private CA
+    CaCert(Vec<u8>),
+    // Accept any certificate, only for tests
+    Insecure,
+}
+
+impl CertificateCheck {
+    pub fn load_ca_cert(path: &Path) -> anyhow::Result<CertificateCheck> {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Cannot read the CA certificate {}", path.display()))?;
+        // Fail now rather than on the first request
+        reqwest::Certificate::from_pem(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        Ok(CertificateCheck::CaCert(pem))
+    }
+
+    pub fn configure(&self, builder: reqwest::ClientBuilder) -> anyhow::Result<reqwest::ClientBuilder> {
+        Ok(match self {
+            CertificateCheck::Default => builder,
+            CertificateCheck::CaCert(pem) => builder.add_root_certificate(reqwest::Certificate::from_pem(pem)?),
+            CertificateCheck::Insecure => builder.danger_accept_invalid_certs(true),
+        })
+    }
+}
+
+// Compressing bulk requests is worth it for remote clusters, but only costs CPU time on a local one.
+// Clusters behind https URLs or a Cloud id are assumed to be remote.
+pub fn compress_by_default(urls: &[String], cloud_id: Option<&str>) -> bool {
+    if cloud_id.is_some() {
+        return true;
+    }
+    let is_remote = |url: &String| match Url::parse(url) {
+        Ok(url) => url.scheme() == "https" && !matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+        Err(_) => false,
+    };
+    !urls.is_empty() && urls.iter().all(is_remote)
+}
+
+// Split the login and password out of `url`, so that they don't show up in error messages
+pub fn split_credentials(url: &str) -> anyhow::Result<(Url, Option<(String, String)>)> {
+    let mut url = Url::parse(url)?;
+    if url.username().is_empty() {
+        return Ok((url, None));
+    }
+    let username = percent_decode_str(url.username()).decode_utf8()?.to_string();
+    let password = percent_decode_str(url.password().unwrap_or_default()).decode_utf8()?.to_string();
+    // Can't fail, since the URL already has a username
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    Ok((url, Some((username, password))))
+}
+
+// The proxy used for requests to `url`: `proxy` if set, otherwise the one of the HTTP_PROXY, HTTPS_PROXY
+// or ALL_PROXY environment variables unless the host is listed in NO_PROXY, as the http client does
+pub fn proxy_for(url: &Url, proxy: Option<&str>) -> Option<String> {
+    if let Some(proxy) = proxy {
+        return Some(proxy.to_string());
+    }
+
+    let env = |name: &str| std::env::var(name).or_else(|_| std::env::var(name.to_lowercase())).ok();
+    let no_proxy = env("NO_PROXY").unwrap_or_default();
+    let host = url.host_str().unwrap_or_default();
+    let excluded = no_proxy.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+        let domain = entry.trim_start_matches('.');
+        entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+    });
+    if excluded {
+        return None;
+    }
+
+    let scheme_proxy = if url.scheme() == "https" { env("HTTPS_PROXY") } else { env("HTTP_PROXY") };
+    scheme_proxy.or_else(|| env("ALL_PROXY")).filter(|proxy| !proxy.is_empty())
+}
+
+pub fn create_client(options: &ClientOptions) -> anyhow::Result<Elasticsearch> {
+    let mut credentials = None;
+
+    if options.engine == Engine::OpenSearch {
+        if options.cloud_id.is_some() {
+            return Err(anyhow!("Cloud IDs can only be used with Elasticsearch"));
+        }
+        if options.api_key.is_some() {
+            return Err(anyhow!("API keys can only be used with Elasticsearch, use a login in the URL instead"));
+        }
+    }
+
+    let builder = match (&options.urls[..], &options.cloud_id) {
+        ([_, ..], Some(_)) => {
+            return Err(anyhow!("Both an Elasticsearch URL and a Cloud ID are provided, only one of them can be used"));
+        }
+        ([], None) => {
+            return Err(anyhow!(
+                "No Elasticsearch URL or Cloud ID provided, use --es-url or --cloud-id \
+                (or the ELASTICSEARCH_URL or ELASTICSEARCH_CLOUD_ID environment variables)"
+            ));
+        }
+        ([], Some(cloud_id)) => TransportBuilder::new(CloudConnectionPool::new(cloud_id)?),
+        (urls, None) => {
+            // Move the login and password out of the URLs, so that they don't show up in error messages. All
+            // requests use the same credentials, so URLs with a login must all have the same one, and it is
+            // also used for the URLs without a login.
+            let mut node_urls = Vec::with_capacity(urls.len());
+            for url in urls {
+                let (url, url_credentials) = split_credentials(url)?;
+                match (&credentials, url_credentials) {
+                    (Some(existing), Some(url_credentials)) if *existing != url_credentials => {
+                        return Err(anyhow!("Elasticsearch URLs have different logins, only one can be used"));
+                    }
+                    (_, Some(url_credentials)) => credentials = Some(url_credentials),
+                    _ => {}
+                }
+                node_urls.push(url);
+            }
+            if node_urls.len() == 1 {
+                TransportBuilder::new(SingleNodeConnectionPool::new(node_urls.remove(0)))
+            } else {
+                TransportBuilder::new(RoundRobinConnectionPool::new(node_urls))
+            }
+        }
+    };
+    if let Some(login) = &options.login {
+        if credentials.is_some() {
+            warn!("Both a login in the Elasticsearch URL and a separate login are provided, using the separate one");
+        }
+        credentials = Some((login.username.clone(), login.password.clone()));
+    }
+    let mut credentials = credentials.map(|(username, password)| Credentials::Basic(username, password));
+
+    if let Some(api_key) = &options.api_key {
+        if credentials.is_some() {
+            return Err(anyhow!("Both a login and an API key are provided, only one of them can be used"));
+        }
+        credentials = Some(parse_api_key(api_key)?);
+    }
+
+    let builder = match credentials {
+        Some(credentials) => builder.auth(credentials),
+        None => builder,
+    };
+    let builder = match options.timeout {
+        Some(timeout) => builder.timeout(timeout),
+        None => builder,
+    };
+    let builder = match &options.certificates {
+        CertificateCheck::Default => builder,
+        // Hostnames are still checked
+        CertificateCheck::CaCert(pem) => builder.cert_validation(CertificateValidation::Full(Certificate::from_pem(pem)?)),
+        CertificateCheck::Insecure => builder.cert_validation(CertificateValidation::None),
+    };
+    // Without an explicit proxy, the http client uses the proxies of the environment
+    let builder = match &options.proxy {
+        Some(proxy) => {
+            let (url, credentials) = split_credentials(proxy)?;
+            let (username, password) = credentials.unzip();
+            builder.proxy(url, username.as_deref(), password.as_deref())
+        }
+        None => builder,
+    };
+
+    Ok(Elasticsearch::new(builder.build()?))
+}
+
+// The root endpoint response. OpenSearch adds a `distribution` to the version.
+//   { "name": "node-1", "version": { "distribution": "opensearch", "number": "2.11.0", ... }, ... }
+#[derive(Debug, Deserialize)]
+struct ServerInfo {
+    version: ServerVersion,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerVersion {
+    distribution: Option<String>,
+    number: String,
+}
+
+// A server version such as "8.12.2" or "7.17.18". Pre-release suffixes such as "-SNAPSHOT" are ignored, and
+// missing components are zero: "7.10" is 7.10.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version { major, minor, patch }
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Version> {
+        let invalid = || anyhow!("invalid version '{}', expecting major.minor.patch such as 7.17.18", s);
+        let number = s.split(['-', '+']).next().unwrap_or_default();
+        let components = number.split('.')
+            .map(|component| component.parse::<u32>().map_err(|_| invalid()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        match components[..] {
+            [major] => Ok(Version::new(major, 0, 0)),
+            [major, minor] => Ok(Version::new(major, minor, 0)),
+            [major, minor, patch] => Ok(Version::new(major, minor, patch)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// Oldest Elasticsearch version that we're known to work with
+pub const DEFAULT_MIN_VERSION: &str = "7.10";
+
+// The server we're connected to, to adapt requests to what it supports
+#[derive(Debug, Clone, Copy)]
+pub struct Server {
+    pub engine: Engine,
+    pub version: Version,
+}
+
+impl Server {
+    // `refresh=wait_for` on bulk requests appeared in Elasticsearch 5.0, and OpenSearch has it since the fork
+    pub fn supports_wait_for_refresh(&self) -> bool {
+        self.engine == Engine::OpenSearch || self.version >= Version::new(5, 0, 0)
+    }
+}
+
+// Check that the server is the `expected` engine, so that a wrong --engine is reported before changing
+// anything rather than as obscure errors later on, and that Elasticsearch isn't older than `min_version`.
+// OpenSearch has its own version numbers, starting from 1.0 for a fork of Elasticsearch 7.10.
+#[instrument(name = "engine", skip(es_client, min_version, force))]
+pub async fn check_server(
+    es_client: &Elasticsearch,
+    expected: Engine,
+    min_version: Version,
+    force: bool
+) -> anyhow::Result<Server> {
+    let info = es_client.info()
+        .send().await?
+        .error_for_status_code()?
+        .json::<ServerInfo>().await?;
+
+    let engine = match info.version.distribution.as_deref() {
+        Some("opensearch") => Engine::OpenSearch,
+        _ => Engine::Elasticsearch,
+    };
+    let version: Version = info.version.number.parse()
+        .with_context(|| format!("Cannot understand the version of {}", engine))?;
+    info!("Connected to {} {}", engine, info.version.number);
+
+    if engine != expected {
+        return Err(anyhow!(
+            "The server is {} {}, but {} was expected (use --engine {})",
+            engine, info.version.number, expected, engine.to_string().to_lowercase()
+        ));
+    }
+    if engine == Engine::Elasticsearch && version < min_version {
+        if !force {
+            return Err(anyhow!(
+                "Elasticsearch {} is older than the minimum supported version {} (use --force to run anyway)",
+                info.version.number, min_version
+            ));
+        }
+        warn!(
+            "Elasticsearch {} is older than {}, some features may not work (--force)",
+            info.version.number, min_version
+        );
+    }
+    Ok(Server { engine, version })
+}
+
+// API keys are either "id:key" or its base64 encoding, as returned by the create API key API in `encoded`.
+// Error messages never include the key itself.
+fn parse_api_key(api_key: &str) -> anyhow::Result<Credentials> {
+    let invalid = || anyhow!("Invalid API key, expecting 'id:key' or its base64 encoding");
+
+    let id_key = if api_key.contains(':') {
+        api_key.to_string()
+    } else {
+        let decoded = base64::decode(api_key.trim()).map_err(|_| invalid())?;
+        String::from_utf8(decoded).map_err(|_| invalid())?
+    };
+
+    let (id, key) = id_key.split_once(':').ok_or_else(invalid)?;
+    Ok(Credentials::ApiKey(id.to_string(), key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(urls: &[&str]) -> ClientOptions {
+        ClientOptions { urls: urls.iter().map(|url| url.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn nodes_can_share_a_login() {
+        assert!(create_client(&options(&["http://elastic:pw@node1:9200", "http://node2:9200"])).is_ok());
+        assert!(create_client(&options(&["http://elastic:pw@node1:9200", "http://elastic:pw@node2:9200"])).is_ok());
+    }
+
+    #[test]
+    fn nodes_cannot_have_different_logins() {
+        let err = create_client(&options(&["http://elastic:pw@node1:9200", "http://admin:pw@node2:9200"])).unwrap_err();
+        assert_eq!(err.to_string(), "Elasticsearch URLs have different logins, only one can be used");
+    }
+
+    #[test]
+    fn parses_real_world_versions() {
+        let versions = [
+            ("8.12.2", Version::new(8, 12, 2)),
+            ("7.17.18", Version::new(7, 17, 18)),
+            ("7.10", Version::new(7, 10, 0)),
+            ("6", Version::new(6, 0, 0)),
+            ("8.13.0-SNAPSHOT", Version::new(8, 13, 0)),
+            ("2.11.0+build.1", Version::new(2, 11, 0)),
+        ];
+        for (text, version) in versions {
+            assert_eq!(text.parse::<Version>().unwrap(), version, "{}", text);
+        }
+        for text in ["", "v8.12.2", "8.x", "8.12.2.1", "8..2"] {
+            assert!(text.parse::<Version>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn versions_are_compared_by_component() {
+        let version = |text: &str| text.parse::<Version>().unwrap();
+        assert!(version("7.17.18") < version("8.12.2"));
+        assert!(version("7.9.3") < version("7.10"));
+        assert!(version("7.10.2") > version(DEFAULT_MIN_VERSION));
+        assert!(version("7.10.0") >= version(DEFAULT_MIN_VERSION));
+        assert!(version("6.8.23") < version(DEFAULT_MIN_VERSION));
+        assert_eq!(version("8.12.2").to_string(), "8.12.2");
+    }
+
+    #[test]
+    fn wait_for_refresh_depends_on_the_version() {
+        let server = |engine, version: &str| Server { engine, version: version.parse().unwrap() };
+        assert!(server(Engine::Elasticsearch, "7.17.18").supports_wait_for_refresh());
+        assert!(!server(Engine::Elasticsearch, "2.4.6").supports_wait_for_refresh());
+        assert!(server(Engine::OpenSearch, "1.0.0").supports_wait_for_refresh());
+    }
+}
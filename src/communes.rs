@@ -0,0 +1,73 @@
+// The communes of Toulouse Métropole, to attach their codes to places so that they can be joined with other
+// datasets
+use crate::text::fold_accents;
+
+#[derive(Debug)]
+pub struct Commune {
+    pub name: &'static str,
+    // Code of the commune in the Code officiel géographique
+    pub insee_code: &'static str,
+    // Toulouse has several
+    pub postal_codes: &'static [&'static str],
+}
+
+const fn commune(name: &'static str, insee_code: &'static str, postal_codes: &'static [&'static str]) -> Commune {
+    Commune { name, insee_code, postal_codes }
+}
+
+pub const COMMUNES: [Commune; 37] = [
+    commune("Aigrefeuille", "31003", &["31280"]),
+    commune("Aucamville", "31022", &["31140"]),
+    commune("Aussonne", "31032", &["31840"]),
+    commune("Balma", "31044", &["31130"]),
+    commune("Beaupuy", "31053", &["31850"]),
+    commune("Beauzelle", "31056", &["31700"]),
+    commune("Blagnac", "31069", &["31700"]),
+    commune("Brax", "31088", &["31490"]),
+    commune("Bruguières", "31091", &["31150"]),
+    commune("Castelginest", "31116", &["31780"]),
+    commune("Colomiers", "31149", &["31770"]),
+    commune("Cornebarrieu", "31150", &["31700"]),
+    commune("Cugnaux", "31157", &["31270"]),
+    commune("Drémil-Lafage", "31163", &["31280"]),
+    commune("Fenouillet", "31182", &["31150"]),
+    commune("Flourens", "31184", &["31130"]),
+    commune("Fonbeauzard", "31186", &["31140"]),
+    commune("Gagnac-sur-Garonne", "31205", &["31150"]),
+    commune("Gratentour", "31230", &["31150"]),
+    commune("Launaguet", "31282", &["31140"]),
+    commune("Lespinasse", "31293", &["31150"]),
+    commune("L'Union", "31561", &["31240"]),
+    commune("Mondonville", "31351", &["31700"]),
+    commune("Mondouzil", "31352", &["31850"]),
+    commune("Mons", "31355", &["31280"]),
+    commune("Montrabé", "31389", &["31850"]),
+    commune("Pibrac", "31417", &["31820"]),
+    commune("Pin-Balma", "31418", &["31130"]),
+    commune("Quint-Fonsegrives", "31445", &["31130"]),
+    commune("Saint-Alban", "31467", &["31140"]),
+    commune("Saint-Jean", "31488", &["31240"]),
+    commune("Saint-Jory", "31490", &["31790"]),
+    commune("Saint-Orens-de-Gameville", "31506", &["31650"]),
+    commune("Seilh", "31541", &["31840"]),
+    commune("Toulouse", "31555", &["31000", "31100", "31200", "31300", "31400", "31500"]),
+    commune("Tournefeuille", "31557", &["31170"]),
+    commune("Villeneuve-Tolosane", "31588", &["31270"]),
+];
+
+// "Saint-Orens-de-Gameville", "SAINT ORENS DE GAMEVILLE" and "St Orens de Gameville" all become
+// "saint orens de gameville"
+fn normalize(city: &str) -> String {
+    fold_accents(city)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| if word == "st" { "saint" } else { word })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The commune named `city`, ignoring case, accents and punctuation
+pub fn find(city: &str) -> Option<&'static Commune> {
+    let city = normalize(city);
+    COMMUNES.iter().find(|commune| normalize(commune.name) == city)
+}
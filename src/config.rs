@@ -0,0 +1,81 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Settings of a TOML configuration file. Keys are the names of the corresponding command line options, with
+// underscores instead of dashes:
+//   es_url = "http://localhost:9200"
+//   index = "xmas-tree-recycling"
+//   bulk_size = 1000
+//   http_timeout = "1m"
+// Options given on the command line take precedence over the file, which takes precedence over environment
+// variables.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    pub log_format: Option<String>,
+    pub log_level: Option<String>,
+    pub es_url: Option<String>,
+    pub cloud_id: Option<String>,
+    pub es_user: Option<String>,
+    pub api_key: Option<String>,
+    pub engine: Option<String>,
+    pub dataset: Option<String>,
+    pub index: Option<String>,
+    pub subscriptions_index: Option<String>,
+    pub data_url: Option<String>,
+    pub metadata_url: Option<String>,
+    pub api: Option<String>,
+    pub keep_indices: Option<u32>,
+    pub backup_dir: Option<PathBuf>,
+    pub no_backup: Option<bool>,
+    pub bulk_size: Option<u32>,
+    pub bulk_bytes: Option<String>,
+    pub concurrency: Option<u32>,
+    pub dataset_concurrency: Option<u32>,
+    pub http_timeout: Option<String>,
+    pub data_header: Option<Vec<String>>,
+    pub opendata_api_key: Option<String>,
+    pub proxy: Option<String>,
+    pub ca_cert: Option<PathBuf>,
+    pub insecure: Option<bool>,
+    pub es_timeout: Option<String>,
+    pub compress: Option<bool>,
+    pub health_timeout: Option<String>,
+    pub skip_health_check: Option<bool>,
+    pub max_attempts: Option<u32>,
+    pub max_rate_limit_wait: Option<String>,
+    pub bbox: Option<String>,
+    pub no_bbox_check: Option<bool>,
+    pub cache_dir: Option<PathBuf>,
+    pub mapping: Option<PathBuf>,
+    pub shards: Option<u32>,
+    pub replicas: Option<u32>,
+    pub strict_schema: Option<bool>,
+    pub no_normalize_city: Option<bool>,
+    pub geohash_precision: Option<u32>,
+    pub center: Option<String>,
+    pub sectors_file: Option<PathBuf>,
+    pub geo_format: Option<String>,
+    pub sort: Option<String>,
+    pub commune: Option<Vec<String>>,
+    pub exclude_commune: Option<Vec<String>>,
+    pub dedup: Option<String>,
+    pub dedup_radius: Option<f64>,
+
+    // Keys that we don't know about, most likely typos
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid configuration file {}", path.display()))
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
@@ -0,0 +1,350 @@
+// Coverage areas of the collection places: the Voronoi cell of each place, i.e. the area that is closer to it
+// than to any other place, clipped to a boundary polygon such as the métropole area. Indexed as a geo_shape, it
+// lets an intersects query find the place that serves a location.
+//
+// Cells are computed on an equirectangular projection around the places' mean latitude, which is accurate
+// enough at the scale of a city. Each cell starts as the boundary, and is clipped by the bisectors of the place
+// and its neighbors, from the closest ones until the others are too far to change it.
+use crate::transform::{BoundingBox, IndexedPlace};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use tracing::{instrument, warn};
+
+// A GeoJSON polygon without holes, in (lon, lat) degrees. The ring is closed and counterclockwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "Polygon")]
+pub struct Polygon {
+    pub coordinates: Vec<Vec<(f64, f64)>>,
+}
+
+// Cells whose area is below this, in squared degrees (about 1 m²), are degenerate and dropped
+const MIN_AREA: f64 = 1e-10;
+
+type Point = (f64, f64);
+
+// The rectangle of `bbox`, as a boundary
+pub fn bbox_boundary(bbox: &BoundingBox) -> Vec<(f64, f64)> {
+    vec![
+        (bbox.min_lon, bbox.min_lat),
+        (bbox.max_lon, bbox.min_lat),
+        (bbox.max_lon, bbox.max_lat),
+        (bbox.min_lon, bbox.max_lat),
+    ]
+}
+
+// The outer ring of the polygon in a GeoJSON file: a Polygon geometry, or a Feature or the first feature of a
+// FeatureCollection with such a geometry
+pub fn read_boundary(path: &Path) -> anyhow::Result<Vec<(f64, f64)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read boundary file {}", path.display()))?;
+    let geojson: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid GeoJSON in boundary file {}", path.display()))?;
+
+    let geometry = match geojson["type"].as_str() {
+        Some("FeatureCollection") => &geojson["features"][0]["geometry"],
+        Some("Feature") => &geojson["geometry"],
+        _ => &geojson,
+    };
+    let polygon = Polygon::deserialize(geometry)
+        .with_context(|| format!("Boundary file {} must contain a Polygon", path.display()))?;
+    let mut ring = polygon.coordinates.into_iter().next().unwrap_or_default();
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    if ring.len() < 3 {
+        return Err(anyhow!("The polygon of boundary file {} has less than 3 points", path.display()));
+    }
+    Ok(ring)
+}
+
+// Set the coverage of all places, and update their content hash. Places whose cell is empty, which happens if
+// they're outside of the boundary, have no coverage.
+#[instrument(name = "coverage", skip_all, fields(places = places.len()))]
+pub fn add_coverage(places: &mut [IndexedPlace], boundary: &[(f64, f64)]) {
+    let locations: Vec<Point> = places.iter().map(|place| (place.location.lon, place.location.lat)).collect();
+    let cells = voronoi_cells(&locations, boundary);
+
+    let mut without_cell = 0;
+    for (place, cell) in places.iter_mut().zip(cells) {
+        if cell.is_none() {
+            without_cell += 1;
+        }
+        place.coverage = cell;
+        place.content_hash = place.compute_content_hash();
+    }
+    if without_cell > 0 {
+        warn!("{} places are outside of the coverage boundary and have no coverage", without_cell);
+    }
+}
+
+// The Voronoi cell of each of `points`, clipped to `boundary`, or None if it's empty. Points at the same
+// location get the same cell.
+pub fn voronoi_cells(points: &[(f64, f64)], boundary: &[(f64, f64)]) -> Vec<Option<Polygon>> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+
+    // Scale longitudes so that distances are the same in all directions
+    let mean_lat = points.iter().map(|(_, lat)| lat).sum::<f64>() / points.len() as f64;
+    let scale = mean_lat.to_radians().cos().max(f64::EPSILON);
+    let project = |(lon, lat): Point| (lon * scale, lat);
+    let unproject = |(x, y): Point| (x / scale, y);
+
+    let projected: Vec<Point> = points.iter().copied().map(project).collect();
+    let boundary: Vec<Point> = boundary.iter().copied().map(project).collect();
+    let grid = Grid::new(&projected);
+
+    (0..projected.len())
+        .map(|index| {
+            let cell = clipped_cell(index, &projected, &grid, &boundary);
+            to_polygon(cell.into_iter().map(unproject).collect())
+        })
+        .collect()
+}
+
+// Points bucketed in squares of a grid, about one point per square, so that the neighbors of a point can be
+// visited from the closest squares to the farthest ones
+struct Grid {
+    origin: Point,
+    size: f64,
+    columns: usize,
+    rows: usize,
+    squares: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    fn new(points: &[Point]) -> Grid {
+        let (mut min, mut max) = (points[0], points[0]);
+        for &(x, y) in points {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        // Not zero if all the points are at the same location
+        let size = ((max.0 - min.0).max(max.1 - min.1) / (points.len() as f64).sqrt()).max(1e-9);
+        let columns = ((max.0 - min.0) / size) as usize + 1;
+        let rows = ((max.1 - min.1) / size) as usize + 1;
+
+        let mut grid = Grid { origin: min, size, columns, rows, squares: vec![Vec::new(); columns * rows] };
+        for (index, &point) in points.iter().enumerate() {
+            let (column, row) = grid.square_of(point);
+            grid.squares[row * columns + column].push(index);
+        }
+        grid
+    }
+
+    fn square_of(&self, (x, y): Point) -> (usize, usize) {
+        let column = ((x - self.origin.0) / self.size) as usize;
+        let row = ((y - self.origin.1) / self.size) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    // Points of the squares at `ring` squares from (`column`, `row`) horizontally or vertically
+    fn ring(&self, (column, row): (usize, usize), ring: usize) -> impl Iterator<Item = usize> + '_ {
+        let (column, row, ring) = (column as isize, row as isize, ring as isize);
+        let horizontal = (column - ring..=column + ring)
+            .flat_map(move |x| std::iter::once((x, row - ring)).chain((ring > 0).then_some((x, row + ring))));
+        let vertical = (row - ring + 1..row + ring)
+            .flat_map(move |y| [(column - ring, y), (column + ring, y)]);
+        horizontal.chain(vertical)
+            .filter(move |&(x, y)| x >= 0 && y >= 0 && (x as usize) < self.columns && (y as usize) < self.rows)
+            .flat_map(move |(x, y)| self.squares[y as usize * self.columns + x as usize].iter().copied())
+    }
+}
+
+// The part of `boundary` that is closer to the point at `index` than to any other point. Points farther than
+// twice the farthest vertex of the cell can't clip it, and those of a ring of squares are at least
+// `(ring - 1) * size` away.
+fn clipped_cell(index: usize, points: &[Point], grid: &Grid, boundary: &[Point]) -> Vec<Point> {
+    let point = points[index];
+    let square = grid.square_of(point);
+    let mut cell = boundary.to_vec();
+    let mut reach = max_distance(point, &cell) * 2.0;
+
+    for ring in 0..=grid.columns.max(grid.rows) {
+        if ring > 0 && (ring - 1) as f64 * grid.size > reach {
+            break;
+        }
+        for neighbor in grid.ring(square, ring) {
+            let other = points[neighbor];
+            if other == point {
+                continue;
+            }
+            cell = clip(&cell, point, other);
+            if cell.is_empty() {
+                return cell;
+            }
+            reach = max_distance(point, &cell) * 2.0;
+        }
+    }
+    cell
+}
+
+fn max_distance(point: Point, polygon: &[Point]) -> f64 {
+    polygon.iter()
+        .map(|vertex| (vertex.0 - point.0).hypot(vertex.1 - point.1))
+        .fold(0.0, f64::max)
+}
+
+// Keep the part of `polygon` that is on the side of `point` of the bisector of `point` and `other`
+// (Sutherland-Hodgman clipping with a single edge)
+fn clip(polygon: &[Point], point: Point, other: Point) -> Vec<Point> {
+    let middle = ((point.0 + other.0) / 2.0, (point.1 + other.1) / 2.0);
+    let direction = (other.0 - point.0, other.1 - point.1);
+    // Negative on the side of `point`
+    let side = |vertex: Point| (vertex.0 - middle.0) * direction.0 + (vertex.1 - middle.1) * direction.1;
+
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (index, &current) in polygon.iter().enumerate() {
+        let previous = polygon[(index + polygon.len() - 1) % polygon.len()];
+        let (current_side, previous_side) = (side(current), side(previous));
+        // Sides have different signs, so they can't be equal
+        let crossing = || {
+            let t = previous_side / (previous_side - current_side);
+            (previous.0 + t * (current.0 - previous.0), previous.1 + t * (current.1 - previous.1))
+        };
+        if current_side <= 0.0 {
+            if previous_side > 0.0 {
+                clipped.push(crossing());
+            }
+            clipped.push(current);
+        } else if previous_side <= 0.0 {
+            clipped.push(crossing());
+        }
+    }
+    if clipped.len() < 3 { Vec::new() } else { clipped }
+}
+
+// A closed counterclockwise ring, without repeated vertices, or None if the polygon is degenerate
+fn to_polygon(mut ring: Vec<Point>) -> Option<Polygon> {
+    ring.dedup();
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    let area = signed_area(&ring);
+    if ring.len() < 3 || area.abs() < MIN_AREA {
+        return None;
+    }
+    if area < 0.0 {
+        ring.reverse();
+    }
+    ring.push(ring[0]);
+    Some(Polygon { coordinates: vec![ring] })
+}
+
+// Positive for counterclockwise rings (shoelace formula)
+fn signed_area(ring: &[Point]) -> f64 {
+    let sum: f64 = ring.iter().zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum();
+    sum / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo;
+
+    // A square of about 8 km around the center of Toulouse
+    const BOUNDARY: [(f64, f64); 4] = [(1.40, 43.57), (1.50, 43.57), (1.50, 43.64), (1.40, 43.64)];
+
+    fn area(cell: &Option<Polygon>) -> f64 {
+        cell.as_ref().map(|cell| signed_area(&cell.coordinates[0])).unwrap_or_default()
+    }
+
+    // Cells don't overlap and cover the boundary, and each point is in its own cell
+    fn assert_tessellation(points: &[(f64, f64)], cells: &[Option<Polygon>]) {
+        assert_eq!(cells.len(), points.len());
+        let mut distinct: Vec<&Polygon> = Vec::new();
+        for cell in cells.iter().flatten() {
+            if !distinct.contains(&cell) {
+                distinct.push(cell);
+            }
+        }
+        let total: f64 = distinct.iter().map(|cell| signed_area(&cell.coordinates[0])).sum();
+        assert!((total - signed_area(&BOUNDARY)).abs() < 1e-9, "{} {}", total, signed_area(&BOUNDARY));
+
+        for (&(lon, lat), cell) in points.iter().zip(cells) {
+            let cell = cell.as_ref().unwrap();
+            assert_eq!(cell.coordinates[0].first(), cell.coordinates[0].last());
+            assert!(signed_area(&cell.coordinates[0]) > 0.0);
+            assert!(geo::polygon_contains(&cell.coordinates, lon, lat), "{:?} {:?}", (lon, lat), cell);
+        }
+    }
+
+    #[test]
+    fn cells_contain_the_locations_closest_to_their_point() {
+        // A deterministic but irregular set of points
+        let points: Vec<(f64, f64)> = (0..50)
+            .map(|n| (1.40 + (n * 37 % 101) as f64 / 1010.0, 43.57 + (n * 53 % 97) as f64 / 1386.0))
+            .collect();
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert_tessellation(&points, &cells);
+
+        // Locations are in the cell of the closest point, on the projection where cells are computed
+        let scale = 43.6_f64.to_radians().cos();
+        let distance = |(lon1, lat1): Point, (lon2, lat2): Point| ((lon1 - lon2) * scale).hypot(lat1 - lat2);
+        for n in 0..400 {
+            let location = (1.40 + (n % 20) as f64 / 200.0 + 0.001, 43.57 + (n / 20) as f64 / 286.0 + 0.001);
+            let (closest, _) = points.iter().enumerate()
+                .map(|(index, &point)| (index, distance(point, location)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            let cell = cells[closest].as_ref().unwrap();
+            assert!(geo::polygon_contains(&cell.coordinates, location.0, location.1), "{:?}", location);
+        }
+    }
+
+    #[test]
+    fn four_points_split_the_boundary_in_quarters() {
+        let points = [(1.425, 43.5875), (1.475, 43.5875), (1.475, 43.6225), (1.425, 43.6225)];
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert_tessellation(&points, &cells);
+        for cell in &cells {
+            assert!((area(cell) - signed_area(&BOUNDARY) / 4.0).abs() < 1e-9);
+        }
+        assert!(geo::polygon_contains(&cells[0].as_ref().unwrap().coordinates, 1.40, 43.57));
+        assert!(geo::polygon_contains(&cells[2].as_ref().unwrap().coordinates, 1.50, 43.64));
+    }
+
+    #[test]
+    fn points_at_the_same_location_have_the_same_cell() {
+        let points = [(1.42, 43.60), (1.48, 43.60), (1.42, 43.60), (1.42, 43.60)];
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert_tessellation(&points, &cells);
+        assert_eq!(cells[0], cells[2]);
+        assert_eq!(cells[0], cells[3]);
+        assert!((area(&cells[0]) - signed_area(&BOUNDARY) / 2.0).abs() < 1e-9);
+
+        // A single location gets the whole boundary
+        let points = [(1.45, 43.60); 3];
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert_tessellation(&points, &cells);
+        assert!((area(&cells[0]) - signed_area(&BOUNDARY)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn collinear_points_have_strips() {
+        let points: Vec<(f64, f64)> = (0..10).map(|n| (1.405 + n as f64 / 100.0, 43.60)).collect();
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert_tessellation(&points, &cells);
+        for cell in &cells {
+            assert!((area(cell) - signed_area(&BOUNDARY) / 10.0).abs() < 1e-9);
+        }
+
+        // Also along a diagonal
+        let points: Vec<(f64, f64)> = (0..10).map(|n| (1.405 + n as f64 / 100.0, 43.575 + n as f64 / 150.0)).collect();
+        assert_tessellation(&points, &voronoi_cells(&points, &BOUNDARY));
+    }
+
+    #[test]
+    fn points_outside_of_the_boundary_have_no_cell() {
+        let points = [(1.45, 43.60), (2.0, 43.60), (2.0, 43.61)];
+        let cells = voronoi_cells(&points, &BOUNDARY);
+        assert!((area(&cells[0]) - signed_area(&BOUNDARY)).abs() < 1e-12);
+        assert_eq!(cells[1..], [None, None]);
+
+        assert_eq!(voronoi_cells(&[], &BOUNDARY), Vec::new());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::geo::GeoPoint;
+use crate::transform::IndexedPlace;
+use std::io::Write;
+
+// Columns, with the latitude first as usually written by humans
+//   record_id,city,street,lat,lon
+//   ef89fdb5cbb3b397d2988b7d23c1fee5199b989c,Toulouse,"88 allée Jean Jaurès, angle rue Riquet",43.6089310498,1.45385907091
+pub const HEADER: [&str; 5] = ["record_id", "city", "street", "lat", "lon"];
+
+// Excel only reads a CSV file as UTF-8 if it starts with a byte order mark
+const BOM: &str = "\u{feff}";
+
+// Write the places as RFC 4180 CSV, with a header line and CRLF line endings
+pub fn write_csv(places: &[IndexedPlace], out: &mut impl Write, bom: bool) -> anyhow::Result<()> {
+    if bom {
+        out.write_all(BOM.as_bytes())?;
+    }
+    write_record(out, &HEADER)?;
+    for place in places {
+        let GeoPoint { lat, lon } = place.location;
+        let (lat, lon) = (lat.to_string(), lon.to_string());
+        write_record(out, &[&place.record_id, &place.city, &place.street, &lat, &lon])?;
+    }
+    Ok(())
+}
+
+fn write_record(out: &mut impl Write, fields: &[&str]) -> anyhow::Result<()> {
+    let fields: Vec<_> = fields.iter().map(|field| quote(field)).collect();
+    write!(out, "{}\r\n", fields.join(","))?;
+    Ok(())
+}
+
+// Fields with a separator, a quote or a line break are enclosed in quotes, with quotes doubled
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_with_separators_and_quotes_parse_back() {
+        let records = [
+            ["88 allée Jean Jaurès, angle rue Riquet", "Toulouse"],
+            ["Place du \"Capitole\"", "L'Union"],
+            ["2 av du Parc\r\nBâtiment B", ""],
+            ["", "Saint-Orens-de-Gameville"],
+        ];
+        let mut out = Vec::new();
+        for record in &records {
+            write_record(&mut out, record).unwrap();
+        }
+
+        let mut reader = ::csv::ReaderBuilder::new().has_headers(false).from_reader(&out[..]);
+        let parsed: Vec<Vec<String>> = reader.records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        let mut out = Vec::new();
+        write_record(&mut out, &["Place du Capitole", "Toulouse", "43.6044622"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Place du Capitole,Toulouse,43.6044622\r\n");
+    }
+}
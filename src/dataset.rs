@@ -0,0 +1,96 @@
+use crate::source::{SourceFields, SourcePlace};
+use crate::INDEX_NAME;
+use serde_json::Value as JsonValue;
+
+const PORTAL_URL: &str = "https://data.toulouse-metropole.fr";
+
+// A point dataset of the Toulouse Métropole open data portal
+#[derive(Debug)]
+pub struct Dataset {
+    pub id: &'static str,
+    pub index_name: &'static str,
+    // Open data license, which requires attributing the data to Toulouse Métropole
+    pub license: &'static str,
+    // Fill the commune, address and location from dataset-specific fields, if they have other names. Fields
+    // that are used are removed from `extra`, so that what remains is unknown.
+    pub map_fields: fn(&mut SourceFields),
+}
+
+pub const DEFAULT_DATASET: &str = "collecte-des-sapins-de-noel";
+
+pub const DATASETS: [Dataset; 4] = [
+    Dataset {
+        id: DEFAULT_DATASET,
+        index_name: INDEX_NAME,
+        license: "ODbL",
+        map_fields: |_| {},
+    },
+    Dataset {
+        id: "recup-verre",
+        index_name: "glass-containers",
+        license: "ODbL",
+        map_fields: |fields| fill_from(&mut fields.adresse, &mut fields.extra, &["adresse", "localisation"]),
+    },
+    Dataset {
+        id: "decheteries",
+        index_name: "recycling-centers",
+        license: "ODbL",
+        map_fields: |fields| {
+            fill_from(&mut fields.adresse, &mut fields.extra, &["adresse_complete", "nom"]);
+            fill_from(&mut fields.commune, &mut fields.extra, &["ville"]);
+        },
+    },
+    Dataset {
+        id: "bornes-textiles",
+        index_name: "textile-bins",
+        license: "ODbL",
+        map_fields: |fields| fill_from(&mut fields.adresse, &mut fields.extra, &["adresse", "localisation"]),
+    },
+];
+
+pub fn find_dataset(id: &str) -> Option<&'static Dataset> {
+    DATASETS.iter().find(|dataset| dataset.id == id)
+}
+
+impl Dataset {
+    // Opendatasoft JSON export
+    pub fn download_url(&self) -> String {
+        format!("{}/explore/dataset/{}/download/?format=json", PORTAL_URL, self.id)
+    }
+
+    // The dataset page on the portal, with its description and terms of use
+    pub fn page_url(&self) -> String {
+        format!("{}/explore/dataset/{}/", PORTAL_URL, self.id)
+    }
+
+    // As required by the license
+    pub fn attribution(&self) -> String {
+        format!("Toulouse Métropole, {}", self.page_url())
+    }
+
+    // Dataset API v1 endpoint, with the metadata of the dataset
+    pub fn metadata_url(&self) -> String {
+        format!("{}/api/datasets/1.0/{}/", PORTAL_URL, self.id)
+    }
+
+    // Records API v2.1 endpoint
+    pub fn records_url(&self) -> String {
+        format!("{}/api/explore/v2.1/catalog/datasets/{}/records", PORTAL_URL, self.id)
+    }
+
+    pub fn map_places(&self, places: &mut [SourcePlace]) {
+        for place in places {
+            (self.map_fields)(&mut place.fields);
+        }
+    }
+}
+
+// Set `target` to the first of the `names` fields that is a string, if it's not set already, and remove
+// these fields from `extra`
+fn fill_from(target: &mut Option<String>, extra: &mut serde_json::Map<String, JsonValue>, names: &[&str]) {
+    for name in names {
+        if let Some(JsonValue::String(value)) = extra.remove(*name) {
+            target.get_or_insert(value);
+        }
+    }
+}
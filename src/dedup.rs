@@ -0,0 +1,175 @@
+use crate::geo::{distance, GeoPoint};
+use crate::transform::IndexedPlace;
+use std::collections::HashMap;
+use tracing::{info, instrument, warn};
+
+// How duplicate places are found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupMode {
+    Off,
+    // Same street and city, and same location at about 1 meter precision
+    Exact,
+    // Same as exact, or within `radius` meters of each other whatever their address
+    Fuzzy { radius: f64 },
+}
+
+// Locations are rounded to 5 decimals, about 1.1 meters in latitude
+const PRECISION: f64 = 1e5;
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    street: String,
+    city: String,
+    lon: i64,
+    lat: i64,
+}
+
+fn key(place: &IndexedPlace) -> Key {
+    let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let GeoPoint { lat, lon } = place.location;
+    Key {
+        street: normalize(&place.street),
+        city: normalize(&place.city),
+        lon: (lon * PRECISION).round() as i64,
+        lat: (lat * PRECISION).round() as i64,
+    }
+}
+
+// The duplicates in `places`, as (duplicate, original) indices. The first occurrence is the original.
+pub fn find_duplicates(places: &[IndexedPlace], mode: DedupMode) -> Vec<(usize, usize)> {
+    let mut duplicates = Vec::new();
+    if mode == DedupMode::Off {
+        return duplicates;
+    }
+
+    let mut originals: HashMap<Key, usize> = HashMap::new();
+    let mut kept: Vec<usize> = Vec::new();
+    for (i, place) in places.iter().enumerate() {
+        if let Some(&original) = originals.get(&key(place)) {
+            duplicates.push((i, original));
+            continue;
+        }
+        if let DedupMode::Fuzzy { radius } = mode {
+            let close = kept.iter().find(|&&j| distance(places[j].location, place.location) <= radius);
+            if let Some(&original) = close {
+                duplicates.push((i, original));
+                continue;
+            }
+        }
+        originals.insert(key(place), i);
+        kept.push(i);
+    }
+    duplicates
+}
+
+// A place that was removed, because it's the same as another one
+#[derive(Debug)]
+pub struct Duplicate {
+    pub place: IndexedPlace,
+    // Record id of the place that was kept
+    pub original: String,
+}
+
+// Remove duplicate places, logging the record ids of those that were discarded. Discarded places are also
+// returned.
+#[instrument(name = "dedup", skip(places), fields(records = places.len()))]
+pub fn dedup_places(places: Vec<IndexedPlace>, mode: DedupMode) -> (Vec<IndexedPlace>, Vec<Duplicate>) {
+    let duplicates = find_duplicates(&places, mode);
+    if duplicates.is_empty() {
+        return (places, Vec::new());
+    }
+
+    let mut original_of = vec![None; places.len()];
+    for (duplicate, original) in &duplicates {
+        warn!(
+            record_id = %places[*duplicate].record_id,
+            "Discarding duplicate of record {}", places[*original].record_id
+        );
+        original_of[*duplicate] = Some(places[*original].record_id.clone());
+    }
+    info!("Removed {} duplicates", duplicates.len());
+
+    let mut kept = Vec::with_capacity(places.len() - duplicates.len());
+    let mut removed = Vec::with_capacity(duplicates.len());
+    for (place, original) in places.into_iter().zip(original_of) {
+        match original {
+            Some(original) => removed.push(Duplicate { place, original }),
+            None => kept.push(place),
+        }
+    }
+    (kept, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FUZZY: DedupMode = DedupMode::Fuzzy { radius: 10.0 };
+
+    // A place of Toulouse `east` meters east of the Capitole
+    fn place(record_id: &str, street: &str, east: f64) -> IndexedPlace {
+        let lon = 1.4442469 + east / (111_195.0 * 43.6044622_f64.to_radians().cos());
+        serde_json::from_value(json!({
+            "dataset_id": "collecte-des-sapins-de-noel",
+            "record_id": record_id,
+            "city": "Toulouse",
+            "city_raw": "TOULOUSE",
+            "street": street,
+            "street_raw": street,
+            "location": [lon, 43.6044622],
+            "indexed_at": "2024-01-08T06:00:00Z",
+            "source_url": "file:places.json"
+        })).unwrap()
+    }
+
+    #[test]
+    fn exact_duplicates_have_the_same_address_and_location() {
+        let places = [
+            place("a1", "Place du Capitole", 0.0),
+            place("b2", "place  du Capitole", 0.5),
+            place("c3", "Place du Capitole", 2.0),
+            place("d4", "rue Lafayette", 0.0),
+        ];
+        assert_eq!(find_duplicates(&places, DedupMode::Exact), [(1, 0)]);
+        assert_eq!(find_duplicates(&places, DedupMode::Off), []);
+    }
+
+    #[test]
+    fn fuzzy_duplicates_are_within_the_radius_whatever_their_address() {
+        let places = [
+            place("a1", "Place du Capitole", 0.0),
+            place("b2", "rue Lafayette", 9.9),
+            place("c3", "rue Lafayette", -10.1),
+            place("d4", "Place du Capitole", 0.0),
+        ];
+        assert_eq!(find_duplicates(&places, FUZZY), [(1, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn duplicates_are_of_kept_places() {
+        // c3 is 8 meters from b2, which is a duplicate, and 16 from a1 which is kept
+        let places = [
+            place("a1", "Place du Capitole", 0.0),
+            place("b2", "rue Lafayette", 8.0),
+            place("c3", "rue Lafayette", 16.0),
+        ];
+        assert_eq!(find_duplicates(&places, FUZZY), [(1, 0)]);
+    }
+
+    #[test]
+    fn the_first_place_of_a_group_is_kept() {
+        let places = vec![
+            place("a1", "rue Lafayette", 100.0),
+            place("b2", "Place du Capitole", 0.0),
+            place("c3", "Place du Capitole", 0.0),
+            place("d4", "place du capitole", 5.0),
+        ];
+        let (kept, removed) = dedup_places(places, FUZZY);
+        let kept: Vec<&str> = kept.iter().map(|place| place.record_id.as_str()).collect();
+        let removed: Vec<(&str, &str)> = removed.iter()
+            .map(|duplicate| (duplicate.place.record_id.as_str(), duplicate.original.as_str()))
+            .collect();
+        assert_eq!((kept, removed), (vec!["a1", "b2"], vec![("c3", "b2"), ("d4", "b2")]));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::geo::{distance, GeoPoint};
+use crate::text::fold_accents;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+
+// The fields needed to compare campaigns, as found in indexed documents and NDJSON exports
+pub const FIELDS: [&str; 4] = ["record_id", "street", "city", "location"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Place {
+    pub record_id: String,
+    pub street: String,
+    pub city: String,
+    pub location: GeoPoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Moved,
+    // Same location, but a different address
+    Renamed,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Moved => "moved",
+            Status::Renamed => "renamed",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub status: Status,
+    // The place in the first campaign, missing for added places
+    pub from: Option<Place>,
+    // The place in the second campaign, missing for removed places
+    pub to: Option<Place>,
+    // Distance in meters between both locations, for moved and renamed places
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+}
+
+impl Change {
+    // The most recent version of the place
+    pub fn place(&self) -> &Place {
+        self.to.as_ref().or(self.from.as_ref()).expect("a change has at least one place")
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+    pub unchanged: usize,
+}
+
+impl Diff {
+    pub fn count(&self, status: Status) -> usize {
+        self.changes.iter().filter(|change| change.status == status).count()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} moved, {} renamed, {} unchanged",
+            self.count(Status::Added), self.count(Status::Removed), self.count(Status::Moved),
+            self.count(Status::Renamed), self.unchanged
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    // Places with the same address are reported as moved if they're farther apart than this, in meters
+    pub move_distance: f64,
+    // Places with different addresses are considered the same if they're closer than this, in meters
+    pub match_radius: f64,
+}
+
+// Read places from an NDJSON export, such as the output of --dry-run
+pub fn read_ndjson(input: impl BufRead) -> anyhow::Result<Vec<Place>> {
+    let mut places = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let place = serde_json::from_str(&line).map_err(|err| anyhow::anyhow!("line {}: {}", i + 1, err))?;
+        places.push(place);
+    }
+    Ok(places)
+}
+
+// Record ids are regenerated every year, so places are matched on their address, ignoring case, accents and
+// punctuation: "88 Allée Jean-Jaurès" and "88 allee jean jaures" are the same address
+fn address_key(place: &Place) -> (String, String) {
+    let normalize = |text: &str| {
+        fold_accents(text)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    (normalize(&place.street), normalize(&place.city))
+}
+
+// Pair `from` and `to` places, closest first, ignoring pairs farther apart than `max_distance`
+fn pair_closest(
+    from: &[usize],
+    to: &[usize],
+    from_places: &[Place],
+    to_places: &[Place],
+    max_distance: f64
+) -> Vec<(usize, usize, f64)> {
+    let mut candidates: Vec<(usize, usize, f64)> = from.iter()
+        .flat_map(|&i| to.iter().map(move |&j| (i, j)))
+        .map(|(i, j)| (i, j, distance(from_places[i].location, to_places[j].location)))
+        .filter(|(_, _, distance)| *distance <= max_distance)
+        .collect();
+    candidates.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let mut paired_from = HashSet::new();
+    let mut paired_to = HashSet::new();
+    let mut pairs = Vec::new();
+    for (i, j, distance) in candidates {
+        if !paired_from.contains(&i) && !paired_to.contains(&j) {
+            paired_from.insert(i);
+            paired_to.insert(j);
+            pairs.push((i, j, distance));
+        }
+    }
+    pairs
+}
+
+// Group the places of a campaign by address. An address listed twice at about the same location is the same
+// place: keep only the first one, so that the duplicate isn't reported as added or moved.
+fn by_address(places: &[Place], move_distance: f64) -> BTreeMap<(String, String), Vec<usize>> {
+    let mut groups: BTreeMap<_, Vec<usize>> = BTreeMap::new();
+    for (i, place) in places.iter().enumerate() {
+        let group = groups.entry(address_key(place)).or_default();
+        if !group.iter().any(|&j| distance(places[j].location, place.location) <= move_distance) {
+            group.push(i);
+        }
+    }
+    groups
+}
+
+// Compare the places of two campaigns
+pub fn diff(from: &[Place], to: &[Place], options: DiffOptions) -> Diff {
+    let mut result = Diff::default();
+    let from_groups = by_address(from, options.move_distance);
+    let mut to_groups = by_address(to, options.move_distance);
+
+    let mut unmatched_from = Vec::new();
+    for (key, from_indices) in &from_groups {
+        let to_indices = to_groups.remove(key).unwrap_or_default();
+        // Whatever the distance: an address that is in both campaigns is the same place
+        let pairs = pair_closest(from_indices, &to_indices, from, to, f64::INFINITY);
+        for (i, j, distance) in &pairs {
+            if *distance > options.move_distance {
+                result.changes.push(Change {
+                    status: Status::Moved,
+                    from: Some(from[*i].clone()),
+                    to: Some(to[*j].clone()),
+                    distance: Some(*distance),
+                });
+            } else {
+                result.unchanged += 1;
+            }
+        }
+        unmatched_from.extend(from_indices.iter().filter(|i| !pairs.iter().any(|(k, _, _)| k == *i)));
+        // Addresses listed more often in the second campaign
+        let unpaired_to = to_indices.into_iter().filter(|j| !pairs.iter().any(|(_, k, _)| k == j)).collect();
+        to_groups.insert(key.clone(), unpaired_to);
+    }
+    let unmatched_to: Vec<usize> = to_groups.into_values().flatten().collect();
+
+    // Renamed addresses: different address, but (almost) the same location
+    let renamed = pair_closest(&unmatched_from, &unmatched_to, from, to, options.match_radius);
+    for (i, j, distance) in &renamed {
+        result.changes.push(Change {
+            status: Status::Renamed,
+            from: Some(from[*i].clone()),
+            to: Some(to[*j].clone()),
+            distance: Some(*distance),
+        });
+    }
+
+    for i in unmatched_from.into_iter().filter(|i| !renamed.iter().any(|(k, _, _)| k == i)) {
+        result.changes.push(Change { status: Status::Removed, from: Some(from[i].clone()), to: None, distance: None });
+    }
+    for j in unmatched_to.into_iter().filter(|j| !renamed.iter().any(|(_, k, _)| k == j)) {
+        result.changes.push(Change { status: Status::Added, from: None, to: Some(to[j].clone()), distance: None });
+    }
+
+    result.changes.sort_by(|a, b| {
+        (a.status, &a.place().city, &a.place().street).cmp(&(b.status, &b.place().city, &b.place().street))
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: DiffOptions = DiffOptions { move_distance: 50.0, match_radius: 30.0 };
+
+    // A place of Toulouse `north` meters north of the Capitole
+    fn place(record_id: &str, street: &str, north: f64) -> Place {
+        let location = GeoPoint::new(43.6044622 + north / 111_195.0, 1.4442469);
+        Place { record_id: record_id.to_string(), street: street.to_string(), city: "Toulouse".to_string(), location }
+    }
+
+    fn in_city(place: Place, city: &str) -> Place {
+        Place { city: city.to_string(), ..place }
+    }
+
+    // Status, street and rounded distance of the changes, and the number of unchanged places
+    fn changes(from: &[Place], to: &[Place]) -> (Vec<(Status, String, Option<i64>)>, usize) {
+        let diff = diff(from, to, OPTIONS);
+        let changes = diff.changes.iter()
+            .map(|change| (change.status, change.place().street.clone(), change.distance.map(|d| d.round() as i64)))
+            .collect();
+        (changes, diff.unchanged)
+    }
+
+    fn change(status: Status, street: &str, distance: Option<i64>) -> (Status, String, Option<i64>) {
+        (status, street.to_string(), distance)
+    }
+
+    #[test]
+    fn places_are_matched_by_address_ignoring_case_accents_and_punctuation() {
+        let from = [place("a1", "88 Allée Jean-Jaurès", 0.0)];
+        let to = [in_city(place("b2", "88 allee jean jaures", 20.0), "TOULOUSE")];
+        assert_eq!(changes(&from, &to), (vec![], 1));
+    }
+
+    #[test]
+    fn places_are_not_matched_by_record_id() {
+        // Ids are regenerated every year, the same id in both years can be another place
+        let from = [place("a1", "Place du Capitole", 0.0)];
+        let to = [in_city(place("a1", "rue des Écoles", 5_000.0), "Balma")];
+        assert_eq!(changes(&from, &to), (vec![
+            change(Status::Added, "rue des Écoles", None),
+            change(Status::Removed, "Place du Capitole", None),
+        ], 0));
+    }
+
+    #[test]
+    fn places_at_the_same_address_farther_than_the_move_distance_are_moved() {
+        let from = [place("a1", "Place du Capitole", 0.0), place("b1", "allée des Pins", 0.0)];
+        let to = [place("a2", "Place du Capitole", 120.0), place("b2", "allée des Pins", 49.0)];
+        assert_eq!(changes(&from, &to), (vec![change(Status::Moved, "Place du Capitole", Some(120))], 1));
+    }
+
+    #[test]
+    fn places_with_another_address_within_the_match_radius_are_renamed() {
+        let from = [place("a1", "Place du Capitole", 0.0), place("b1", "allée des Pins", 500.0)];
+        let to = [place("a2", "Pl. Capitole", 25.0), place("b2", "allée des Chênes", 540.0)];
+        assert_eq!(changes(&from, &to), (vec![
+            change(Status::Added, "allée des Chênes", None),
+            change(Status::Removed, "allée des Pins", None),
+            change(Status::Renamed, "Pl. Capitole", Some(25)),
+        ], 0));
+    }
+
+    #[test]
+    fn renamed_places_are_paired_with_the_closest_one() {
+        let from = [place("a1", "Place du Capitole", 0.0)];
+        let to = [place("a2", "Pl. Capitole", 20.0), place("b2", "Capitole", 10.0)];
+        assert_eq!(changes(&from, &to), (vec![
+            change(Status::Added, "Pl. Capitole", None),
+            change(Status::Renamed, "Capitole", Some(10)),
+        ], 0));
+    }
+
+    #[test]
+    fn addresses_listed_twice_at_the_same_location_are_one_place() {
+        let from = [place("a1", "Place du Capitole", 0.0)];
+        let to = [place("a2", "Place du Capitole", 0.0), place("b2", "place du capitole", 10.0)];
+        assert_eq!(changes(&from, &to), (vec![], 1));
+        assert_eq!(changes(&to, &from), (vec![], 1));
+    }
+
+    #[test]
+    fn addresses_listed_twice_at_different_locations_are_paired_closest_first() {
+        // Both ends of a long street, one of which moved a bit: the other one isn't reported as moved to it
+        let from = [place("a1", "allée Jean Jaurès", 0.0), place("b1", "allée Jean Jaurès", 800.0)];
+        let to = [place("a2", "allée Jean Jaurès", 900.0), place("b2", "allée Jean Jaurès", 0.0)];
+        assert_eq!(changes(&from, &to), (vec![change(Status::Moved, "allée Jean Jaurès", Some(100))], 1));
+    }
+
+    #[test]
+    fn addresses_listed_more_often_in_one_campaign_are_added_or_removed() {
+        let from = [place("a1", "allée Jean Jaurès", 0.0)];
+        let to = [place("a2", "allée Jean Jaurès", 0.0), place("b2", "allée Jean Jaurès", 800.0)];
+        assert_eq!(changes(&from, &to), (vec![change(Status::Added, "allée Jean Jaurès", None)], 1));
+    }
+}
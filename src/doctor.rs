@@ -0,0 +1,206 @@
+// Checks of the setup done by the doctor command before a first run: each one passes, warns about something
+// that may cause problems later, or fails with the reason why a run wouldn't work.
+use crate::index;
+use elasticsearch::nodes::NodesStatsParts;
+use elasticsearch::Elasticsearch;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub reason: String,
+}
+
+impl Check {
+    pub fn pass(name: &'static str, reason: impl Into<String>) -> Check {
+        Check { name, status: Status::Pass, reason: reason.into() }
+    }
+
+    pub fn warn(name: &'static str, reason: impl Into<String>) -> Check {
+        Check { name, status: Status::Warn, reason: reason.into() }
+    }
+
+    pub fn fail(name: &'static str, reason: impl Into<String>) -> Check {
+        Check { name, status: Status::Fail, reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let status = match self.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "{}  {:<13} {}", status, self.name, self.reason)
+    }
+}
+
+// Whether the cluster answers, and accepts the credentials
+pub async fn connection(es_client: &Elasticsearch, cluster: &str) -> Check {
+    let response = match es_client.ping().send().await {
+        Ok(response) => response,
+        Err(err) => return Check::fail("connection", format!("Cannot connect to {}: {}", cluster, err)),
+    };
+    match response.status_code().as_u16() {
+        200..=299 => Check::pass("connection", format!("Connected to {}", cluster)),
+        401 => Check::fail("connection", format!("{} rejected the credentials", cluster)),
+        403 => Check::fail("connection", format!("The user isn't allowed to access {}", cluster)),
+        status => Check::fail("connection", format!("{} answered with HTTP status {}", cluster, status)),
+    }
+}
+
+// Red clusters don't accept the data of some indices, and runs wait until they're at least yellow
+pub async fn cluster_health(es_client: &Elasticsearch) -> Check {
+    match crate::health::status(es_client).await {
+        Ok(status) if status == "green" => Check::pass("health", "Cluster health is green"),
+        Ok(status) if status == "yellow" => {
+            Check::warn("health", "Cluster health is yellow, some replicas aren't allocated")
+        }
+        Ok(status) => Check::fail("health", format!("Cluster health is {}, runs would wait until it's yellow", status)),
+        Err(err) => Check::fail("health", format!("Cannot get the cluster health: {}", err)),
+    }
+}
+
+// The disk usage of the fullest node, compared with the watermarks of the disk-based shard allocation
+pub async fn disk_watermarks(es_client: &Elasticsearch) -> Check {
+    match fullest_node(es_client).await {
+        Ok(check) => check,
+        Err(err) => Check::warn("disk", format!("Cannot get the disk usage of the nodes: {}", err)),
+    }
+}
+
+async fn fullest_node(es_client: &Elasticsearch) -> anyhow::Result<Check> {
+    let settings = es_client.cluster()
+        .get_settings()
+        .include_defaults(true)
+        .flat_settings(true)
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+    // Transient settings override persistent ones, which override the defaults
+    let setting = |name: &str| ["transient", "persistent", "defaults"].iter()
+        .find_map(|level| settings[level][name].as_str().map(str::to_string));
+
+    if setting("cluster.routing.allocation.disk.threshold_enabled").as_deref() == Some("false") {
+        return Ok(Check::pass("disk", "Disk-based shard allocation is disabled"));
+    }
+    let watermark = |name: &str, default: &str| {
+        let value = setting(&format!("cluster.routing.allocation.disk.watermark.{}", name));
+        Watermark::parse(value.as_deref().unwrap_or(default))
+    };
+    let watermarks = [
+        ("flood stage", watermark("flood_stage", "95%"), "its indices are made read-only"),
+        ("high", watermark("high", "90%"), "shards are moved away from it"),
+        ("low", watermark("low", "85%"), "no new shards are allocated to it"),
+    ];
+
+    let stats = es_client.nodes()
+        .stats(NodesStatsParts::Metric(&["fs"]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+    let mut nodes: Vec<(String, u64, u64)> = stats["nodes"].as_object()
+        .map(|nodes| nodes.iter()
+            .filter_map(|(id, node)| {
+                let total = node["fs"]["total"]["total_in_bytes"].as_u64()?;
+                let available = node["fs"]["total"]["available_in_bytes"].as_u64()?;
+                let name = node["name"].as_str().unwrap_or(id).to_string();
+                Some((name, total, available))
+            })
+            .collect())
+        .unwrap_or_default();
+    if nodes.is_empty() {
+        return Ok(Check::warn("disk", "The nodes don't report their disk usage"));
+    }
+
+    nodes.sort_by(|a, b| used_percent(b.1, b.2).total_cmp(&used_percent(a.1, a.2)));
+    let (name, total, available) = &nodes[0];
+    let used = used_percent(*total, *available);
+
+    for (index, (level, watermark, consequence)) in watermarks.iter().enumerate() {
+        if let Some(watermark) = watermark {
+            if watermark.is_reached(*total, *available) {
+                let reason = format!(
+                    "Node {} is above the {} watermark ({:.0}% used): {}", name, level, used, consequence
+                );
+                // Above the flood stage, nothing can be written
+                return Ok(if index == 0 { Check::fail("disk", reason) } else { Check::warn("disk", reason) });
+            }
+        }
+    }
+    Ok(Check::pass("disk", format!("Highest disk usage is {:.0}% on node {}, below the watermarks", used, name)))
+}
+
+fn used_percent(total: u64, available: u64) -> f64 {
+    if total == 0 { 0.0 } else { (total - available.min(total)) as f64 * 100.0 / total as f64 }
+}
+
+// A watermark is a percentage or ratio of used disk space, or an amount of free space such as "500mb"
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Watermark {
+    UsedPercent(f64),
+    FreeBytes(u64),
+}
+
+impl Watermark {
+    // None for values that can't be understood, which are ignored
+    fn parse(value: &str) -> Option<Watermark> {
+        let value = value.trim().to_lowercase();
+        if let Some(percent) = value.strip_suffix('%') {
+            return percent.trim().parse().ok().map(Watermark::UsedPercent);
+        }
+        if let Ok(ratio) = value.parse::<f64>() {
+            return Some(Watermark::UsedPercent(ratio * 100.0));
+        }
+        let digits = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+        let (number, unit) = value.split_at(digits);
+        let multiplier: u64 = match unit.trim() {
+            "b" => 1,
+            "kb" => 1 << 10,
+            "mb" => 1 << 20,
+            "gb" => 1 << 30,
+            "tb" => 1 << 40,
+            "pb" => 1 << 50,
+            _ => return None,
+        };
+        let number: f64 = number.parse().ok()?;
+        Some(Watermark::FreeBytes((number * multiplier as f64) as u64))
+    }
+
+    fn is_reached(&self, total: u64, available: u64) -> bool {
+        match self {
+            Watermark::UsedPercent(percent) => used_percent(total, available) >= *percent,
+            Watermark::FreeBytes(bytes) => available <= *bytes,
+        }
+    }
+}
+
+// Whether `name` is an index or an alias, and how many documents it has. Not existing yet is fine, the first
+// run creates it.
+pub async fn target_index(es_client: &Elasticsearch, name: &str) -> Check {
+    let result = async {
+        let indices = index::indices_for(es_client, name).await?;
+        let count = index::count_documents(es_client, name).await?;
+        Ok::<_, anyhow::Error>((indices, count))
+    }.await;
+
+    match result {
+        Err(err) => Check::fail("index", format!("Cannot get index {}: {}", name, err)),
+        Ok((_, None)) => Check::pass("index", format!("Index {} doesn't exist yet, it will be created", name)),
+        Ok((indices, Some(count))) if !indices.iter().any(|index| index == name) => Check::pass(
+            "index", format!("Alias {} has {} documents, in indices {}", name, count, indices.join(", "))
+        ),
+        Ok((_, Some(count))) => Check::pass("index", format!("Index {} has {} documents", name, count)),
+    }
+}
@@ -0,0 +1,96 @@
+use thiserror::Error;
+
+// Errors of an ingestion run, by stage, so that schedulers can tell them apart with the process exit code.
+// The underlying cause is kept as the error source.
+#[derive(Debug, Error)]
+pub enum IngestError {
+    #[error("Failed to fetch the source data")]
+    Fetch(#[source] anyhow::Error),
+
+    #[error("Invalid source data")]
+    Parse(#[source] anyhow::Error),
+
+    #[error("Failed to set up the index")]
+    IndexSetup(#[source] anyhow::Error),
+
+    #[error("{report}")]
+    Bulk { failed: usize, report: String },
+
+    #[error("The index doesn't have the expected content")]
+    Verification(#[source] anyhow::Error),
+
+    #[error("Interrupted, {indexed} documents were stored")]
+    Interrupted { indexed: usize },
+}
+
+impl IngestError {
+    // Keep in sync with the exit codes listed in the command line help
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IngestError::Fetch(_) => 10,
+            IngestError::Parse(_) => 11,
+            IngestError::IndexSetup(_) => 12,
+            IngestError::Bulk { .. } => 13,
+            IngestError::Verification(_) => 14,
+            // As shells do for processes killed by Ctrl-C
+            IngestError::Interrupted { .. } => 130,
+        }
+    }
+}
+
+// Exit code for errors that aren't ingestion errors, such as configuration errors
+pub const DEFAULT_EXIT_CODE: i32 = 1;
+
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.downcast_ref::<IngestError>().map_or(DEFAULT_EXIT_CODE, IngestError::exit_code)
+}
+
+pub trait Classify<T> {
+    // Classify the error as `kind`, unless it's already an ingestion error
+    fn classify(self, kind: fn(anyhow::Error) -> IngestError) -> anyhow::Result<T>;
+}
+
+impl<T> Classify<T> for anyhow::Result<T> {
+    fn classify(self, kind: fn(anyhow::Error) -> IngestError) -> anyhow::Result<T> {
+        self.map_err(|err| if err.is::<IngestError>() { err } else { kind(err).into() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn each_kind_of_error_has_its_exit_code() {
+        let cause = || anyhow!("connection refused");
+        let expected = [
+            (IngestError::Fetch(cause()), 10),
+            (IngestError::Parse(cause()), 11),
+            (IngestError::IndexSetup(cause()), 12),
+            (IngestError::Bulk { failed: 2, report: "2 documents failed".to_string() }, 13),
+            (IngestError::Verification(cause()), 14),
+            (IngestError::Interrupted { indexed: 0 }, 130),
+        ];
+        for (err, code) in expected {
+            let message = err.to_string();
+            // Also with some context added on the way up
+            assert_eq!(exit_code(&anyhow::Error::from(err).context("Loading the data")), code, "{}", message);
+        }
+    }
+
+    #[test]
+    fn other_errors_have_the_default_exit_code() {
+        assert_eq!(exit_code(&anyhow!("Invalid configuration")), DEFAULT_EXIT_CODE);
+        let io: anyhow::Result<()> = Err(std::io::Error::other("disk full").into());
+        assert_eq!(exit_code(&io.context("Failed to write the output").unwrap_err()), DEFAULT_EXIT_CODE);
+    }
+
+    #[test]
+    fn classify_keeps_the_first_kind_and_the_cause() {
+        let result: anyhow::Result<()> = Err(anyhow!("unexpected end of file"));
+        let err = result.classify(IngestError::Parse).classify(IngestError::IndexSetup).unwrap_err();
+        assert_eq!(exit_code(&err), 11);
+        assert_eq!(format!("{:#}", err), "Invalid source data: unexpected end of file");
+    }
+}
@@ -0,0 +1,109 @@
+// Lifecycle events of runs, written to stdout as JSON lines with --progress-events for the tools that orchestrate
+// them:
+//   {"timestamp":"2024-01-08T06:00:01.250Z","run_id":"4dc3...","event":"batch_indexed",
+//    "dataset":"collecte-des-sapins-de-noel","batch":3,"docs":500,"failed":0}
+// Logs go to stderr, so that they don't mix with the events.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    FetchStarted { dataset: String },
+    // Number of source records, before they're transformed
+    FetchCompleted { dataset: String, records: usize },
+    // Batches are numbered from 1 in each dataset, in the order they complete
+    BatchIndexed { dataset: String, batch: usize, docs: usize, failed: usize },
+    RunCompleted { indexed: usize, failed: usize },
+    RunFailed { indexed: usize, failed: usize, error: String },
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+    timestamp: DateTime<Utc>,
+    run_id: &'a str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+// The run that events are about, if they're enabled
+static RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+
+// Write the events of run `run_id` from now on
+pub fn start_run(run_id: &str) {
+    *RUN_ID.lock().unwrap() = Some(run_id.to_string());
+}
+
+pub fn emit(event: Event) {
+    let run_id = RUN_ID.lock().unwrap();
+    let Some(run_id) = run_id.as_deref() else { return };
+    let line = EventLine { timestamp: Utc::now(), run_id, event: &event };
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    // Events are informational: a closed stdout shouldn't stop the run
+    let _ = serde_json::to_writer(&mut out, &line).map_err(std::io::Error::from)
+        .and_then(|()| writeln!(out))
+        .and_then(|()| out.flush());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The line of `event`, which is the schema that tools rely on
+    fn line(event: Event) -> serde_json::Value {
+        let timestamp = "2024-01-08T06:00:01.25Z".parse().unwrap();
+        let line = EventLine { timestamp, run_id: "test-run", event: &event };
+        serde_json::to_value(&line).unwrap()
+    }
+
+    #[test]
+    fn fetch_started_events() {
+        let event = Event::FetchStarted { dataset: "collecte-des-sapins-de-noel".to_string() };
+        assert_eq!(line(event), json!({
+            "timestamp": "2024-01-08T06:00:01.250Z", "run_id": "test-run", "event": "fetch_started",
+            "dataset": "collecte-des-sapins-de-noel"
+        }));
+    }
+
+    #[test]
+    fn fetch_completed_events() {
+        let event = Event::FetchCompleted { dataset: "collecte-des-sapins-de-noel".to_string(), records: 312 };
+        assert_eq!(line(event), json!({
+            "timestamp": "2024-01-08T06:00:01.250Z", "run_id": "test-run", "event": "fetch_completed",
+            "dataset": "collecte-des-sapins-de-noel", "records": 312
+        }));
+    }
+
+    #[test]
+    fn batch_indexed_events() {
+        let event = Event::BatchIndexed {
+            dataset: "collecte-des-sapins-de-noel".to_string(), batch: 3, docs: 498, failed: 2
+        };
+        assert_eq!(line(event), json!({
+            "timestamp": "2024-01-08T06:00:01.250Z", "run_id": "test-run", "event": "batch_indexed",
+            "dataset": "collecte-des-sapins-de-noel", "batch": 3, "docs": 498, "failed": 2
+        }));
+    }
+
+    #[test]
+    fn run_completed_events() {
+        let event = Event::RunCompleted { indexed: 312, failed: 0 };
+        assert_eq!(line(event), json!({
+            "timestamp": "2024-01-08T06:00:01.250Z", "run_id": "test-run", "event": "run_completed",
+            "indexed": 312, "failed": 0
+        }));
+    }
+
+    #[test]
+    fn run_failed_events() {
+        let event = Event::RunFailed { indexed: 200, failed: 0, error: "Failed to store data".to_string() };
+        assert_eq!(line(event), json!({
+            "timestamp": "2024-01-08T06:00:01.250Z", "run_id": "test-run", "event": "run_failed",
+            "indexed": 200, "failed": 0, "error": "Failed to store data"
+        }));
+    }
+}
@@ -0,0 +1,80 @@
+// Formats of the `export` command, to use the places without Elasticsearch
+use crate::transform::IndexedPlace;
+use crate::html::{self, MapOptions};
+use crate::{csv, geojson, gpx, kml};
+use std::io::Write;
+
+pub trait Exporter {
+    fn write(&self, places: &[IndexedPlace], out: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+pub struct GeojsonExporter;
+
+impl Exporter for GeojsonExporter {
+    fn write(&self, places: &[IndexedPlace], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        geojson::write_feature_collection(places, &mut out)
+    }
+}
+
+pub struct CsvExporter {
+    pub bom: bool,
+}
+
+impl Exporter for CsvExporter {
+    fn write(&self, places: &[IndexedPlace], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        csv::write_csv(places, &mut out, self.bom)
+    }
+}
+
+pub struct KmlExporter;
+
+impl Exporter for KmlExporter {
+    fn write(&self, places: &[IndexedPlace], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        kml::write_kml(places, &mut out)
+    }
+}
+
+pub struct GpxExporter;
+
+impl Exporter for GpxExporter {
+    fn write(&self, places: &[IndexedPlace], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        gpx::write_gpx(places, &mut out)
+    }
+}
+
+pub struct HtmlExporter {
+    pub options: MapOptions,
+}
+
+impl Exporter for HtmlExporter {
+    fn write(&self, places: &[IndexedPlace], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        html::write_map(places, &mut out, self.options)
+    }
+}
+
+// Title of the KML document, GPX file and HTML map
+pub const TITLE: &str = "Toulouse xmas tree collection places";
+
+// Escape the characters that have a meaning in XML text and attribute values, and drop the control characters
+// that XML 1.0 doesn't allow at all
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() && c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Shown along with the name of points
+pub fn description(place: &IndexedPlace) -> String {
+    format!("{} ({})", place.city, place.record_id)
+}
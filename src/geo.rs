@@ -0,0 +1,338 @@
+// Locations, and location codes and distances computed from their coordinates in degrees
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+const PLUS_CODE_ALPHABET: &[u8; 20] = b"23456789CFGHJMPQRVWX";
+// Number of digits before the '+' separator, and in total for a standard code of about 14 x 14 meters
+const PLUS_CODE_SEPARATOR_POSITION: usize = 8;
+const PLUS_CODE_LENGTH: usize = 10;
+// Size of the cells of a standard code is 1/8000 degree
+const PLUS_CODE_RESOLUTION: f64 = 8000.0;
+
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// A location, with named coordinates: Elasticsearch reads geo_point arrays as [lon, lat] but strings as
+// "lat,lon", and a tuple makes it too easy to swap them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> GeoPoint {
+        GeoPoint { lat, lon }
+    }
+}
+
+// How points are serialized. They can be deserialized from any of these formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointFormat {
+    // [lon, lat], the GeoJSON order
+    Array,
+    // {"lat": lat, "lon": lon}
+    Object,
+    // "lat,lon"
+    String,
+}
+
+// The same for the whole process, set once from the command line
+static POINT_FORMAT: AtomicU8 = AtomicU8::new(PointFormat::Array as u8);
+
+pub fn set_point_format(format: PointFormat) {
+    POINT_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn point_format() -> PointFormat {
+    match POINT_FORMAT.load(Ordering::Relaxed) {
+        value if value == PointFormat::Object as u8 => PointFormat::Object,
+        value if value == PointFormat::String as u8 => PointFormat::String,
+        _ => PointFormat::Array,
+    }
+}
+
+impl GeoPoint {
+    // Serialize in `format` rather than the format of the process
+    pub fn serialize_as<S: Serializer>(&self, format: PointFormat, serializer: S) -> Result<S::Ok, S::Error> {
+        match format {
+            PointFormat::Array => [self.lon, self.lat].serialize(serializer),
+            PointFormat::Object => LatLon { lat: self.lat, lon: self.lon }.serialize(serializer),
+            // Floats are written with the shortest representation that reads back as the same number
+            PointFormat::String => serializer.collect_str(&format_args!("{},{}", self.lat, self.lon)),
+        }
+    }
+}
+
+impl Serialize for GeoPoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_as(point_format(), serializer)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LatLon {
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyPoint {
+    Array([f64; 2]),
+    Object(LatLon),
+    String(String),
+}
+
+impl<'de> Deserialize<'de> for GeoPoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<GeoPoint, D::Error> {
+        match AnyPoint::deserialize(deserializer)? {
+            AnyPoint::Array([lon, lat]) => Ok(GeoPoint { lat, lon }),
+            AnyPoint::Object(LatLon { lat, lon }) => Ok(GeoPoint { lat, lon }),
+            AnyPoint::String(text) => {
+                let invalid = || D::Error::custom(format!("invalid point '{}', expecting 'lat,lon'", text));
+                let (lat, lon) = text.split_once(',').ok_or_else(invalid)?;
+                let lat = lat.trim().parse().map_err(|_| invalid())?;
+                let lon = lon.trim().parse().map_err(|_| invalid())?;
+                Ok(GeoPoint { lat, lon })
+            }
+        }
+    }
+}
+
+// Geohash of `precision` characters (between 1 and 12). Bits alternate between longitude and latitude,
+// starting with longitude, each one halving the range that contains the location.
+pub fn geohash(lon: f64, lat: f64, precision: usize) -> String {
+    let (mut lon_range, mut lat_range) = ((-180.0, 180.0), (-90.0, 90.0));
+    let mut hash = String::with_capacity(precision);
+    let mut is_lon = true;
+
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if is_lon { (&mut lon_range, lon) } else { (&mut lat_range, lat) };
+            let middle = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= middle {
+                index |= 1;
+                range.0 = middle;
+            } else {
+                range.1 = middle;
+            }
+            is_lon = !is_lon;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+// Open Location Code ("plus code") with the standard 10 digits, such as "8FM3JC2V+V3"
+pub fn plus_code(lon: f64, lat: f64) -> String {
+    // Latitude 90 is in the cell just below it, and longitudes wrap around
+    let lat = lat.clamp(-90.0, 90.0);
+    let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+    let max_lat_value = (180.0 * PLUS_CODE_RESOLUTION) as i64 - 1;
+
+    // Integers, so that floating point imprecision doesn't change the digits. Rounding at 6 decimals first
+    // makes values such as 43.6 x 8000 = 348799.99999999994 land in the expected cell.
+    let to_value = |degrees: f64| ((degrees * PLUS_CODE_RESOLUTION * 1e6).round() / 1e6).floor() as i64;
+    let mut lat_value = to_value(lat + 90.0).min(max_lat_value);
+    let mut lon_value = to_value(lon + 180.0);
+
+    let mut digits = [0u8; PLUS_CODE_LENGTH];
+    for pair in (0..PLUS_CODE_LENGTH / 2).rev() {
+        digits[pair * 2] = PLUS_CODE_ALPHABET[(lat_value % 20) as usize];
+        digits[pair * 2 + 1] = PLUS_CODE_ALPHABET[(lon_value % 20) as usize];
+        lat_value /= 20;
+        lon_value /= 20;
+    }
+
+    let digits: String = digits.iter().map(|digit| *digit as char).collect();
+    format!("{}+{}", &digits[..PLUS_CODE_SEPARATOR_POSITION], &digits[PLUS_CODE_SEPARATOR_POSITION..])
+}
+
+// Great-circle distance in meters between two locations
+pub fn distance(a: GeoPoint, b: GeoPoint) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let (d_lat, d_lon) = (lat2 - lat1, (b.lon - a.lon).to_radians());
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+// Whether (`lon`, `lat`) is inside `polygon`, a GeoJSON polygon whose first ring is the outline and the others
+// holes. Points on the outline are inside, and points on the outline of a hole aren't in it.
+pub fn polygon_contains(polygon: &[Vec<(f64, f64)>], lon: f64, lat: f64) -> bool {
+    let Some((outline, holes)) = polygon.split_first() else { return false };
+    ring_contains(outline, lon, lat)
+        && !holes.iter().any(|hole| ring_contains(hole, lon, lat) && !on_ring(hole, lon, lat))
+}
+
+// Ray casting: the number of edges that a ray going east crosses is odd for points inside of the ring. Rings can
+// be closed or not.
+fn ring_contains(ring: &[(f64, f64)], lon: f64, lat: f64) -> bool {
+    if on_ring(ring, lon, lat) {
+        return true;
+    }
+    let mut inside = false;
+    for (&(lon1, lat1), &(lon2, lat2)) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        if (lat1 > lat) != (lat2 > lat) && lon < lon1 + (lat - lat1) * (lon2 - lon1) / (lat2 - lat1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn on_ring(ring: &[(f64, f64)], lon: f64, lat: f64) -> bool {
+    // About a millimeter, so that rounding doesn't decide on which side of an edge a point is
+    const TOLERANCE: f64 = 1e-8;
+    ring.iter().zip(ring.iter().cycle().skip(1)).any(|(&(lon1, lat1), &(lon2, lat2))| {
+        let cross = (lon2 - lon1) * (lat - lat1) - (lat2 - lat1) * (lon - lon1);
+        let length = (lon2 - lon1).hypot(lat2 - lat1);
+        let within = lon >= lon1.min(lon2) - TOLERANCE && lon <= lon1.max(lon2) + TOLERANCE
+            && lat >= lat1.min(lat2) - TOLERANCE && lat <= lat1.max(lat2) + TOLERANCE;
+        within && cross.abs() <= TOLERANCE * length.max(TOLERANCE)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The Capitole, in Toulouse
+    const CAPITOLE: (f64, f64) = (1.4442469, 43.6044622);
+
+    #[test]
+    fn geohash_of_reference_locations() {
+        let (lon, lat) = CAPITOLE;
+        assert_eq!(geohash(lon, lat, 7), "spc00cg");
+        assert_eq!(geohash(lon, lat, 12), "spc00cgwmkus");
+        assert_eq!(geohash(lon, lat, 1), "s");
+        assert_eq!(geohash(10.40744, 57.64911, 11), "u4pruydqqvj");
+        assert_eq!(geohash(-5.6, 42.6, 5), "ezs42");
+    }
+
+    #[test]
+    fn plus_code_of_reference_locations() {
+        let (lon, lat) = CAPITOLE;
+        assert_eq!(plus_code(lon, lat), "8FM3JC3V+QM");
+        assert_eq!(plus_code(1.45385907091, 43.6089310498), "8FM3JF53+HG");
+        // From the test data of the Open Location Code reference implementation
+        assert_eq!(plus_code(8.0000625, 47.0000625), "8FVC2222+22");
+        assert_eq!(plus_code(2.7821875, 20.3700625), "7FG49QCJ+2V");
+        assert_eq!(plus_code(174.7859375, -41.2730625), "4VCPPQGP+Q9");
+    }
+
+    fn to_json(point: GeoPoint, format: PointFormat) -> serde_json::Value {
+        point.serialize_as(format, serde_json::value::Serializer).unwrap()
+    }
+
+    #[test]
+    fn points_are_serialized_in_each_format() {
+        let point = GeoPoint::new(CAPITOLE.1, CAPITOLE.0);
+        assert_eq!(to_json(point, PointFormat::Array), serde_json::json!([1.4442469, 43.6044622]));
+        assert_eq!(to_json(point, PointFormat::Object), serde_json::json!({ "lat": 43.6044622, "lon": 1.4442469 }));
+        assert_eq!(to_json(point, PointFormat::String), serde_json::json!("43.6044622,1.4442469"));
+    }
+
+    #[test]
+    fn invalid_point_strings_are_errors() {
+        for text in ["43.6044622", "43.6044622;1.4442469", "north,east", ""] {
+            let err = serde_json::from_value::<GeoPoint>(serde_json::json!(text)).unwrap_err();
+            assert!(err.to_string().contains("expecting 'lat,lon'"), "{}: {}", text, err);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn points_round_trip_in_each_format(lat in -90.0..=90.0f64, lon in -180.0..=180.0f64) {
+            let point = GeoPoint::new(lat, lon);
+            for format in [PointFormat::Array, PointFormat::Object, PointFormat::String] {
+                let json = to_json(point, format);
+                let parsed: GeoPoint = serde_json::from_value(json.clone()).unwrap();
+                proptest::prop_assert_eq!(parsed, point, "{:?}: {}", format, json);
+
+                // Also as text, the way documents are sent
+                let parsed: GeoPoint = serde_json::from_str(&json.to_string()).unwrap();
+                proptest::prop_assert_eq!(parsed, point, "{:?}: {}", format, json);
+            }
+
+            // Each format has its own order: Elasticsearch reads arrays as [lon, lat] and strings as "lat,lon"
+            proptest::prop_assert_eq!(to_json(point, PointFormat::Array), serde_json::json!([lon, lat]));
+            let string = format!("{},{}", lat, lon);
+            proptest::prop_assert_eq!(to_json(point, PointFormat::String), serde_json::json!(string));
+            proptest::prop_assert_eq!(to_json(point, PointFormat::Object)["lat"].as_f64(), Some(lat));
+        }
+    }
+
+    #[test]
+    fn distances_between_reference_locations() {
+        let (lon, lat) = CAPITOLE;
+        let capitole = GeoPoint::new(lat, lon);
+        assert_eq!(distance(capitole, capitole), 0.0);
+        // The Gare Matabiau, about 1 km away
+        let matabiau = GeoPoint::new(43.6112, 1.4537);
+        assert!((distance(capitole, matabiau) - 1_068.0).abs() < 1.0, "{}", distance(capitole, matabiau));
+        assert_eq!(distance(capitole, matabiau), distance(matabiau, capitole));
+        // A degree of latitude is 1/360 of a great circle
+        let north = GeoPoint::new(lat + 1.0, lon);
+        assert!((distance(capitole, north) - 2.0 * std::f64::consts::PI * EARTH_RADIUS / 360.0).abs() < 1e-6);
+        // Antipodes
+        let antipode = GeoPoint::new(-lat, lon - 180.0);
+        assert!((distance(capitole, antipode) - std::f64::consts::PI * EARTH_RADIUS).abs() < 1e-3);
+    }
+
+    #[test]
+    fn points_of_polygons_with_holes() {
+        // A square of 4 degrees with a square hole of 2 degrees in its middle, the outline not closed
+        let polygon = vec![
+            vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)],
+            vec![(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0), (1.0, 1.0)],
+        ];
+        let inside = [(0.5, 0.5), (3.5, 2.0), (2.0, 3.9)];
+        let in_the_hole = [(2.0, 2.0), (1.5, 2.5)];
+        let outside = [(-0.5, 2.0), (4.5, 2.0), (2.0, -1e-6), (5.0, 5.0)];
+        for &(lon, lat) in &inside {
+            assert!(polygon_contains(&polygon, lon, lat), "{} {}", lon, lat);
+        }
+        for (lon, lat) in [&in_the_hole[..], &outside].concat() {
+            assert!(!polygon_contains(&polygon, lon, lat), "{} {}", lon, lat);
+        }
+        assert!(!polygon_contains(&[], 2.0, 2.0));
+    }
+
+    #[test]
+    fn points_on_edges_are_inside() {
+        let polygon = vec![
+            vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (0.0, 0.0)],
+            vec![(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)],
+        ];
+        // Corners, edges, and the closing edge of the hole which isn't closed
+        let outline = [(0.0, 0.0), (4.0, 4.0), (2.0, 0.0), (4.0, 1.5), (0.0, 3.0), (2.0, 4.0)];
+        let hole = [(1.0, 1.0), (3.0, 3.0), (2.0, 1.0), (3.0, 2.5), (1.0, 2.0), (2.0, 3.0)];
+        for (lon, lat) in [&outline[..], &hole].concat() {
+            assert!(polygon_contains(&polygon, lon, lat), "{} {}", lon, lat);
+            // Within the tolerance
+            assert!(polygon_contains(&polygon, lon + 5e-9, lat - 5e-9), "{} {}", lon, lat);
+        }
+        // Not further
+        assert!(!polygon_contains(&polygon, 2.0, -1e-7));
+        assert!(!polygon_contains(&polygon, 2.0, 1.0 + 1e-7));
+        assert!(!polygon_contains(&polygon, 1.0 + 1e-7, 2.0));
+    }
+
+    #[test]
+    fn points_on_diagonal_edges_are_inside() {
+        // A triangle of Toulouse, whose edges aren't exact in floating point
+        let (lon, lat) = CAPITOLE;
+        let triangle = vec![vec![(lon, lat), (lon + 0.1, lat + 0.03), (lon + 0.02, lat + 0.07)]];
+        for t in [0.1, 0.25, 0.5, 0.9] {
+            let on_edge = (lon + 0.1 * t, lat + 0.03 * t);
+            assert!(polygon_contains(&triangle, on_edge.0, on_edge.1), "{:?}", on_edge);
+            let on_other_edge = (lon + 0.1 + (0.02 - 0.1) * t, lat + 0.03 + (0.07 - 0.03) * t);
+            assert!(polygon_contains(&triangle, on_other_edge.0, on_other_edge.1), "{:?}", on_other_edge);
+        }
+        assert!(!polygon_contains(&triangle, lon + 0.05, lat + 0.015 - 1e-6));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::retry::{http_failure, retry, RetryPolicy};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+// Address search of the Base Adresse Nationale
+pub const DEFAULT_GEOCODER_URL: &str = "https://api-adresse.data.gouv.fr/search/";
+
+// Results are a GeoJSON FeatureCollection, best match first, with a relevance score between 0 and 1:
+//   {
+//     "type": "FeatureCollection",
+//     "features": [ {
+//       "type": "Feature",
+//       "geometry": { "type": "Point", "coordinates": [1.442455, 43.606822] },
+//       "properties": { "label": "12 Rue du Taur 31000 Toulouse", "score": 0.97, ... }
+//     } ]
+//   }
+#[derive(Debug, Deserialize)]
+struct GeocodeResponse {
+    features: Vec<Feature>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Feature {
+    geometry: Geometry,
+    properties: Properties,
+}
+
+#[derive(Debug, Deserialize)]
+struct Geometry {
+    coordinates: (f64, f64), // lon, lat
+}
+
+#[derive(Debug, Deserialize)]
+struct Properties {
+    label: String,
+    score: f64,
+}
+
+// The best match for an address
+#[derive(Debug, Clone)]
+pub struct GeocodedAddress {
+    // The full address that was found, which may differ from the one that was searched
+    pub label: String,
+    pub score: f64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// Find the location of `address` with the geocoder at `url`, or None if it doesn't know that address
+#[instrument(name = "geocode", skip(client, retry_policy), fields(duration_ms))]
+pub async fn geocode(
+    client: &reqwest::Client,
+    url: &str,
+    address: &str,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<Option<GeocodedAddress>> {
+    let response = retry(retry_policy, "geocode the address", || async move {
+        client.get(url)
+            .query(&[("q", address), ("limit", "1")])
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(http_failure)?
+            .json::<GeocodeResponse>().await
+            .map_err(http_failure)
+    }).await?;
+
+    let address = response.features.into_iter().next().map(|feature| {
+        let (lon, lat) = feature.geometry.coordinates;
+        GeocodedAddress { label: feature.properties.label, score: feature.properties.score, lat, lon }
+    });
+    debug!("Best match: {:?}", address);
+    Ok(address)
+}
@@ -0,0 +1,90 @@
+use crate::diff::{Change, Diff, Status};
+use crate::geo::GeoPoint;
+use crate::transform::IndexedPlace;
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::io::Write;
+
+// A GeoJSON (RFC 7946) Feature with a Point geometry. Coordinates are written as is: serde_json outputs
+// the shortest representation that parses back to the same f64, so there's no precision loss.
+//   {
+//     "type": "Feature",
+//     "id": "ef89fdb5cbb3b397d2988b7d23c1fee5199b989c",
+//     "geometry": { "type": "Point", "coordinates": [1.45385907091, 43.6089310498] },
+//     "properties": { "street": "88 allée Jean Jaurès / angle rue Riquet", "city": "TOULOUSE", ... }
+//   }
+pub fn feature(place: &IndexedPlace) -> JsonValue {
+    let GeoPoint { lat, lon } = place.location;
+    json!({
+        "type": "Feature",
+        "id": place.record_id,
+        "geometry": {
+            "type": "Point",
+            "coordinates": [lon, lat],
+        },
+        "properties": {
+            "street": place.street,
+            "city": place.city,
+            "record_id": place.record_id,
+            "dataset_id": place.dataset_id,
+        }
+    })
+}
+
+pub fn feature_collection(places: &[IndexedPlace]) -> JsonValue {
+    json!({
+        "type": "FeatureCollection",
+        "features": places.iter().map(feature).collect::<Vec<_>>(),
+    })
+}
+
+pub fn write_feature_collection(places: &[IndexedPlace], out: &mut impl Write) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *out, &feature_collection(places))?;
+    writeln!(out)?;
+    Ok(())
+}
+
+// A change between two campaigns, at the place's most recent location. The `marker-color` property
+// (simplestyle-spec) colors the points by status in most map viewers.
+pub fn change_feature(change: &Change) -> JsonValue {
+    let place = change.place();
+    let GeoPoint { lat, lon } = place.location;
+    let color = match change.status {
+        Status::Added => "#2e7d32",
+        Status::Removed => "#c62828",
+        Status::Moved => "#ef6c00",
+        Status::Renamed => "#1565c0",
+    };
+    let mut feature = json!({
+        "type": "Feature",
+        "id": place.record_id,
+        "geometry": {
+            "type": "Point",
+            "coordinates": [lon, lat],
+        },
+        "properties": {
+            "status": change.status,
+            "street": place.street,
+            "city": place.city,
+            "record_id": place.record_id,
+            "marker-color": color,
+        }
+    });
+    if let (Some(from), Some(_)) = (&change.from, &change.to) {
+        let properties = &mut feature["properties"];
+        properties["previous_street"] = json!(from.street);
+        properties["previous_coordinates"] = json!([from.location.lon, from.location.lat]);
+        properties["distance"] = json!(change.distance);
+    }
+    feature
+}
+
+pub fn write_diff_feature_collection(diff: &Diff, out: &mut impl Write) -> anyhow::Result<()> {
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": diff.changes.iter().map(change_feature).collect::<Vec<_>>(),
+    });
+    serde_json::to_writer(&mut *out, &collection)?;
+    writeln!(out)?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use crate::export::{description, escape_xml, TITLE};
+use crate::geo::GeoPoint;
+use crate::transform::IndexedPlace;
+use std::io::Write;
+
+// A GPX 1.1 file with a waypoint per place, for car GPS and hiking apps. Coordinates are attributes, in
+// degrees.
+//   <wpt lat="43.6089310498" lon="1.45385907091">
+//     <name>88 allée Jean Jaurès / angle rue Riquet</name>
+//     <desc>Toulouse (ef89fdb5cbb3b397d2988b7d23c1fee5199b989c)</desc>
+//   </wpt>
+pub fn write_gpx(places: &[IndexedPlace], out: &mut impl Write) -> anyhow::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<gpx version="1.1" creator="{}" xmlns="http://www.topografix.com/GPX/1/1">"#,
+        env!("CARGO_PKG_NAME")
+    )?;
+    writeln!(out, "  <metadata><name>{}</name></metadata>", escape_xml(TITLE))?;
+    for place in places {
+        let GeoPoint { lat, lon } = place.location;
+        writeln!(out, r#"  <wpt lat="{}" lon="{}">"#, lat, lon)?;
+        writeln!(out, "    <name>{}</name>", escape_xml(&place.street))?;
+        writeln!(out, "    <desc>{}</desc>", escape_xml(&description(place)))?;
+        writeln!(out, "  </wpt>")?;
+    }
+    writeln!(out, "</gpx>")?;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use crate::retry::{es_failure, es_response, Failure};
+use anyhow::anyhow;
+use elasticsearch::cluster::ClusterHealthParts;
+use elasticsearch::Elasticsearch;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{info, instrument, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const UNREACHABLE: &str = "unknown (cluster unreachable)";
+
+#[derive(Debug, Deserialize)]
+struct ClusterHealth {
+    status: String,
+}
+
+// Wait until the cluster health is at least yellow, so that we don't start deleting data on a cluster that
+// won't accept the new data. A cluster that can't be reached (e.g. still starting up) is waited for as well.
+#[instrument(name = "health", skip(es_client))]
+pub async fn wait_for_health(es_client: &Elasticsearch, timeout: Duration) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut last_status = String::new();
+
+    loop {
+        let status = match cluster_health(es_client).await {
+            Ok(status) => status,
+            Err(Failure::Permanent(err)) => return Err(err.context("Failed to get the cluster health")),
+            Err(Failure::Transient(err) | Failure::RateLimited(err, _)) => {
+                if last_status != UNREACHABLE {
+                    warn!("Cannot get the cluster health: {}", err);
+                }
+                UNREACHABLE.to_string()
+            }
+        };
+
+        if status != last_status {
+            info!("Cluster health is {}", status);
+        }
+        if status == "green" || status == "yellow" {
+            return Ok(());
+        }
+        last_status = status;
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(anyhow!(
+                "Cluster health is still {} after {:?}, expecting at least yellow (use --skip-health-check to ignore)",
+                last_status, timeout
+            ));
+        }
+        tokio::time::delay_for(POLL_INTERVAL.min(deadline - now)).await;
+    }
+}
+
+// The cluster health status, without waiting: green, yellow or red
+pub async fn status(es_client: &Elasticsearch) -> anyhow::Result<String> {
+    cluster_health(es_client).await
+        .map_err(|(Failure::Transient(err) | Failure::Permanent(err) | Failure::RateLimited(err, _))| err)
+}
+
+async fn cluster_health(es_client: &Elasticsearch) -> Result<String, Failure> {
+    let response = es_client.cluster()
+        .health(ClusterHealthParts::None)
+        .send().await
+        .map_err(es_failure)?;
+    let health = es_response(response)?
+        .json::<ClusterHealth>().await
+        .map_err(|err| Failure::Permanent(err.into()))?;
+    Ok(health.status)
+}
@@ -0,0 +1,64 @@
+// A single HTML file with a map of the places, to share them with people who don't use GIS tools. The places
+// are embedded as a GeoJSON block, displayed with Leaflet and OpenStreetMap tiles, which need Internet access,
+// or drawn without a background map by a small inline script for offline use.
+use crate::export::{escape_xml, TITLE};
+use crate::geojson;
+use crate::transform::IndexedPlace;
+use std::io::Write;
+
+const LEAFLET_TEMPLATE: &str = include_str!("templates/leaflet.html");
+const OFFLINE_TEMPLATE: &str = include_str!("templates/offline.html");
+
+const MARKER_CLUSTER_ASSETS: &str = concat!(
+    r#"<link rel="stylesheet" href="https://unpkg.com/leaflet.markercluster@1.5.3/dist/MarkerCluster.css">"#, "\n",
+    r#"<link rel="stylesheet" href="https://unpkg.com/leaflet.markercluster@1.5.3/dist/MarkerCluster.Default.css">"#,
+    "\n",
+    r#"<script src="https://unpkg.com/leaflet.markercluster@1.5.3/dist/leaflet.markercluster.js"></script>"#,
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct MapOptions {
+    // Draw the points without Leaflet, which is loaded from a CDN
+    pub offline: bool,
+    // Markers are grouped in clusters if there are more places than this
+    pub cluster_above: usize,
+}
+
+pub fn write_map(places: &[IndexedPlace], out: &mut impl Write, options: MapOptions) -> anyhow::Result<()> {
+    let clustered = places.len() > options.cluster_above;
+    let template = if options.offline { OFFLINE_TEMPLATE } else { LEAFLET_TEMPLATE };
+    let html = fill(template, &[
+        ("title", escape_xml(TITLE)),
+        ("count", places.len().to_string()),
+        ("places", script_data(&serde_json::to_string(&geojson::feature_collection(places))?)),
+        ("cluster", clustered.to_string()),
+        ("cluster_assets", if clustered { MARKER_CLUSTER_ASSETS.to_string() } else { String::new() }),
+    ]);
+    out.write_all(html.as_bytes())?;
+    Ok(())
+}
+
+// Replace the {{name}} placeholders of `template`. Values are inserted as is, and aren't searched for
+// placeholders themselves.
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else { break };
+        let name = &rest[start + 2..start + end];
+        result.push_str(&rest[..start]);
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// JSON in a <script> element must not contain "</script" or "<!--". '<' can only be in its strings, where it can
+// be escaped.
+fn script_data(json: &str) -> String {
+    json.replace('<', "\\u003c")
+}
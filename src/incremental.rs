@@ -0,0 +1,274 @@
+use crate::geo::{self, GeoPoint};
+use crate::index;
+use crate::transform::IndexedPlace;
+use elasticsearch::Elasticsearch;
+use std::collections::{HashMap, HashSet};
+use tracing::instrument;
+
+// What has to change in the index so that it matches the source data
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub to_index: Vec<IndexedPlace>,
+    pub to_delete: Vec<String>,
+    // With soft deletes, documents that are no longer in the source data are kept as inactive
+    pub to_deactivate: Vec<String>,
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    // Inactive documents that are back in the source data, included in `to_index`
+    pub reactivated: usize,
+    // Documents that were already inactive, and stay so
+    pub inactive: usize,
+    // Record ids of the places of `to_index` that are new, back in the source data, or that moved: those that
+    // subscribers are alerted about
+    pub relocated: HashSet<String>,
+}
+
+// What's needed of a document of the index to compare it with the source data
+#[derive(Debug, Clone)]
+pub struct IndexedDocument {
+    pub content_hash: String,
+    pub active: bool,
+    pub location: Option<GeoPoint>,
+}
+
+// Locations closer than this are the same, whatever the rounding of their coordinates
+const SAME_LOCATION_METERS: f64 = 1.0;
+
+impl Changes {
+    // Everything is indexed, for when the index starts empty
+    pub fn all(places: Vec<IndexedPlace>) -> Changes {
+        let relocated = places.iter().map(|place| place.record_id.clone()).collect();
+        Changes { added: places.len(), to_index: places, relocated, ..Changes::default() }
+    }
+
+    // Number of documents in the index once the changes are applied
+    pub fn expected_count(&self) -> usize {
+        self.added + self.updated + self.unchanged + self.to_deactivate.len() + self.inactive
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} added, {} updated, {} deleted, {} unchanged",
+            self.added, self.updated, self.to_delete.len(), self.unchanged
+        );
+        if !self.to_deactivate.is_empty() || self.reactivated > 0 {
+            summary.push_str(&format!(
+                ", {} deactivated, {} reactivated", self.to_deactivate.len(), self.reactivated
+            ));
+        }
+        summary
+    }
+}
+
+// Compare the source `places` with the documents in the index, by record id. With `soft_delete`, documents that
+// are no longer in the source data are deactivated rather than deleted.
+pub fn plan_changes(
+    mut existing: HashMap<String, IndexedDocument>,
+    places: Vec<IndexedPlace>,
+    soft_delete: bool
+) -> Changes {
+    let mut changes = Changes::default();
+    for place in places {
+        match existing.remove(&place.record_id) {
+            None => {
+                changes.added += 1;
+                changes.relocated.insert(place.record_id.clone());
+                changes.to_index.push(place);
+            }
+            // Indexing it again makes it active, even if its content didn't change
+            Some(document) if !document.active => {
+                changes.updated += 1;
+                changes.reactivated += 1;
+                changes.relocated.insert(place.record_id.clone());
+                changes.to_index.push(place);
+            }
+            Some(document) if document.content_hash == place.content_hash => changes.unchanged += 1,
+            Some(document) => {
+                changes.updated += 1;
+                let moved = document.location
+                    .is_none_or(|location| geo::distance(location, place.location) > SAME_LOCATION_METERS);
+                if moved {
+                    changes.relocated.insert(place.record_id.clone());
+                }
+                changes.to_index.push(place);
+            }
+        }
+    }
+
+    let mut removed: Vec<String> = if soft_delete {
+        let (active, inactive): (Vec<_>, Vec<_>) = existing.into_iter().partition(|(_, document)| document.active);
+        changes.inactive = inactive.len();
+        active.into_iter().map(|(id, _)| id).collect()
+    } else {
+        existing.into_keys().collect()
+    };
+    removed.sort();
+    if soft_delete {
+        changes.to_deactivate = removed;
+    } else {
+        changes.to_delete = removed;
+    }
+    changes
+}
+
+// The content hash, activity and location of all documents in `index`, by document id (which is the record id).
+// Documents indexed by older versions have an empty hash, and will be updated.
+#[instrument(name = "hashes", skip(es_client))]
+pub async fn indexed_documents(
+    es_client: &Elasticsearch,
+    index: &str
+) -> anyhow::Result<HashMap<String, IndexedDocument>> {
+    let documents = index::scroll_documents(es_client, index, &["content_hash", "active", "location"]).await?;
+    Ok(documents.into_iter()
+        .map(|(id, source)| {
+            let document = IndexedDocument {
+                content_hash: source["content_hash"].as_str().unwrap_or_default().to_string(),
+                active: source["active"].as_bool().unwrap_or(true),
+                location: serde_json::from_value(source["location"].clone()).ok(),
+            };
+            (id, document)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A place `east` meters east of the Capitole
+    fn place(record_id: &str, content_hash: &str, east: f64) -> IndexedPlace {
+        serde_json::from_value(json!({
+            "dataset_id": "collecte-des-sapins-de-noel",
+            "record_id": record_id,
+            "city": "Toulouse",
+            "city_raw": "TOULOUSE",
+            "street": "Place du Capitole",
+            "street_raw": "Place du Capitole",
+            "location": location(east),
+            "indexed_at": "2024-01-08T06:00:00Z",
+            "source_url": "file:places.json",
+            "content_hash": content_hash
+        })).unwrap()
+    }
+
+    fn location(east: f64) -> GeoPoint {
+        GeoPoint::new(43.6044622, 1.4442469 + east / (111_195.0 * 43.6044622_f64.to_radians().cos()))
+    }
+
+    fn document(content_hash: &str, active: bool, east: f64) -> IndexedDocument {
+        IndexedDocument { content_hash: content_hash.to_string(), active, location: Some(location(east)) }
+    }
+
+    fn ids(places: &[IndexedPlace]) -> Vec<&str> {
+        places.iter().map(|place| place.record_id.as_str()).collect()
+    }
+
+    fn sorted(ids: &HashSet<String>) -> Vec<&str> {
+        let mut ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn places_are_added_updated_deleted_or_unchanged() {
+        let existing = HashMap::from([
+            ("a1".to_string(), document("hash-a", true, 0.0)),
+            ("b2".to_string(), document("hash-b", true, 0.0)),
+            ("c3".to_string(), document("hash-c", true, 0.0)),
+            ("d4".to_string(), document("hash-d", true, 0.0)),
+        ]);
+        let places = vec![place("a1", "hash-a", 0.0), place("b2", "hash-b2", 0.0), place("e5", "hash-e", 0.0)];
+        let changes = plan_changes(existing, places, false);
+
+        assert_eq!(ids(&changes.to_index), ["b2", "e5"]);
+        assert_eq!(changes.to_delete, ["c3", "d4"]);
+        assert!(changes.to_deactivate.is_empty());
+        assert_eq!((changes.added, changes.updated, changes.unchanged), (1, 1, 1));
+        assert_eq!(changes.expected_count(), 3);
+        assert_eq!(changes.summary(), "1 added, 1 updated, 2 deleted, 1 unchanged");
+    }
+
+    #[test]
+    fn documents_of_older_versions_are_updated() {
+        let existing = HashMap::from([
+            ("a1".to_string(), IndexedDocument { content_hash: String::new(), active: true, location: None }),
+        ]);
+        let changes = plan_changes(existing, vec![place("a1", "hash-a", 0.0)], false);
+        assert_eq!((ids(&changes.to_index), changes.updated), (vec!["a1"], 1));
+    }
+
+    #[test]
+    fn new_and_moved_places_are_relocated() {
+        let existing = HashMap::from([
+            ("a1".to_string(), document("hash-a", true, 0.0)),
+            ("b2".to_string(), document("hash-b", true, 0.0)),
+            ("c3".to_string(), document("hash-c", true, 0.0)),
+            ("d4".to_string(), IndexedDocument { content_hash: String::new(), active: true, location: None }),
+        ]);
+        // b2 changed but is at the same location, c3 moved by 5 meters
+        let places = vec![
+            place("a1", "hash-a", 0.0),
+            place("b2", "hash-b2", 0.5),
+            place("c3", "hash-c2", 5.0),
+            place("d4", "hash-d", 0.0),
+            place("e5", "hash-e", 0.0),
+        ];
+        let changes = plan_changes(existing, places, false);
+        assert_eq!(sorted(&changes.relocated), ["c3", "d4", "e5"]);
+    }
+
+    #[test]
+    fn everything_is_added_to_empty_indices() {
+        let places = vec![place("a1", "hash-a", 0.0), place("b2", "hash-b", 0.0)];
+        assert_eq!(plan_changes(HashMap::new(), places.clone(), false).summary(), Changes::all(places).summary());
+    }
+
+    #[test]
+    fn places_that_disappeared_are_deactivated_with_soft_deletes() {
+        let existing = HashMap::from([
+            ("a1".to_string(), document("hash-a", true, 0.0)),
+            ("b2".to_string(), document("hash-b", true, 0.0)),
+            ("c3".to_string(), document("hash-c", false, 0.0)),
+        ]);
+        let changes = plan_changes(existing, vec![place("a1", "hash-a", 0.0)], true);
+
+        assert!(changes.to_index.is_empty());
+        assert!(changes.to_delete.is_empty());
+        assert_eq!(changes.to_deactivate, ["b2"]);
+        // c3 was already inactive
+        assert_eq!((changes.unchanged, changes.inactive, changes.reactivated), (1, 1, 0));
+        assert_eq!(changes.expected_count(), 3);
+        assert_eq!(changes.summary(), "0 added, 0 updated, 0 deleted, 1 unchanged, 1 deactivated, 0 reactivated");
+    }
+
+    #[test]
+    fn inactive_places_that_came_back_are_reactivated() {
+        let existing = HashMap::from([
+            ("a1".to_string(), document("hash-a", false, 0.0)),
+            ("b2".to_string(), document("hash-b", true, 0.0)),
+        ]);
+        // Even with the same contents
+        let places = vec![place("a1", "hash-a", 0.0), place("b2", "hash-b", 0.0)];
+        let changes = plan_changes(existing, places, true);
+
+        assert_eq!(ids(&changes.to_index), ["a1"]);
+        assert!(changes.to_index[0].active);
+        assert!(changes.to_index[0].removed_at.is_none());
+        assert_eq!(sorted(&changes.relocated), ["a1"]);
+        assert_eq!((changes.updated, changes.reactivated, changes.unchanged, changes.inactive), (1, 1, 1, 0));
+        assert_eq!(changes.expected_count(), 2);
+        assert_eq!(changes.summary(), "0 added, 1 updated, 0 deleted, 1 unchanged, 0 deactivated, 1 reactivated");
+    }
+
+    #[test]
+    fn inactive_places_are_deleted_without_soft_deletes() {
+        let existing = HashMap::from([
+            ("a1".to_string(), document("hash-a", false, 0.0)),
+            ("b2".to_string(), document("hash-b", true, 0.0)),
+        ]);
+        let changes = plan_changes(existing, Vec::new(), false);
+        assert_eq!((changes.to_delete, changes.inactive), (vec!["a1".to_string(), "b2".to_string()], 0));
+    }
+}
@@ -0,0 +1,1444 @@
+use crate::error::IngestError;
+use crate::events::{self, Event};
+use crate::interrupt;
+use crate::progress::{self, Progress, Unit};
+use crate::retry::{es_failure, es_response, is_transient_status, retry, RetryPolicy};
+use crate::sink::Sink;
+use crate::summary;
+use crate::telemetry::{self, timed};
+use crate::transform::IndexedPlace;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use elasticsearch::http::headers::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use elasticsearch::http::request::{Body, NdBody};
+use elasticsearch::http::response::Response;
+use elasticsearch::indices::{
+    IndicesCloneParts, IndicesCreateParts, IndicesDeleteParts, IndicesExistsParts, IndicesGetAliasParts,
+    IndicesGetMappingParts, IndicesGetParts, IndicesPutMappingParts, IndicesPutSettingsParts, IndicesRefreshParts,
+};
+use elasticsearch::nodes::NodesInfoParts;
+use elasticsearch::http::{Method, StatusCode};
+use elasticsearch::params::Conflicts;
+use elasticsearch::{
+    BulkOperation, ClearScrollParts, CountParts, DeleteByQueryParts, Elasticsearch, ScrollParts, SearchParts,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+use tracing::{debug, info, info_span, instrument, warn, Instrument};
+
+// Bulk responses have an item for each operation, keyed by the operation type ("index" here). Elasticsearch
+// 7.x and 8.x, and OpenSearch, have the same structure, with more fields that we ignore.
+//   {
+//     "took": 30,
+//     "errors": true,
+//     "items": [
+//       { "index": { "_id": "ef89fd...", "status": 201 } },
+//       { "index": { "_id": "0a12bc...", "status": 400, "error": { "type": "mapper_parsing_exception", "reason": "..." } } }
+//     ]
+//   }
+
+#[derive(Debug, Deserialize)]
+pub struct BulkResponse {
+    // Milliseconds spent processing the request
+    pub took: u64,
+    pub errors: bool,
+    pub items: Vec<HashMap<String, BulkItem>>,
+}
+
+// Longest part of a response body included in error messages
+const MAX_BODY_IN_ERRORS: usize = 2000;
+
+// Parse the body of a response to a bulk request with `operations` operations. A body that doesn't have the
+// expected structure, or not an item per operation, is an error that includes the body: it's most likely a
+// response from an unsupported version or a proxy, that we must not mistake for a success.
+pub fn parse_bulk_response(body: &str, operations: usize) -> anyhow::Result<BulkResponse> {
+    let invalid = |reason: String| {
+        let mut end = body.len().min(MAX_BODY_IN_ERRORS);
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        let truncated = if end < body.len() { format!(" ({} more bytes)", body.len() - end) } else { String::new() };
+        anyhow!("Unexpected bulk response ({}), response body: {}{}", reason, &body[..end], truncated)
+    };
+
+    let response: BulkResponse = serde_json::from_str(body).map_err(|err| invalid(err.to_string()))?;
+    if response.items.len() != operations {
+        return Err(invalid(format!("{} items for {} operations", response.items.len(), operations)));
+    }
+    if let Some(item) = response.items.iter().find(|item| item.len() != 1) {
+        return Err(invalid(format!("an item has {} results instead of one", item.len())));
+    }
+
+    let failed = response.items.iter().flat_map(HashMap::values).filter(|result| result.status >= 300).count();
+    debug!("Bulk request took {}ms: {} items, {} failed", response.took, operations, failed);
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkItem {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub status: u16,
+    pub error: Option<BulkItemError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkItemError {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    // Not always present in OpenSearch responses
+    #[serde(default)]
+    pub reason: String,
+}
+
+// A document that Elasticsearch refused to index
+#[derive(Debug)]
+pub struct FailedDocument {
+    pub record_id: String,
+    pub status: u16,
+    pub error: BulkItemError,
+    // Number of times we tried to index it
+    pub attempts: u32,
+}
+
+impl FailedDocument {
+    // Rejections because the cluster is overloaded may succeed later, while errors such as a
+    // mapper_parsing_exception will fail the same way again
+    pub fn is_transient(&self) -> bool {
+        self.error.error_type == "es_rejected_execution_exception"
+            || StatusCode::from_u16(self.status).is_ok_and(is_transient_status)
+    }
+}
+
+// Outcome of sending a batch to a sink
+#[derive(Debug, Default)]
+pub struct BatchOutcome {
+    pub failures: Vec<FailedDocument>,
+    // Documents that were stored after being retried
+    pub retried: usize,
+}
+
+// How documents are sent to a sink
+#[derive(Debug, Clone, Copy)]
+pub struct BulkOptions {
+    pub bulk_size: usize,
+    // Maximum size of the body of a bulk request, before compression
+    pub bulk_bytes: usize,
+    // Maximum number of batches in flight
+    pub concurrency: usize,
+}
+
+// Groups documents in batches of up to `bulk_size` documents and `bulk_bytes` bytes of bulk request body, as
+// written by `sink`. A document larger than `bulk_bytes` is sent alone.
+pub struct Batcher<'a> {
+    sink: &'a dyn Sink,
+    bulk_size: usize,
+    bulk_bytes: usize,
+    batch: Vec<IndexedPlace>,
+    bytes: usize,
+}
+
+impl<'a> Batcher<'a> {
+    pub fn new(options: &BulkOptions, sink: &'a dyn Sink) -> Batcher<'a> {
+        Batcher { sink, bulk_size: options.bulk_size, bulk_bytes: options.bulk_bytes, batch: Vec::new(), bytes: 0 }
+    }
+
+    // Add `place`, and return the current batch if `place` doesn't fit in it
+    pub fn push(&mut self, place: IndexedPlace) -> Option<Vec<IndexedPlace>> {
+        let size = self.sink.operation_len(&place);
+        let full = if self.batch.len() >= self.bulk_size || self.bytes + size > self.bulk_bytes {
+            self.take()
+        } else {
+            None
+        };
+        if size > self.bulk_bytes {
+            warn!(
+                "Document {} is {} bytes, more than the {} bytes of a bulk request: it's sent alone",
+                place.record_id, size, self.bulk_bytes
+            );
+        }
+        self.bytes += size;
+        self.batch.push(place);
+        full
+    }
+
+    // The documents that weren't returned yet
+    pub fn take(&mut self) -> Option<Vec<IndexedPlace>> {
+        self.bytes = 0;
+        Some(std::mem::take(&mut self.batch)).filter(|batch| !batch.is_empty())
+    }
+}
+
+// The number of batches `places` are sent in to Elasticsearch
+pub fn count_batches(places: &[IndexedPlace], options: &BulkOptions) -> usize {
+    let (mut batches, mut documents, mut bytes) = (0, 0, 0);
+    for place in places {
+        let size = operation_len(&index_action(None, &place.record_id), place);
+        if documents == 0 || documents >= options.bulk_size || bytes + size > options.bulk_bytes {
+            batches += 1;
+            documents = 0;
+            bytes = 0;
+        }
+        documents += 1;
+        bytes += size;
+    }
+    batches
+}
+
+// The action line of a document in a bulk request body. Requests to an index's _bulk endpoint don't need the
+// index name.
+pub fn index_action(index: Option<&str>, id: &str) -> JsonValue {
+    match index {
+        Some(index) => json!({ "index": { "_index": index, "_id": id } }),
+        None => json!({ "index": { "_id": id } }),
+    }
+}
+
+// The length of the `action` and source lines of `place` in a bulk request body, with their newlines
+pub fn operation_len(action: &JsonValue, place: &IndexedPlace) -> usize {
+    let len = |value: serde_json::Result<Vec<u8>>| value.map(|line| line.len()).unwrap_or_default();
+    len(serde_json::to_vec(action)) + len(serde_json::to_vec(place)) + 2
+}
+
+// Outcome of storing documents in an index
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    pub batches: usize,
+    // All stored documents, including those that were retried
+    pub indexed: usize,
+    pub retried: usize,
+    pub failures: Vec<FailedDocument>,
+}
+
+impl IngestStats {
+    pub fn failed(&self) -> usize {
+        self.failures.len()
+    }
+
+    pub fn first_try(&self) -> usize {
+        self.indexed - self.retried
+    }
+
+    // Render the failed documents, one per line, after a summary of successes vs failures
+    pub fn failure_report(&self) -> String {
+        let mut report = format!(
+            "Failed to store data: {} documents indexed on first try, {} after retries, {} failed",
+            self.first_try(), self.retried, self.failed()
+        );
+        for failure in &self.failures {
+            let attempts = match failure.attempts {
+                0 | 1 => String::new(),
+                attempts => format!(" after {} attempts", attempts),
+            };
+            report.push_str(&format!(
+                "\n  record {} failed{}: {}: {}",
+                failure.record_id, attempts, failure.error.error_type, failure.error.reason
+            ));
+        }
+        report
+    }
+}
+
+// Read an index definition from a JSON file, with the same structure as `index_definition()`
+pub fn load_index_definition(path: &Path) -> anyhow::Result<JsonValue> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read the index definition {}", path.display()))?;
+    let definition: JsonValue = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid index definition {}", path.display()))?;
+    if !definition.is_object() {
+        return Err(anyhow!("Invalid index definition {}: expecting a JSON object", path.display()));
+    }
+
+    if definition["mappings"]["properties"]["location"]["type"] != "geo_point" {
+        warn!(
+            "The index definition {} doesn't map location as a geo_point: searches by distance will not work",
+            path.display()
+        );
+    }
+    Ok(definition)
+}
+
+// A single shard is plenty for a few hundred documents, and replicas can't be allocated on the single-node
+// clusters used in development, whose health would stay yellow
+pub const DEFAULT_SHARDS: u32 = 1;
+pub const DEFAULT_REPLICAS: u32 = 0;
+
+// Set the number of shards and replicas in the settings of `definition`. `shards` and `replicas` replace the
+// values of the definition, whichever way they're written there, and the defaults are used for those that are in
+// neither.
+pub fn set_shards(definition: &mut JsonValue, shards: Option<u32>, replicas: Option<u32>) -> anyhow::Result<()> {
+    let definition = definition.as_object_mut().ok_or_else(|| anyhow!("The index definition isn't an object"))?;
+    let settings = definition.entry("settings").or_insert_with(|| json!({}));
+    if settings.is_null() {
+        *settings = json!({});
+    }
+    let settings = settings.as_object_mut().ok_or_else(|| anyhow!("The index settings aren't an object"))?;
+    set_setting(settings, "number_of_shards", shards, DEFAULT_SHARDS);
+    set_setting(settings, "number_of_replicas", replicas, DEFAULT_REPLICAS);
+    Ok(())
+}
+
+// Elasticsearch accepts "name", "index.name" and { "index": { "name" } }, and rejects settings that are written
+// in more than one way
+fn set_setting(settings: &mut serde_json::Map<String, JsonValue>, name: &str, value: Option<u32>, default: u32) {
+    let dotted = format!("index.{}", name);
+    let in_definition = settings.contains_key(name) || settings.contains_key(&dotted)
+        || settings.get("index").and_then(JsonValue::as_object).is_some_and(|index| index.contains_key(name));
+    match value {
+        Some(value) => {
+            settings.remove(&dotted);
+            if let Some(index) = settings.get_mut("index").and_then(JsonValue::as_object_mut) {
+                index.remove(name);
+            }
+            settings.insert(name.to_string(), value.into());
+        }
+        None if !in_definition => {
+            settings.insert(name.to_string(), default.into());
+        }
+        None => {}
+    }
+}
+
+// How the `folded` sub-fields ignore accents and case: with the ICU plugin, which also folds non-latin
+// characters and ligatures, or with the asciifolding filter that is always available
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Folding {
+    Icu,
+    Ascii,
+}
+
+impl Folding {
+    fn filters(self) -> JsonValue {
+        match self {
+            Folding::Icu => json!(["icu_folding"]),
+            Folding::Ascii => json!(["lowercase", "asciifolding"]),
+        }
+    }
+}
+
+impl std::fmt::Display for Folding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Folding::Icu => "icu_folding",
+            Folding::Ascii => "asciifolding",
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NodesPlugins {
+    nodes: HashMap<String, NodePlugins>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodePlugins {
+    plugins: Vec<Plugin>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Plugin {
+    name: String,
+}
+
+// ICU folding if all the nodes have the analysis-icu plugin, since shards can be allocated to any of them
+pub async fn detect_folding(es_client: &Elasticsearch) -> anyhow::Result<Folding> {
+    let response = es_client.nodes()
+        .info(NodesInfoParts::Metric(&["plugins"]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<NodesPlugins>().await?;
+    let has_icu = |node: &NodePlugins| node.plugins.iter().any(|plugin| plugin.name == "analysis-icu");
+    if !response.nodes.is_empty() && response.nodes.values().all(has_icu) {
+        Ok(Folding::Icu)
+    } else {
+        Ok(Folding::Ascii)
+    }
+}
+
+// The index has a geo_point for location, a full-text street with an exact sub-field for display and
+// aggregations, a city that matches case-insensitively, sub-fields of both that ignore accents, and run metadata.
+// Other properties use the defaults.
+pub fn index_definition(folding: Folding) -> JsonValue {
+    json!({
+        "settings": {
+            "number_of_shards": DEFAULT_SHARDS,
+            "number_of_replicas": DEFAULT_REPLICAS,
+            "analysis": {
+                "normalizer": {
+                    "lowercase": { "type": "custom", "filter": ["lowercase"] }
+                },
+                // So that "jaures" matches "Jaurès"
+                "analyzer": {
+                    "suggest": { "type": "custom", "tokenizer": "standard", "filter": ["lowercase", "asciifolding"] },
+                    "folded": { "type": "custom", "tokenizer": "standard", "filter": folding.filters() }
+                }
+            }
+        },
+        "mappings": {
+            "properties": {
+                "location": { "type": "geo_point" },
+                "coverage": { "type": "geo_shape" },
+                "geohash": { "type": "keyword" },
+                "plus_code": { "type": "keyword" },
+                "distance_to_center_m": { "type": "float" },
+                "sector": { "type": "keyword" },
+                "campaign_year": { "type": "integer" },
+                "street": {
+                    "type": "text",
+                    "analyzer": "french",
+                    "fields": {
+                        "keyword": { "type": "keyword" },
+                        "folded": { "type": "text", "analyzer": "folded" }
+                    }
+                },
+                "street_raw": { "type": "keyword" },
+                "house_number": { "type": "keyword" },
+                "street_type": { "type": "keyword" },
+                "street_name": { "type": "keyword" },
+                "address_note": { "type": "keyword" },
+                "street_suggest": { "type": "completion", "analyzer": "suggest" },
+                "city": {
+                    "type": "keyword",
+                    "normalizer": "lowercase",
+                    "fields": {
+                        "folded": { "type": "text", "analyzer": "folded" }
+                    }
+                },
+                "city_raw": { "type": "keyword" },
+                "insee_code": { "type": "keyword" },
+                "postal_code": { "type": "keyword" },
+                "indexed_at": { "type": "date" },
+                "run_id": { "type": "keyword" },
+                "active": { "type": "boolean" },
+                "removed_at": { "type": "date" },
+                "source_url": { "type": "keyword" },
+                "content_hash": { "type": "keyword" },
+                // Kept in the source, but not indexed
+                "raw": { "type": "object", "enabled": false }
+            }
+        }
+    })
+}
+
+#[instrument(name = "create", skip(es_client, retry_policy), fields(duration_ms))]
+pub async fn create_index(
+    es_client: &Elasticsearch,
+    index: &str,
+    definition: &JsonValue,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<()> {
+    info!("Setting up index {}", index);
+    retry(retry_policy, "create index", || async move {
+        let response = es_client.indices().create(IndicesCreateParts::Index(index))
+            .body(definition)
+            .send().await
+            .map_err(es_failure)?;
+        es_response(response)
+    }).await?;
+    Ok(())
+}
+
+// Also true if `index` is an alias. Other errors than a 404, such as missing privileges, don't tell whether it
+// exists.
+pub async fn index_exists(es_client: &Elasticsearch, index: &str) -> anyhow::Result<bool> {
+    let response = es_client.indices()
+        .exists(IndicesExistsParts::Index(&[index]))
+        .send().await?;
+    match response.status_code() {
+        StatusCode::NOT_FOUND => Ok(false),
+        status if status.is_success() => Ok(true),
+        status => Err(anyhow!("Cannot check if index {} exists: Elasticsearch returned status {}", index, status)),
+    }
+}
+
+// Number of documents in `index`, or None if it doesn't exist
+pub async fn count_documents(es_client: &Elasticsearch, index: &str) -> anyhow::Result<Option<usize>> {
+    count_matching(es_client, index, &json!({ "match_all": {} })).await
+}
+
+// Number of documents of `index` that match `query`, or None if the index doesn't exist
+pub async fn count_matching(
+    es_client: &Elasticsearch,
+    index: &str,
+    query: &JsonValue
+) -> anyhow::Result<Option<usize>> {
+    let response = es_client
+        .count(CountParts::Index(&[index]))
+        .body(json!({ "query": query }))
+        .send().await?;
+    if response.status_code() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status_code()?.json::<JsonValue>().await?;
+    let count = response["count"].as_u64()
+        .ok_or_else(|| anyhow!("Unexpected count response: {}", response))?;
+    Ok(Some(count as usize))
+}
+
+// Delete `index`, ignoring the error if it doesn't exist. Other errors, e.g. if it's an alias or writes are
+// forbidden, mean that it wasn't deleted.
+#[instrument(name = "delete", skip(es_client), fields(duration_ms))]
+pub async fn delete_index(es_client: &Elasticsearch, index: &str) -> anyhow::Result<()> {
+    let response = timed(es_client.indices()
+        .delete(IndicesDeleteParts::Index(&[index]))
+        .send()).await?;
+    if response.status_code() != StatusCode::NOT_FOUND {
+        response.error_for_status_code()?;
+    }
+    Ok(())
+}
+
+// Reject writes to `index`, which is needed to clone it
+pub async fn block_writes(es_client: &Elasticsearch, index: &str) -> anyhow::Result<()> {
+    es_client.indices()
+        .put_settings(IndicesPutSettingsParts::Index(&[index]))
+        .body(json!({ "index.blocks.write": true }))
+        .send().await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+pub async fn unblock_writes(es_client: &Elasticsearch, index: &str) -> anyhow::Result<()> {
+    es_client.indices()
+        .put_settings(IndicesPutSettingsParts::Index(&[index]))
+        .body(json!({ "index.blocks.write": null }))
+        .send().await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+// Copy the write-blocked `source` to a new index `target` with the clone API, which hard-links the segments of
+// `source` instead of indexing the documents again
+#[instrument(name = "clone", skip(es_client), fields(duration_ms))]
+pub async fn clone_index(es_client: &Elasticsearch, source: &str, target: &str) -> anyhow::Result<()> {
+    info!("Copying index {} to {}", source, target);
+    // The target gets the settings of the source, including the write block
+    timed(es_client.indices()
+        .clone(IndicesCloneParts::IndexTarget(source, target))
+        .body(json!({ "settings": { "index.blocks.write": null } }))
+        .send()).await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+// The indices that `name` refers to: the index itself, or the indices behind the alias along with the
+// previous timestamped indices kept by alias mode. Empty if none of them exist.
+pub async fn indices_for(es_client: &Elasticsearch, name: &str) -> anyhow::Result<Vec<String>> {
+    let prefix = format!("{}-", name);
+    let pattern = format!("{}*", prefix);
+    let response = es_client.indices()
+        .get(IndicesGetParts::Index(&[name, &pattern]))
+        .ignore_unavailable(true)
+        .send().await?
+        .error_for_status_code()?;
+
+    let indices = response.json::<JsonValue>().await?;
+    let mut names: Vec<String> = indices.as_object()
+        .map(|indices| indices.keys()
+            .filter(|index| !index.starts_with(&prefix) || is_timestamped_index(name, index))
+            .cloned()
+            .collect())
+        .unwrap_or_default();
+    names.sort();
+
+    Ok(names)
+}
+
+// Delete all of `indices`
+pub async fn delete_indices(es_client: &Elasticsearch, indices: &[String]) -> anyhow::Result<()> {
+    for index in indices {
+        info!("Deleting index {}", index);
+        es_client.indices()
+            .delete(IndicesDeleteParts::Index(&[index]))
+            .send().await?
+            .error_for_status_code()?;
+    }
+    Ok(())
+}
+
+// Store the `places` of `dataset` in `sink`, in batches of up to `options.bulk_size` documents and
+// `options.bulk_bytes` bytes, with up to `options.concurrency` batches in flight. Documents rejected by the sink
+// are reported in the result's `failures`. Batches can complete in any order, since document ids don't depend on
+// it. When interrupted, no new batch is sent and the batches in flight are waited for, to report how many
+// documents were stored.
+pub async fn index_places(
+    sink: &dyn Sink,
+    dataset: &str,
+    mut places: impl Iterator<Item = IndexedPlace>,
+    options: &BulkOptions
+) -> anyhow::Result<IngestStats> {
+    let total = places.size_hint().1.map(|total| total as u64);
+    let mut batcher = Batcher::new(options, sink);
+    let batches = std::iter::from_fn(|| {
+        if interrupt::is_interrupted() {
+            return None;
+        }
+        places.by_ref().find_map(|place| batcher.push(place)).or_else(|| batcher.take())
+    });
+    store_batches(sink, dataset, stream::iter(batches), options.concurrency, total).await
+}
+
+// Store the `batches` of `dataset` in `sink` as they come, with up to `concurrency` of them in flight. `total` is the
+// number of documents, if it's known in advance.
+#[instrument(name = "bulk", skip_all)]
+pub async fn store_batches(
+    sink: &dyn Sink,
+    dataset: &str,
+    batches: impl Stream<Item = Vec<IndexedPlace>>,
+    concurrency: usize,
+    total: Option<u64>
+) -> anyhow::Result<IngestStats> {
+
+    info!("Storing data");
+    sink.prepare().await?;
+    let mut stats = IngestStats::default();
+    let progress = Progress::new("Storing", Unit::Documents, total);
+
+    let results = batches.enumerate()
+        .map(|(i, batch)| {
+            let span = info_span!("batch", batch = i + 1, docs = batch.len(), duration_ms = tracing::field::Empty);
+            let sent = batch.len();
+            async move { sink.send(batch).await.map(|outcome| (sent, outcome)) }.instrument(span)
+        })
+        .buffer_unordered(concurrency);
+    futures::pin_mut!(results);
+
+    // Returning on the first error drops `results`, which cancels the requests that are still in flight
+    while let Some(result) = results.next().await {
+        let (sent, outcome) = result?;
+        stats.batches += 1;
+        stats.indexed += sent - outcome.failures.len();
+        telemetry::add_indexed(sent - outcome.failures.len());
+        telemetry::add_failed(outcome.failures.len());
+        stats.retried += outcome.retried;
+        events::emit(Event::BatchIndexed {
+            dataset: dataset.to_string(),
+            batch: stats.batches,
+            docs: sent - outcome.failures.len(),
+            failed: outcome.failures.len(),
+        });
+        stats.failures.extend(outcome.failures);
+        progress.inc(sent as u64);
+        progress.set_message(&format!("({} batches)", stats.batches));
+    }
+    sink.finish().await?;
+
+    let duration = progress.finish();
+    info!(
+        "Sent {} batches, stored {} documents in {:.1}s ({:.0} documents/s)",
+        stats.batches, stats.indexed, duration.as_secs_f64(), progress::rate(stats.indexed, duration)
+    );
+    if interrupt::is_interrupted() {
+        return Err(IngestError::Interrupted { indexed: stats.indexed }.into());
+    }
+    if stats.retried > 0 || stats.failed() > 0 {
+        info!(
+            "{} documents stored on first try, {} after retries, {} failed",
+            stats.first_try(), stats.retried, stats.failed()
+        );
+    }
+
+    Ok(stats)
+}
+
+// Sends documents to an Elasticsearch index with bulk requests
+pub struct ElasticsearchSink<'a> {
+    pub es_client: &'a Elasticsearch,
+    pub index: &'a str,
+    pub retry_policy: RetryPolicy,
+    // Bulk requests return once their documents are visible to searches
+    pub wait_for_refresh: bool,
+    // Gzip the bulk request bodies
+    pub compress: bool,
+}
+
+// The body of a bulk request. It's serialized, and compressed, once even if the request is retried.
+pub struct BulkBody {
+    bytes: Bytes,
+    uncompressed_len: usize,
+    compressed: bool,
+}
+
+impl BulkBody {
+    pub fn new<T: Body>(operations: Vec<T>, compress: bool) -> anyhow::Result<BulkBody> {
+        let mut ndjson = BytesMut::new();
+        NdBody::new(operations).write(&mut ndjson)?;
+        let uncompressed_len = ndjson.len();
+        if !compress {
+            return Ok(BulkBody { bytes: ndjson.freeze(), uncompressed_len, compressed: false });
+        }
+
+        // The fastest level already divides the size by about 10: bulk bodies are very repetitive JSON
+        let mut encoder = GzEncoder::new(Vec::with_capacity(uncompressed_len / 4), Compression::fast());
+        encoder.write_all(&ndjson)?;
+        let bytes = encoder.finish()?;
+        debug!("Compressed bulk request from {} to {} bytes", uncompressed_len, bytes.len());
+        Ok(BulkBody { bytes: bytes.into(), uncompressed_len, compressed: true })
+    }
+
+    // The bulk request builder of the client can't send a compressed body (it adds a trailing newline)
+    pub async fn send(
+        &self,
+        es_client: &Elasticsearch,
+        index: &str,
+        wait_for_refresh: bool
+    ) -> Result<Response, elasticsearch::Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+        if self.compressed {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+        let query: &[(&str, &str)] = if wait_for_refresh { &[("refresh", "wait_for")] } else { &[] };
+
+        summary::add_bulk_bytes(self.uncompressed_len as u64, self.bytes.len() as u64, self.compressed);
+        let path = format!("/{}/_bulk", index);
+        let start = Instant::now();
+        let response = es_client.send(Method::Post, &path, headers, Some(query), Some(self.bytes.clone()), None).await;
+        telemetry::record_bulk_duration(start.elapsed());
+        response
+    }
+}
+
+impl ElasticsearchSink<'_> {
+    async fn send_bulk(&self, batch: &[&IndexedPlace]) -> anyhow::Result<Vec<FailedDocument>> {
+        // create a bulk indexing operation for each place, using the record id as the document id
+        // so that indexing the same data twice doesn't create duplicates
+        let operations = batch.iter()
+            .map(|place| BulkOperation::from(BulkOperation::index(place).id(&place.record_id)))
+            .collect();
+        let body = BulkBody::new(operations, self.compress)?;
+
+        let (es_client, index, body) = (self.es_client, self.index, &body);
+        let wait_for_refresh = self.wait_for_refresh;
+        let response = retry(&self.retry_policy, "send bulk request", || async move {
+            let response = body.send(es_client, index, wait_for_refresh).await.map_err(es_failure)?;
+            es_response(response)
+        }).await?;
+
+        // Make sure we don't have bulk ingestion errors
+        let bulk_response = parse_bulk_response(&response.text().await?, batch.len())?;
+        Ok(bulk_failures(bulk_response))
+    }
+}
+
+#[async_trait]
+impl Sink for ElasticsearchSink<'_> {
+    // Documents rejected with a transient error are sent again, with the same backoff as failed requests,
+    // until they're stored or `retry_policy.max_attempts` is reached. Other rejected documents aren't retried.
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        let mut pending: Vec<&IndexedPlace> = batch.iter().collect();
+        let mut outcome = BatchOutcome::default();
+        let mut attempt = 1;
+
+        loop {
+            let failures = self.send_bulk(&pending).await?;
+            if attempt > 1 {
+                outcome.retried += pending.len() - failures.len();
+            }
+
+            let (transient, permanent): (Vec<_>, Vec<_>) = failures.into_iter()
+                .map(|failure| FailedDocument { attempts: attempt, ..failure })
+                .partition(|failure| failure.is_transient());
+            outcome.failures.extend(permanent);
+
+            if transient.is_empty() || attempt >= self.retry_policy.max_attempts {
+                outcome.failures.extend(transient);
+                return Ok(outcome);
+            }
+
+            let delay = self.retry_policy.delay(attempt);
+            warn!(
+                "{} documents were rejected ({}), retrying them in {:.1}s",
+                transient.len(), transient[0].error.error_type, delay.as_secs_f64()
+            );
+            let ids: HashSet<&str> = transient.iter().map(|failure| failure.record_id.as_str()).collect();
+            pending.retain(|place| ids.contains(place.record_id.as_str()));
+            tokio::time::delay_for(delay).await;
+            summary::add_retry();
+            attempt += 1;
+        }
+    }
+}
+
+// Delete the documents with these `ids`, `bulk_size` at a time. Documents that don't exist are ignored.
+#[instrument(name = "delete_documents", skip_all, fields(count = ids.len()))]
+pub async fn delete_documents(
+    es_client: &Elasticsearch,
+    index: &str,
+    ids: &[String],
+    bulk_size: usize,
+    compress: bool,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<Vec<FailedDocument>> {
+    bulk_by_id(es_client, index, ids, bulk_size, compress, retry_policy, |id| BulkOperation::delete(id).into()).await
+}
+
+// Mark the documents with these `ids` as no longer in the source data since `removed_at`, `bulk_size` at a
+// time. Documents that don't exist are ignored.
+#[instrument(name = "deactivate_documents", skip_all, fields(count = ids.len()))]
+pub async fn deactivate_documents(
+    es_client: &Elasticsearch,
+    index: &str,
+    ids: &[String],
+    removed_at: DateTime<Utc>,
+    bulk_size: usize,
+    compress: bool,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<Vec<FailedDocument>> {
+    let update = json!({ "doc": { "active": false, "removed_at": removed_at } });
+    bulk_by_id(es_client, index, ids, bulk_size, compress, retry_policy, |id| {
+        BulkOperation::update(id, update.clone()).into()
+    }).await
+}
+
+// Send the `operation` of each id, ignoring those on documents that don't exist
+async fn bulk_by_id(
+    es_client: &Elasticsearch,
+    index: &str,
+    ids: &[String],
+    bulk_size: usize,
+    compress: bool,
+    retry_policy: &RetryPolicy,
+    operation: impl Fn(&str) -> BulkOperation<JsonValue>
+) -> anyhow::Result<Vec<FailedDocument>> {
+    let mut failures = Vec::new();
+    for batch in ids.chunks(bulk_size) {
+        let operations = batch.iter().map(|id| operation(id)).collect();
+        let body = &BulkBody::new(operations, compress)?;
+        let response = retry(retry_policy, "send bulk request", || async move {
+            let response = body.send(es_client, index, false).await.map_err(es_failure)?;
+            es_response(response)
+        }).await?;
+
+        let mut bulk_response = parse_bulk_response(&response.text().await?, batch.len())?;
+        for item in &mut bulk_response.items {
+            item.retain(|_, result| result.status != 404);
+        }
+        failures.extend(bulk_failures(bulk_response));
+    }
+    Ok(failures)
+}
+
+// Index `documents`, with their id and source, `bulk_size` at a time. Documents that already exist are
+// overwritten.
+#[instrument(name = "index_documents", skip_all, fields(count = documents.len()))]
+pub async fn index_documents(
+    es_client: &Elasticsearch,
+    index: &str,
+    documents: &[(String, JsonValue)],
+    bulk_size: usize,
+    compress: bool,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<IngestStats> {
+    let mut stats = IngestStats::default();
+    let progress = Progress::new("Restoring", Unit::Documents, Some(documents.len() as u64));
+    for batch in documents.chunks(bulk_size) {
+        let operations = batch.iter()
+            .map(|(id, source)| BulkOperation::from(BulkOperation::index(source).id(id)))
+            .collect();
+        let body = &BulkBody::new(operations, compress)?;
+        let response = retry(retry_policy, "send bulk request", || async move {
+            let response = body.send(es_client, index, false).await.map_err(es_failure)?;
+            es_response(response)
+        }).await?;
+
+        let failures = bulk_failures(parse_bulk_response(&response.text().await?, batch.len())?);
+        stats.batches += 1;
+        stats.indexed += batch.len() - failures.len();
+        stats.failures.extend(failures);
+        progress.inc(batch.len() as u64);
+    }
+    progress.finish();
+    Ok(stats)
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteByQueryResponse {
+    deleted: usize,
+    version_conflicts: usize,
+    #[serde(default)]
+    failures: Vec<JsonValue>,
+}
+
+// Delete the documents of `index` that match `query`, and return how many were deleted. Documents that are
+// updated while being deleted are version conflicts: they're looked for again, with the same backoff as failed
+// requests, until none are left or `retry_policy.max_attempts` is reached.
+#[instrument(name = "delete_by_query", skip(es_client, retry_policy))]
+pub async fn delete_by_query(
+    es_client: &Elasticsearch,
+    index: &str,
+    query: &JsonValue,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<usize> {
+    let mut deleted = 0;
+    let mut attempt = 1;
+    loop {
+        let response = retry(retry_policy, "delete documents", || async move {
+            let response = es_client
+                .delete_by_query(DeleteByQueryParts::Index(&[index]))
+                .conflicts(Conflicts::Proceed)
+                .refresh(true)
+                .body(json!({ "query": query }))
+                .send().await
+                .map_err(es_failure)?;
+            es_response(response)
+        }).await?;
+        let response = response.json::<DeleteByQueryResponse>().await?;
+        if let Some(failure) = response.failures.first() {
+            return Err(anyhow!(
+                "{} documents could not be deleted after deleting {}, first failure: {}",
+                response.failures.len(), deleted + response.deleted, failure
+            ));
+        }
+        deleted += response.deleted;
+
+        if response.version_conflicts == 0 {
+            return Ok(deleted);
+        }
+        if attempt >= retry_policy.max_attempts {
+            return Err(anyhow!(
+                "{} documents were still being updated after {} attempts, {} were deleted",
+                response.version_conflicts, attempt, deleted
+            ));
+        }
+        let delay = retry_policy.delay(attempt);
+        warn!(
+            "{} documents were updated while being deleted, trying again in {:.1}s",
+            response.version_conflicts, delay.as_secs_f64()
+        );
+        tokio::time::delay_for(delay).await;
+        summary::add_retry();
+        attempt += 1;
+    }
+}
+
+// Extract the failed documents from a bulk response. Items are checked even if `errors` is false, in case
+// it's not consistent with them.
+pub fn bulk_failures(response: BulkResponse) -> Vec<FailedDocument> {
+    response.items.into_iter()
+        .flat_map(|item| item.into_values())
+        .filter(|result| result.status >= 300)
+        .map(|result| {
+            let status = result.status;
+            FailedDocument {
+                record_id: result.id,
+                status,
+                error: result.error.unwrap_or_else(|| BulkItemError {
+                    error_type: "unknown_error".to_string(),
+                    reason: format!("status {}", status),
+                }),
+                attempts: 1,
+            }
+        })
+        .collect()
+}
+
+const SCROLL_KEEP_ALIVE: &str = "1m";
+const SCROLL_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ScrollResponse {
+    #[serde(rename = "_scroll_id")]
+    scroll_id: String,
+    hits: ScrollHits,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScrollHits {
+    hits: Vec<ScrollHit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScrollHit {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "_source", default)]
+    source: JsonValue,
+}
+
+// The `fields` of all documents in `index`, by document id
+pub async fn scroll_documents(
+    es_client: &Elasticsearch,
+    index: &str,
+    fields: &[&str]
+) -> anyhow::Result<HashMap<String, JsonValue>> {
+    let mut documents = HashMap::new();
+    scroll(es_client, index, json!(fields), |id, source| {
+        documents.insert(id, source);
+        Ok(())
+    }).await?;
+    Ok(documents)
+}
+
+// Call `each` with the id and `_source` (a source filter) of all documents in `index`, a page at a time, and
+// return the number of documents
+#[instrument(name = "scroll", skip(es_client, each))]
+pub async fn scroll(
+    es_client: &Elasticsearch,
+    index: &str,
+    source: JsonValue,
+    mut each: impl FnMut(String, JsonValue) -> anyhow::Result<()>
+) -> anyhow::Result<usize> {
+    let first_page = async {
+        es_client
+            .search(SearchParts::Index(&[index]))
+            .scroll(SCROLL_KEEP_ALIVE)
+            .body(json!({
+                "size": SCROLL_SIZE,
+                "_source": source,
+                "sort": ["_doc"]
+            }))
+            .send().await?
+            .error_for_status_code()?
+            .json::<ScrollResponse>().await
+    };
+    let mut response = first_page.await?;
+    let mut scroll_id = response.scroll_id.clone();
+
+    let mut count = 0;
+    let scrolled = async {
+        while !response.hits.hits.is_empty() {
+            count += response.hits.hits.len();
+            for hit in response.hits.hits {
+                each(hit.id, hit.source)?;
+            }
+            response = es_client
+                .scroll(ScrollParts::None)
+                .body(json!({ "scroll": SCROLL_KEEP_ALIVE, "scroll_id": scroll_id }))
+                .send().await?
+                .error_for_status_code()?
+                .json::<ScrollResponse>().await?;
+            scroll_id = response.scroll_id.clone();
+        }
+        Ok::<_, anyhow::Error>(())
+    }.await;
+
+    // Also when scrolling failed. The scroll context expires anyway, no need to fail if it can't be freed now.
+    let cleared = es_client
+        .clear_scroll(ClearScrollParts::None)
+        .body(json!({ "scroll_id": [scroll_id] }))
+        .send().await;
+    if let Err(err) = cleared {
+        debug!("Cannot clear scroll: {}", err);
+    }
+
+    scrolled?;
+    Ok(count)
+}
+
+// Make the documents that were sent visible to searches
+pub async fn refresh(es_client: &Elasticsearch, index: &str) -> anyhow::Result<()> {
+    es_client.indices()
+        .refresh(IndicesRefreshParts::Index(&[index]))
+        .send().await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+// Check that `index` contains the `expected` number of documents (or at least that number if `at_least` is true)
+#[instrument(name = "verify", skip(es_client))]
+pub async fn verify_count(
+    es_client: &Elasticsearch,
+    index: &str,
+    expected: usize,
+    at_least: bool
+) -> anyhow::Result<()> {
+
+    // Make sure all documents that were sent are visible to the count API
+    refresh(es_client, index).await?;
+
+    let response = es_client
+        .count(CountParts::Index(&[index]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+
+    let count = response["count"].as_u64()
+        .ok_or_else(|| anyhow!("Unexpected count response: {}", response))? as usize;
+
+    if count == expected || (at_least && count > expected) {
+        info!("Verified that index {} contains {} documents", index, count);
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Index {} contains {} documents, but {} documents were sent",
+            index, count, expected
+        ))
+    }
+}
+
+// Check that the mapping of `index` has the field types of `definition`, which may not be the case
+// if an index template or a previous version of this tool created it differently
+#[instrument(name = "verify_mapping", skip(es_client, definition))]
+pub async fn verify_mapping(es_client: &Elasticsearch, index: &str, definition: &JsonValue) -> anyhow::Result<()> {
+    let response = es_client.indices()
+        .get_mapping(IndicesGetMappingParts::Index(&[index]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+
+    let mut expected = Vec::new();
+    field_types(&definition["mappings"]["properties"], "", &mut expected);
+
+    // The response is keyed by concrete index name, which is different from `index` if it's an alias
+    let mut problems = Vec::new();
+    for (name, definition) in response.as_object().into_iter().flatten() {
+        let mut actual = Vec::new();
+        field_types(&definition["mappings"]["properties"], "", &mut actual);
+        let actual: HashMap<_, _> = actual.into_iter().collect();
+        let location = if name == index { String::new() } else { format!(" in {}", name) };
+
+        for (field, field_type) in &expected {
+            match actual.get(field) {
+                Some(actual_type) if actual_type == field_type => {}
+                Some(actual_type) => problems.push(format!(
+                    "field {}{} is {} instead of {}", field, location, actual_type, field_type
+                )),
+                None => problems.push(format!("field {}{} is missing", field, location)),
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow!("Unexpected mapping for index {}: {}", index, problems.join(", ")));
+    }
+
+    info!("Verified the mapping of index {}", index);
+    Ok(())
+}
+
+// Check that `index`, which other datasets also use, maps location as a geo_point, adding it to the mapping if
+// it's not mapped yet. Other fields are mapped dynamically if they aren't already.
+#[instrument(name = "location_mapping", skip(es_client))]
+pub async fn ensure_location_mapping(es_client: &Elasticsearch, index: &str) -> anyhow::Result<()> {
+    let response = es_client.indices()
+        .get_mapping(IndicesGetMappingParts::Index(&[index]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+
+    // Keyed by concrete index name, which is different from `index` if it's an alias
+    for (name, definition) in response.as_object().into_iter().flatten() {
+        match definition["mappings"]["properties"]["location"]["type"].as_str() {
+            Some("geo_point") => {}
+            Some(other) => return Err(anyhow!(
+                "Field location of index {} is a {}, not a geo_point: the places can't be stored in it", name, other
+            )),
+            None => {
+                info!("Adding location to the mapping of index {}", name);
+                es_client.indices()
+                    .put_mapping(IndicesPutMappingParts::Index(&[name]))
+                    .body(json!({ "properties": { "location": { "type": "geo_point" } } }))
+                    .send().await?
+                    .error_for_status_code()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Collect the (path, type) of all fields and sub-fields in mapping `properties`
+fn field_types(properties: &JsonValue, prefix: &str, types: &mut Vec<(String, String)>) {
+    for (name, field) in properties.as_object().into_iter().flatten() {
+        let path = format!("{}{}", prefix, name);
+        if let Some(field_type) = field["type"].as_str() {
+            types.push((path.clone(), field_type.to_string()));
+        }
+        field_types(&field["properties"], &format!("{}.", path), types);
+        field_types(&field["fields"], &format!("{}.", path), types);
+    }
+}
+
+// Atomically point the `alias` to `new_index`, removing it from the indices it pointed to previously
+#[instrument(name = "switch_alias", skip(es_client))]
+pub async fn switch_alias(es_client: &Elasticsearch, alias: &str, new_index: &str) -> anyhow::Result<()> {
+
+    info!("Switching alias {} to {}", alias, new_index);
+
+    let mut actions = vec![
+        json!({ "add": { "index": new_index, "alias": alias } })
+    ];
+
+    // Returns 404 if the alias doesn't exist yet
+    let response = es_client.indices()
+        .get_alias(IndicesGetAliasParts::Name(&[alias]))
+        .send().await?;
+
+    if response.status_code().is_success() {
+        let aliases = response.json::<JsonValue>().await?;
+        if let Some(indices) = aliases.as_object() {
+            for index in indices.keys() {
+                actions.push(json!({ "remove": { "index": index, "alias": alias } }));
+            }
+        }
+    } else if index_exists(es_client, alias).await? {
+        // A concrete index created by a non-alias run would conflict with the alias name: remove it in
+        // the same atomic operation.
+        actions.push(json!({ "remove_index": { "index": alias } }));
+    }
+
+    es_client.indices()
+        .update_aliases()
+        .body(json!({ "actions": actions }))
+        .send().await?
+        .error_for_status_code()?;
+
+    Ok(())
+}
+
+// Add `index` to the indices the `alias` points to, keeping the other ones
+#[instrument(name = "add_alias", skip(es_client))]
+pub async fn add_alias(es_client: &Elasticsearch, alias: &str, index: &str) -> anyhow::Result<()> {
+
+    info!("Adding index {} to alias {}", index, alias);
+
+    es_client.indices()
+        .update_aliases()
+        .body(json!({ "actions": [{ "add": { "index": index, "alias": alias } }] }))
+        .send().await?
+        .error_for_status_code()?;
+
+    Ok(())
+}
+
+// Delete timestamped indices created by previous alias runs, keeping the `keep` most recent ones
+#[instrument(name = "delete_old_indices", skip(es_client))]
+pub async fn delete_old_indices(es_client: &Elasticsearch, alias: &str, keep: usize) -> anyhow::Result<()> {
+
+    let pattern = format!("{}-*", alias);
+    let response = es_client.indices()
+        .get(IndicesGetParts::Index(&[&pattern]))
+        .send().await?
+        .error_for_status_code()?;
+
+    let indices = response.json::<JsonValue>().await?;
+
+    // Timestamps sort chronologically. Only consider names that exactly match the timestamped format
+    // so that we never delete an index that was created by some other means.
+    let mut names: Vec<&String> = indices.as_object()
+        .map(|indices| indices.keys().filter(|name| is_timestamped_index(alias, name)).collect())
+        .unwrap_or_default();
+    names.sort();
+
+    let obsolete = names.len().saturating_sub(keep);
+    for name in &names[..obsolete] {
+        info!("Deleting old index {}", name);
+        es_client.indices()
+            .delete(IndicesDeleteParts::Index(&[name]))
+            .send().await?
+            .error_for_status_code()?;
+    }
+
+    Ok(())
+}
+
+pub fn is_timestamped_index(alias: &str, name: &str) -> bool {
+    name.strip_prefix(alias)
+        .and_then(|suffix| suffix.strip_prefix('-'))
+        .and_then(|suffix| NaiveDateTime::parse_from_str(suffix, "%Y%m%d-%H%M%S").ok())
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_definitions_only_differ_by_their_folding() {
+        let icu = index_definition(Folding::Icu);
+        let mut ascii = index_definition(Folding::Ascii);
+        assert_eq!(icu["settings"]["analysis"]["analyzer"]["folded"]["filter"], json!(["icu_folding"]));
+        assert_eq!(ascii["settings"]["analysis"]["analyzer"]["folded"]["filter"], json!(["lowercase", "asciifolding"]));
+        for definition in [&icu, &ascii] {
+            assert_eq!(definition["mappings"]["properties"]["street"]["fields"]["folded"]["analyzer"], "folded");
+            assert_eq!(definition["mappings"]["properties"]["city"]["fields"]["folded"]["analyzer"], "folded");
+        }
+
+        ascii["settings"]["analysis"]["analyzer"]["folded"]["filter"] = json!(["icu_folding"]);
+        assert_eq!(ascii, icu);
+    }
+
+    #[test]
+    fn shards_and_replicas_default_to_a_single_copy() {
+        let mut definition = json!({ "mappings": { "properties": {} } });
+        set_shards(&mut definition, None, None).unwrap();
+        assert_eq!(definition["settings"], json!({ "number_of_shards": 1, "number_of_replicas": 0 }));
+
+        let mut definition = json!({ "settings": null });
+        set_shards(&mut definition, None, Some(2)).unwrap();
+        assert_eq!(definition["settings"], json!({ "number_of_shards": 1, "number_of_replicas": 2 }));
+    }
+
+    #[test]
+    fn settings_of_the_definition_are_kept_unless_given() {
+        let mut definition = json!({ "settings": {
+            "index.number_of_shards": 3,
+            "refresh_interval": "5s",
+            "analysis": { "analyzer": { "folded": { "tokenizer": "standard" } } }
+        } });
+        set_shards(&mut definition, None, None).unwrap();
+        assert_eq!(definition["settings"], json!({
+            "index.number_of_shards": 3,
+            "number_of_replicas": 0,
+            "refresh_interval": "5s",
+            "analysis": { "analyzer": { "folded": { "tokenizer": "standard" } } }
+        }));
+    }
+
+    #[test]
+    fn given_shards_and_replicas_replace_those_of_the_definition() {
+        let settings = [
+            json!({ "number_of_shards": 3, "number_of_replicas": 2 }),
+            json!({ "index.number_of_shards": 3, "index.number_of_replicas": 2 }),
+            json!({ "index": { "number_of_shards": 3, "number_of_replicas": 2 } }),
+        ];
+        for settings in settings {
+            let mut definition = json!({ "settings": settings });
+            set_shards(&mut definition, Some(2), Some(1)).unwrap();
+            let settings = definition["settings"].as_object().unwrap();
+            assert_eq!(settings["number_of_shards"], 2, "{:?}", settings);
+            assert_eq!(settings["number_of_replicas"], 1, "{:?}", settings);
+            // Written once, as Elasticsearch requires
+            assert!(!settings.contains_key("index.number_of_shards"), "{:?}", settings);
+            assert!(!settings.contains_key("index.number_of_replicas"), "{:?}", settings);
+            if let Some(index) = settings.get("index") {
+                assert_eq!(index, &json!({}));
+            }
+        }
+
+        // Only the given ones are replaced, and the other index settings are kept
+        let mut definition = json!({ "settings": {
+            "index": { "number_of_shards": 3, "number_of_replicas": 2, "refresh_interval": "5s" }
+        } });
+        set_shards(&mut definition, None, Some(1)).unwrap();
+        assert_eq!(definition["settings"], json!({
+            "index": { "number_of_shards": 3, "refresh_interval": "5s" },
+            "number_of_replicas": 1
+        }));
+    }
+
+    #[test]
+    fn definitions_and_settings_must_be_objects() {
+        let message = set_shards(&mut json!([]), Some(1), None).unwrap_err().to_string();
+        assert_eq!(message, "The index definition isn't an object");
+        let message = set_shards(&mut json!({ "settings": "1 shard" }), Some(1), None).unwrap_err().to_string();
+        assert_eq!(message, "The index settings aren't an object");
+    }
+
+    #[test]
+    fn failure_report_lists_the_failed_documents() {
+        let body = json!({
+            "took": 12,
+            "errors": true,
+            "items": [
+                { "index": { "_id": "a1", "status": 201 } },
+                { "index": { "_id": "b2", "status": 400, "error": {
+                    "type": "mapper_parsing_exception", "reason": "failed to parse field [location]"
+                } } },
+                { "index": { "_id": "c3", "status": 200 } },
+                { "index": { "_id": "d4", "status": 429, "error": {
+                    "type": "es_rejected_execution_exception", "reason": "rejected execution"
+                } } },
+            ]
+        });
+        let response = parse_bulk_response(&body.to_string(), 4).unwrap();
+        let failures = bulk_failures(response);
+        assert_eq!(failures.len(), 2);
+        assert!(!failures[0].is_transient());
+        assert!(failures[1].is_transient());
+
+        let stats = IngestStats { batches: 1, indexed: 2, retried: 0, failures };
+        assert_eq!(
+            stats.failure_report(),
+            "Failed to store data: 2 documents indexed on first try, 0 after retries, 2 failed\n  \
+            record b2 failed: mapper_parsing_exception: failed to parse field [location]\n  \
+            record d4 failed: es_rejected_execution_exception: rejected execution"
+        );
+    }
+
+    #[test]
+    fn failure_report_shows_the_attempts_of_retried_documents() {
+        let failure = FailedDocument {
+            record_id: "d4".to_string(),
+            status: 429,
+            error: BulkItemError { error_type: "es_rejected_execution_exception".to_string(), reason: String::new() },
+            attempts: 3,
+        };
+        let stats = IngestStats { batches: 2, indexed: 5, retried: 1, failures: vec![failure] };
+        assert_eq!(
+            stats.failure_report(),
+            "Failed to store data: 4 documents indexed on first try, 1 after retries, 1 failed\n  \
+            record d4 failed after 3 attempts: es_rejected_execution_exception: "
+        );
+    }
+
+    #[test]
+    fn items_without_an_error_are_reported_with_their_status() {
+        let body = r#"{"took":1,"errors":true,"items":[{"index":{"_id":"a1","status":503}}]}"#;
+        let failures = bulk_failures(parse_bulk_response(body, 1).unwrap());
+        assert_eq!(failures[0].error.error_type, "unknown_error");
+        assert_eq!(failures[0].error.reason, "status 503");
+        assert!(failures[0].is_transient());
+    }
+
+    #[test]
+    fn parses_elasticsearch_7_responses() {
+        let response = parse_bulk_response(include_str!("../tests/fixtures/bulk-7.17.json"), 3).unwrap();
+        assert_eq!(response.took, 30);
+        assert!(response.errors);
+
+        let failures = bulk_failures(response);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].record_id, "5cac2a7ad02a5c9a0d1c53b3957e97f97be62e0b");
+        assert_eq!(failures[0].status, 400);
+        assert_eq!(failures[0].error.error_type, "mapper_parsing_exception");
+        assert!(!failures[0].is_transient());
+    }
+
+    #[test]
+    fn parses_elasticsearch_8_responses() {
+        let response = parse_bulk_response(include_str!("../tests/fixtures/bulk-8.12.json"), 3).unwrap();
+        assert_eq!(response.took, 8);
+
+        let failures = bulk_failures(response);
+        let kinds: Vec<(&str, bool)> = failures.iter()
+            .map(|failure| (failure.error.error_type.as_str(), failure.is_transient()))
+            .collect();
+        assert_eq!(kinds, [("es_rejected_execution_exception", true), ("document_parsing_exception", false)]);
+    }
+
+    #[test]
+    fn unexpected_responses_are_errors_with_the_body() {
+        let fixture = include_str!("../tests/fixtures/bulk-8.12.json");
+        let responses = [
+            (fixture, 4, "3 items for 4 operations"),
+            (r#"{"took": 3, "errors": false}"#, 1, "missing field `items`"),
+            (r#"{"took": 3, "errors": false, "items": [{}]}"#, 1, "an item has 0 results instead of one"),
+            ("<html><body>502 Bad Gateway</body></html>", 1, "expected value"),
+        ];
+        for (body, operations, reason) in responses {
+            let message = parse_bulk_response(body, operations).unwrap_err().to_string();
+            assert!(message.starts_with("Unexpected bulk response ("), "{}", message);
+            assert!(message.contains(reason), "{}", message);
+            assert!(message.ends_with(&format!("response body: {}", body)), "{}", message);
+        }
+    }
+
+    #[test]
+    fn long_bodies_are_truncated_in_errors() {
+        let body = format!("<html>{}</html>", "é".repeat(MAX_BODY_IN_ERRORS));
+        let message = parse_bulk_response(&body, 1).unwrap_err().to_string();
+        assert!(message.ends_with(&format!("({} more bytes)", body.len() - MAX_BODY_IN_ERRORS)), "{}", message);
+    }
+}
@@ -0,0 +1,94 @@
+// Ctrl-C and SIGTERM handling: the first signal stops sending new batches and lets the requests in flight
+// complete, so that we know what was stored. A second signal, or the requests taking too long, exits
+// immediately. In watch mode, there's one more step: the first signal lets the current run complete and
+// stops the scheduling of the next ones.
+use crate::error::IngestError;
+use crate::telemetry;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{error, warn};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static FINISH_RUNS: AtomicBool = AtomicBool::new(false);
+
+// How long to wait for the requests in flight after the first signal
+const DEADLINE: Duration = Duration::from_secs(30);
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// No more runs should be started
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed) || is_interrupted()
+}
+
+// Let the current run complete on the first signal
+pub fn finish_runs_on_signal() {
+    FINISH_RUNS.store(true, Ordering::Relaxed);
+}
+
+// Wait for `duration`, or until a signal is received. Returns false in the latter case.
+pub async fn sleep(duration: Duration) -> bool {
+    let deadline = std::time::Instant::now() + duration;
+    while !is_stopping() {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        tokio::time::delay_for(remaining.min(Duration::from_secs(1))).await;
+    }
+    false
+}
+
+// Fail with an interruption error if a signal was received
+pub fn check() -> anyhow::Result<()> {
+    if is_interrupted() {
+        return Err(IngestError::Interrupted { indexed: 0 }.into());
+    }
+    Ok(())
+}
+
+// Listen for signals in the background. Must be called from the tokio runtime.
+pub fn install_handler() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::spawn(async move {
+        let mut signals = 0;
+        loop {
+            #[cfg(unix)]
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            #[cfg(not(unix))]
+            let _ = tokio::signal::ctrl_c().await;
+
+            signals += 1;
+            if signals == 1 && FINISH_RUNS.load(Ordering::Relaxed) {
+                STOPPING.store(true, Ordering::Relaxed);
+                warn!("Stopping once the current run is complete (interrupt again to stop it now)");
+                continue;
+            }
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                error!("Interrupted again, exiting now");
+                std::process::exit(IngestError::Interrupted { indexed: 0 }.exit_code());
+            }
+
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            warn!(
+                "Interrupted, waiting up to {}s for the requests in flight to complete (interrupt again to exit now)",
+                DEADLINE.as_secs()
+            );
+            tokio::spawn(async {
+                tokio::time::delay_for(DEADLINE).await;
+                error!("Requests in flight didn't complete after {}s, exiting now", DEADLINE.as_secs());
+                telemetry::shutdown();
+                std::process::exit(IngestError::Interrupted { indexed: 0 }.exit_code());
+            });
+        }
+    });
+    Ok(())
+}
+
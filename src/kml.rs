@@ -0,0 +1,29 @@
+use crate::export::{description, escape_xml, TITLE};
+use crate::geo::GeoPoint;
+use crate::transform::IndexedPlace;
+use std::io::Write;
+
+// A KML 2.2 document with a Placemark per place, for Google Earth, Organic Maps and most GPS apps. KML
+// coordinates are longitude first, like GeoJSON.
+//   <Placemark>
+//     <name>88 allée Jean Jaurès / angle rue Riquet</name>
+//     <description>Toulouse (ef89fdb5cbb3b397d2988b7d23c1fee5199b989c)</description>
+//     <Point><coordinates>1.45385907091,43.6089310498</coordinates></Point>
+//   </Placemark>
+pub fn write_kml(places: &[IndexedPlace], out: &mut impl Write) -> anyhow::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(out, "  <Document>")?;
+    writeln!(out, "    <name>{}</name>", escape_xml(TITLE))?;
+    for place in places {
+        let GeoPoint { lat, lon } = place.location;
+        writeln!(out, "    <Placemark>")?;
+        writeln!(out, "      <name>{}</name>", escape_xml(&place.street))?;
+        writeln!(out, "      <description>{}</description>", escape_xml(&description(place)))?;
+        writeln!(out, "      <Point><coordinates>{},{}</coordinates></Point>", lon, lat)?;
+        writeln!(out, "    </Placemark>")?;
+    }
+    writeln!(out, "  </Document>")?;
+    writeln!(out, "</kml>")?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+// Fetch the Toulouse xmas tree collection places, transform them and store them in Elasticsearch.
+//
+// The pipeline is split in three steps that can be used independently:
+// - `source`: fetch and parse the Opendatasoft export,
+// - `transform`: convert source records into the documents we index,
+// - `index`: create the index and bulk-load documents into it.
+//
+// `pipeline` can also run them at the same time on a stream of records, with bounded memory.
+//
+// Once loaded, `search` queries the indexed places and `stats` summarizes them. `export` writes them as GeoJSON,
+// CSV, KML or GPX for use without Elasticsearch, and `serve` exposes them to frontends as a JSON API.
+
+pub mod backup;
+pub mod cache;
+pub mod checkpoint;
+pub mod client;
+pub mod communes;
+pub mod config;
+pub mod coverage;
+pub mod csv;
+pub mod dataset;
+pub mod dedup;
+pub mod diff;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod geo;
+pub mod geocode;
+pub mod geojson;
+pub mod gpx;
+pub mod health;
+pub mod html;
+pub mod incremental;
+pub mod index;
+pub mod interrupt;
+pub mod kml;
+pub mod meta;
+pub mod output;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod pipeline;
+pub mod plan;
+pub mod progress;
+pub mod reindex;
+pub mod rejects;
+pub mod retry;
+pub mod s3;
+pub mod sample;
+pub mod schedule;
+pub mod search;
+pub mod sectors;
+pub mod serve;
+pub mod sink;
+pub mod source;
+pub mod sqlite;
+pub mod stats;
+pub mod subscriptions;
+pub mod suggest;
+pub mod summary;
+pub mod telemetry;
+pub mod text;
+pub mod transform;
+pub mod verify;
+
+pub const INDEX_NAME: &str = "xmas-tree-recycling";
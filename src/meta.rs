@@ -0,0 +1,66 @@
+// Where the data of an index comes from and when it was loaded, stored in the `_meta` of its mapping so that
+// anyone reading the index can attribute the data as its license requires
+use crate::dataset::Dataset;
+use chrono::{DateTime, Utc};
+use elasticsearch::indices::{IndicesGetMappingParts, IndicesPutMappingParts};
+use elasticsearch::Elasticsearch;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexMeta {
+    pub dataset_id: String,
+    pub license: String,
+    pub attribution: String,
+    pub data_url: String,
+    // Version of this tool
+    pub tool_version: String,
+    pub last_run: DateTime<Utc>,
+}
+
+impl IndexMeta {
+    pub fn new(dataset: &Dataset, data_url: &str) -> IndexMeta {
+        IndexMeta {
+            dataset_id: dataset.id.to_string(),
+            license: dataset.license.to_string(),
+            attribution: dataset.attribution(),
+            data_url: data_url.to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            last_run: Utc::now(),
+        }
+    }
+
+    // Add it to an index definition, for the index to have it from the start
+    pub fn add_to(&self, definition: &mut JsonValue) {
+        definition["mappings"]["_meta"] = json!(self);
+    }
+}
+
+// Replace the metadata of `index`, at the end of a run
+pub async fn put_meta(es_client: &Elasticsearch, index: &str, meta: &IndexMeta) -> anyhow::Result<()> {
+    es_client.indices()
+        .put_mapping(IndicesPutMappingParts::Index(&[index]))
+        .body(json!({ "_meta": meta }))
+        .send().await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+// The metadata of the indices that `index` refers to, by concrete index name. None for indices that weren't
+// created by this tool, or by versions that didn't store it.
+pub async fn get_meta(es_client: &Elasticsearch, index: &str) -> anyhow::Result<Vec<(String, Option<IndexMeta>)>> {
+    let response = es_client.indices()
+        .get_mapping(IndicesGetMappingParts::Index(&[index]))
+        .send().await?
+        .error_for_status_code()?
+        .json::<JsonValue>().await?;
+
+    let mut metas: Vec<_> = response.as_object().into_iter().flatten()
+        .map(|(name, definition)| {
+            (name.clone(), serde_json::from_value(definition["mappings"]["_meta"].clone()).ok())
+        })
+        .collect();
+    metas.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(metas)
+}
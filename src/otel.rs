@@ -0,0 +1,58 @@
+// OpenTelemetry export of the tracing spans and of the metrics recorded by `telemetry`, with the OTLP/HTTP
+// protocol. The exporters are configured by the standard OTEL_* environment variables, such as
+// OTEL_EXPORTER_OTLP_ENDPOINT (http://localhost:4318 by default) and OTEL_EXPORTER_OTLP_HEADERS.
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{MetricExporter, SpanExporter};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::sync::Mutex;
+use tracing::{warn, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const SERVICE_NAME: &str = "xmas-tree-recycling";
+
+struct Providers {
+    tracer: SdkTracerProvider,
+    meter: SdkMeterProvider,
+}
+
+// Taken by `shutdown`, which must only flush once
+static PROVIDERS: Mutex<Option<Providers>> = Mutex::new(None);
+
+// Start exporting, and return the layer that turns spans into OpenTelemetry traces
+pub fn init<S>() -> anyhow::Result<impl Layer<S>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let span_exporter = SpanExporter::builder().with_http().build()?;
+    let metric_exporter = MetricExporter::builder().with_http().build()?;
+
+    let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+    let tracer = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_resource(resource.clone())
+        .build();
+    let meter = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter)
+        .with_resource(resource)
+        .build();
+
+    opentelemetry::global::set_meter_provider(meter.clone());
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer.tracer(SERVICE_NAME));
+    *PROVIDERS.lock().unwrap() = Some(Providers { tracer, meter });
+    Ok(layer)
+}
+
+// Send the spans and metrics that haven't been exported yet
+pub fn shutdown() {
+    if let Some(providers) = PROVIDERS.lock().unwrap().take() {
+        if let Err(err) = providers.tracer.shutdown() {
+            warn!("Cannot export OpenTelemetry traces: {}", err);
+        }
+        if let Err(err) = providers.meter.shutdown() {
+            warn!("Cannot export OpenTelemetry metrics: {}", err);
+        }
+    }
+}
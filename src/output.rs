@@ -0,0 +1,339 @@
+// Rendering of the places found by the read commands: an aligned table for people, JSON for scripts, or the CSV
+// and GeoJSON formats of the export command
+use crate::export::{CsvExporter, Exporter, GeojsonExporter};
+use crate::search::SearchResult;
+use crate::transform::IndexedPlace;
+use std::io::Write;
+
+// Width of the tables when it can't be found, such as when the output isn't a terminal
+const DEFAULT_WIDTH: usize = 100;
+// Narrowest column that a truncated cell is cut down to
+const MIN_TRUNCATED_WIDTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+    Geojson,
+}
+
+pub fn write_results(results: &[SearchResult], format: Format, out: &mut dyn Write) -> anyhow::Result<()> {
+    match format {
+        Format::Table => write!(out, "{}", results_table(results, terminal_width()))?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, results)?;
+            writeln!(out)?;
+        }
+        Format::Csv => CsvExporter { bom: false }.write(&places(results), out)?,
+        Format::Geojson => GeojsonExporter.write(&places(results), out)?,
+    }
+    Ok(())
+}
+
+fn places(results: &[SearchResult]) -> Vec<IndexedPlace> {
+    results.iter().map(|result| result.place.clone()).collect()
+}
+
+// Street, city and id of the places, preceded by their distance if the search was near a location. Streets are
+// truncated for the lines to fit in `width`.
+pub fn results_table(results: &[SearchResult], width: usize) -> String {
+    let cells = |result: &SearchResult| {
+        [result.place.street.clone(), result.place.city.clone(), result.place.record_id.clone()]
+    };
+    if results.iter().any(|result| result.distance.is_some()) {
+        let rows: Vec<[String; 4]> = results.iter()
+            .map(|result| {
+                let [street, city, id] = cells(result);
+                [result.distance.map(format_distance).unwrap_or_default(), street, city, id]
+            })
+            .collect();
+        table(["Distance", "Street", "City", "Id"], &rows, [true, false, false, false], Some((width, 1)))
+    } else {
+        let rows: Vec<[String; 3]> = results.iter().map(cells).collect();
+        table(["Street", "City", "Id"], &rows, [false, false, false], Some((width, 0)))
+    }
+}
+
+pub fn format_distance(meters: f64) -> String {
+    if meters < 1000.0 {
+        format!("{:.0} m", meters)
+    } else {
+        format!("{:.1} km", meters / 1000.0)
+    }
+}
+
+// Columns of the terminal that stdout is, if it is one
+pub fn terminal_width() -> usize {
+    console::Term::stdout().size_checked().map(|(_, columns)| columns as usize).unwrap_or(DEFAULT_WIDTH)
+}
+
+pub fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]], align_right: [bool; N]) {
+    print!("{}", table(header, rows, align_right, None));
+}
+
+// `rows` in columns, with cells left or right aligned. With `fit` (width, column), the cells of that column are
+// truncated so that lines aren't wider than the width, if that leaves it wide enough.
+pub fn table<const N: usize>(
+    header: [&str; N],
+    rows: &[[String; N]],
+    align_right: [bool; N],
+    fit: Option<(usize, usize)>
+) -> String {
+    let mut widths = header.map(|title| title.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    if let Some((max_width, column)) = fit {
+        // Columns are separated by 2 spaces
+        let total = widths.iter().sum::<usize>() + 2 * (N - 1);
+        if total > max_width {
+            let others = total - widths[column];
+            widths[column] = max_width.saturating_sub(others).max(MIN_TRUNCATED_WIDTH).min(widths[column]);
+        }
+    }
+
+    let mut table = String::new();
+    let mut push_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells.iter().enumerate()
+            .map(|(i, cell)| {
+                let cell = truncate(cell, widths[i]);
+                match (align_right[i], i == N - 1) {
+                    (true, _) => format!("{:>w$}", cell, w = widths[i]),
+                    // No trailing spaces
+                    (false, true) => cell,
+                    (false, false) => format!("{:w$}", cell, w = widths[i]),
+                }
+            })
+            .collect();
+        table.push_str(&line.join("  "));
+        table.push('\n');
+    };
+
+    push_row(header.to_vec());
+    for row in rows {
+        push_row(row.iter().map(String::as_str).collect());
+    }
+    table
+}
+
+// At most `width` characters of `text`, ending with an ellipsis if it was cut
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::GeoPoint;
+    use crate::source::SourcePlace;
+    use crate::transform::{transform, BoundingBox, TransformOptions};
+    use serde_json::json;
+
+    fn place(record_id: &str, commune: &str, adresse: &str, (lat, lon): (f64, f64)) -> IndexedPlace {
+        let record = json!({
+            "datasetid": "collecte-des-sapins-de-noel",
+            "recordid": record_id,
+            "fields": { "commune": commune, "adresse": adresse, "geo_point_2d": [lat, lon] }
+        });
+        let source: SourcePlace = serde_json::from_value(record).unwrap();
+        let options = TransformOptions {
+            bbox: Some(BoundingBox::TOULOUSE_METROPOLE),
+            normalize_city: true,
+            geohash_precision: 7,
+            campaign_year: 2023,
+            indexed_at: "2024-01-08T06:00:00Z".parse().unwrap(),
+            run_id: "test-run".to_string(),
+            source_url: "file:places.json".to_string(),
+            center: GeoPoint::new(43.6045, 1.444),
+            sectors: Vec::new(),
+        };
+        transform(source, &options).unwrap()
+    }
+
+    const LONG_STREET: &str = "88 allée Jean Jaurès, angle rue Riquet, devant le gymnase";
+
+    // The same places, found by a full-text search or by a search near a location
+    fn results(near: bool) -> Vec<SearchResult> {
+        let places = [
+            (place("a1", "TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469)), 2.54, 12.3),
+            (place("b2", "BALMA", "r des Écoles", (43.6109, 1.4997)), 1.87, 4520.8),
+            (place("c3", "L UNION", LONG_STREET, (43.6580, 1.4832)), 0.92, 7035.0),
+        ];
+        places.iter()
+            .map(|(place, score, distance)| SearchResult {
+                place: place.clone(),
+                score: if near { None } else { Some(*score) },
+                distance: if near { Some(*distance) } else { None },
+            })
+            .collect()
+    }
+
+    fn render(results: &[SearchResult], format: Format) -> String {
+        let mut out = Vec::new();
+        write_results(results, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tables_have_a_distance_column_for_searches_near_a_location() {
+        assert_eq!(results_table(&results(false), 100), concat!(
+            "Street                                                     City      Id\n",
+            "Place du Capitole                                          Toulouse  a1\n",
+            "rue des Écoles                                             Balma     b2\n",
+            "88 allée Jean Jaurès, angle rue Riquet, devant le gymnase  L Union   c3\n",
+        ));
+        assert_eq!(results_table(&results(true), 100), concat!(
+            "Distance  Street                                                     City      Id\n",
+            "    12 m  Place du Capitole                                          Toulouse  a1\n",
+            "  4.5 km  rue des Écoles                                             Balma     b2\n",
+            "  7.0 km  88 allée Jean Jaurès, angle rue Riquet, devant le gymnase  L Union   c3\n",
+        ));
+    }
+
+    #[test]
+    fn streets_are_truncated_for_tables_to_fit_in_the_width() {
+        assert_eq!(results_table(&results(false), 50), concat!(
+            "Street                                City      Id\n",
+            "Place du Capitole                     Toulouse  a1\n",
+            "rue des Écoles                        Balma     b2\n",
+            "88 allée Jean Jaurès, angle rue Riq…  L Union   c3\n",
+        ));
+        assert_eq!(results_table(&results(true), 50), concat!(
+            "Distance  Street                      City      Id\n",
+            "    12 m  Place du Capitole           Toulouse  a1\n",
+            "  4.5 km  rue des Écoles              Balma     b2\n",
+            "  7.0 km  88 allée Jean Jaurès, ang…  L Union   c3\n",
+        ));
+    }
+
+    #[test]
+    fn json_results_have_their_score_or_distance() {
+        let results = [results(false)[0].clone(), results(true)[0].clone()];
+        assert_eq!(render(&results, Format::Json), r#"[
+  {
+    "dataset_id": "collecte-des-sapins-de-noel",
+    "record_id": "a1",
+    "city": "Toulouse",
+    "city_raw": "TOULOUSE",
+    "insee_code": "31555",
+    "postal_code": [
+      "31000",
+      "31100",
+      "31200",
+      "31300",
+      "31400",
+      "31500"
+    ],
+    "street": "Place du Capitole",
+    "street_raw": "Place du Capitole",
+    "house_number": null,
+    "street_type": "place",
+    "street_name": "du Capitole",
+    "address_note": null,
+    "street_suggest": [
+      "Place du Capitole",
+      "du Capitole",
+      "Capitole"
+    ],
+    "location": [
+      1.4442469,
+      43.6044622
+    ],
+    "geohash": "spc00cg",
+    "plus_code": "8FM3JC3V+QM",
+    "distance_to_center_m": 20.319456385073718,
+    "sector": null,
+    "campaign_year": 2023,
+    "indexed_at": "2024-01-08T06:00:00Z",
+    "run_id": "test-run",
+    "active": true,
+    "source_url": "file:places.json",
+    "content_hash": "864481469bc560413609d034b8c262ccc5dbf666",
+    "_score": 2.54,
+    "distance": null
+  },
+  {
+    "dataset_id": "collecte-des-sapins-de-noel",
+    "record_id": "a1",
+    "city": "Toulouse",
+    "city_raw": "TOULOUSE",
+    "insee_code": "31555",
+    "postal_code": [
+      "31000",
+      "31100",
+      "31200",
+      "31300",
+      "31400",
+      "31500"
+    ],
+    "street": "Place du Capitole",
+    "street_raw": "Place du Capitole",
+    "house_number": null,
+    "street_type": "place",
+    "street_name": "du Capitole",
+    "address_note": null,
+    "street_suggest": [
+      "Place du Capitole",
+      "du Capitole",
+      "Capitole"
+    ],
+    "location": [
+      1.4442469,
+      43.6044622
+    ],
+    "geohash": "spc00cg",
+    "plus_code": "8FM3JC3V+QM",
+    "distance_to_center_m": 20.319456385073718,
+    "sector": null,
+    "campaign_year": 2023,
+    "indexed_at": "2024-01-08T06:00:00Z",
+    "run_id": "test-run",
+    "active": true,
+    "source_url": "file:places.json",
+    "content_hash": "864481469bc560413609d034b8c262ccc5dbf666",
+    "_score": null,
+    "distance": 12.3
+  }
+]
+"#);
+    }
+
+    #[test]
+    fn csv_results_are_exported_places() {
+        assert_eq!(render(&results(false), Format::Csv), concat!(
+            "record_id,city,street,lat,lon\r\n",
+            "a1,Toulouse,Place du Capitole,43.6044622,1.4442469\r\n",
+            "b2,Balma,rue des Écoles,43.6109,1.4997\r\n",
+            "c3,L Union,\"88 allée Jean Jaurès, angle rue Riquet, devant le gymnase\",43.658,1.4832\r\n",
+        ));
+    }
+
+    #[test]
+    fn geojson_results_are_exported_places() {
+        let geojson: serde_json::Value = serde_json::from_str(&render(&results(true), Format::Geojson)).unwrap();
+        let feature = |id: &str, street: &str, city: &str, (lon, lat): (f64, f64)| json!({
+            "type": "Feature",
+            "id": id,
+            "geometry": { "type": "Point", "coordinates": [lon, lat] },
+            "properties": {
+                "dataset_id": "collecte-des-sapins-de-noel", "record_id": id, "street": street, "city": city
+            }
+        });
+        assert_eq!(geojson, json!({
+            "type": "FeatureCollection",
+            "features": [
+                feature("a1", "Place du Capitole", "Toulouse", (1.4442469, 43.6044622)),
+                feature("b2", "rue des Écoles", "Balma", (1.4997, 43.6109)),
+                feature("c3", LONG_STREET, "L Union", (1.4832, 43.658)),
+            ]
+        }));
+    }
+}
@@ -0,0 +1,78 @@
+// Streams source records to a sink in three stages connected by bounded channels, so that only a few batches
+// are in memory whatever the size of the data. Slow bulk requests slow down the parsing, rather than letting
+// parsed records pile up:
+//   parse, on a blocking thread -> transform, in batches -> store, with up to `concurrency` batches in flight
+// An error in any stage cancels the other ones, and is the one returned.
+use crate::index::{self, Batcher, BulkOptions, IngestStats};
+use crate::interrupt;
+use crate::sink::Sink;
+use crate::source::SourcePlace;
+use crate::transform::IndexedPlace;
+use anyhow::anyhow;
+use futures::stream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+pub struct Pipeline<'a> {
+    pub sink: &'a dyn Sink,
+    pub dataset: &'a str,
+    pub options: BulkOptions,
+}
+
+impl Pipeline<'_> {
+    // `parse` passes each record to its callback, and must stop if the callback fails. `transform` returns None
+    // for records that are skipped.
+    pub async fn run<P, T>(&self, parse: P, mut transform: T) -> anyhow::Result<IngestStats>
+    where
+        P: FnOnce(&mut dyn FnMut(SourcePlace) -> anyhow::Result<()>) -> anyhow::Result<()> + Send + 'static,
+        T: FnMut(SourcePlace) -> Option<IndexedPlace>,
+    {
+        let BulkOptions { bulk_size, concurrency, .. } = self.options;
+        // A batch worth of records waiting to be transformed, and as many batches as can be sent in parallel
+        let (mut records_tx, mut records_rx) = mpsc::channel::<SourcePlace>(bulk_size);
+        let (mut batches_tx, batches_rx) = mpsc::channel::<Vec<IndexedPlace>>(concurrency);
+
+        // The next stages are gone when they failed or were interrupted: they report why, not the parser
+        let closed = Arc::new(AtomicBool::new(false));
+        let parser_closed = closed.clone();
+        let parsed = tokio::task::spawn_blocking(move || {
+            parse(&mut |record| {
+                futures::executor::block_on(records_tx.send(record)).map_err(|_| {
+                    parser_closed.store(true, Ordering::Relaxed);
+                    anyhow!("The pipeline was stopped")
+                })
+            })
+        });
+        let parse_stage = async {
+            match parsed.await? {
+                Err(_) if closed.load(Ordering::Relaxed) => Ok(()),
+                result => result,
+            }
+        };
+
+        let mut batcher = Batcher::new(&self.options, self.sink);
+        let transform_stage = async move {
+            while let Some(record) = records_rx.recv().await {
+                if interrupt::is_interrupted() {
+                    return Ok(());
+                }
+                if let Some(full) = transform(record).and_then(|place| batcher.push(place)) {
+                    batches_tx.send(full).await.map_err(|_| anyhow!("The pipeline was stopped"))?;
+                }
+            }
+            if let Some(batch) = batcher.take() {
+                batches_tx.send(batch).await.map_err(|_| anyhow!("The pipeline was stopped"))?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let batches = stream::unfold(batches_rx, |mut batches_rx| async {
+            batches_rx.recv().await.map(|batch| (batch, batches_rx))
+        });
+        let store_stage = index::store_batches(self.sink, self.dataset, batches, concurrency, None);
+
+        let ((), (), stats) = futures::try_join!(parse_stage, transform_stage, store_stage)?;
+        Ok(stats)
+    }
+}
@@ -0,0 +1,65 @@
+// What a run will do to the index, computed once the data is fetched and transformed, but before changing
+// anything. It's printed with --plan, and must be confirmed before runs that replace the index contents.
+use anyhow::anyhow;
+use std::io::{BufRead, IsTerminal};
+
+#[derive(Debug)]
+pub struct Plan {
+    pub dataset: String,
+    // URLs or Cloud id of the cluster, without passwords
+    pub cluster: String,
+    // Index and alias operations, in the order they're done
+    pub actions: Vec<String>,
+    pub to_write: usize,
+    // Bulk requests needed to write them, of up to `bulk_size` documents and `bulk_bytes` bytes
+    pub batches: usize,
+    pub to_delete: usize,
+    pub to_deactivate: usize,
+    pub bulk_size: usize,
+    pub bulk_bytes: usize,
+    // Whether documents that are in the index now can be lost
+    pub destructive: bool,
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Plan for dataset {} on {}:", self.dataset, self.cluster)?;
+        for action in &self.actions {
+            writeln!(f, "  - {}", action)?;
+        }
+        writeln!(
+            f, "  - Write {} documents in {} bulk requests of up to {} documents and {} bytes",
+            self.to_write, self.batches, self.bulk_size, self.bulk_bytes
+        )?;
+        if self.to_delete > 0 {
+            writeln!(f, "  - Delete {} documents that are no longer in the source data", self.to_delete)?;
+        }
+        if self.to_deactivate > 0 {
+            writeln!(f, "  - Mark {} documents that are no longer in the source data as inactive", self.to_deactivate)?;
+        }
+        Ok(())
+    }
+}
+
+// Show a destructive plan and ask to confirm it on the terminal, unless it was confirmed beforehand with `yes`
+pub fn confirm(plan: &Plan, yes: bool) -> anyhow::Result<()> {
+    if yes || !plan.destructive {
+        return Ok(());
+    }
+
+    eprint!("{}", plan);
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!(
+            "This run replaces the index contents and must be confirmed, but stdin is not a terminal. \
+            Use --yes to confirm it beforehand, after checking what it would do with --plan."
+        ));
+    }
+    eprint!("Type 'yes' to proceed: ");
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+    if answer.trim() != "yes" {
+        return Err(anyhow!("Not confirmed, the index was left untouched"));
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+// Progress of long operations: a progress bar on interactive terminals, periodic log lines otherwise
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+static BARS: AtomicBool = AtomicBool::new(false);
+
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Show progress bars instead of logging progress. Should only be enabled if `can_draw_bars()`.
+pub fn enable_bars(enabled: bool) {
+    BARS.store(enabled, Ordering::Relaxed);
+}
+
+// Bars are drawn on stderr, which has to be a terminal rather than a file or a log collector
+pub fn can_draw_bars() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Bytes,
+    Documents,
+}
+
+pub struct Progress {
+    what: &'static str,
+    unit: Unit,
+    total: Option<u64>,
+    bar: Option<ProgressBar>,
+    position: AtomicU64,
+    start: Instant,
+    last_log: Mutex<Instant>,
+}
+
+impl Progress {
+    // Progress of `what` (e.g. "Downloading"), expected to reach `total` if it's known
+    pub fn new(what: &'static str, unit: Unit, total: Option<u64>) -> Progress {
+        let bar = BARS.load(Ordering::Relaxed).then(|| {
+            let template = match (unit, total) {
+                (Unit::Bytes, Some(_)) => "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})",
+                (Unit::Bytes, None) => "{spinner} {msg} {bytes} ({bytes_per_sec})",
+                (Unit::Documents, Some(_)) => "{msg} [{bar:30}] {pos}/{len} documents ({per_sec})",
+                (Unit::Documents, None) => "{spinner} {msg} {pos} documents ({per_sec})",
+            };
+            let bar = total.map_or_else(ProgressBar::new_spinner, ProgressBar::new);
+            if let Ok(style) = ProgressStyle::with_template(template) {
+                bar.set_style(style.progress_chars("=> "));
+            }
+            bar.set_message(what);
+            bar
+        });
+
+        let now = Instant::now();
+        Progress {
+            what,
+            unit,
+            total,
+            bar,
+            position: AtomicU64::new(0),
+            start: now,
+            last_log: Mutex::new(now),
+        }
+    }
+
+    pub fn inc(&self, delta: u64) {
+        let position = self.position.fetch_add(delta, Ordering::Relaxed) + delta;
+        match &self.bar {
+            Some(bar) => bar.inc(delta),
+            None => {
+                if let Ok(mut last_log) = self.last_log.lock() {
+                    if last_log.elapsed() >= LOG_INTERVAL {
+                        *last_log = Instant::now();
+                        info!("{}: {}", self.what, self.describe(position));
+                    }
+                }
+            }
+        }
+    }
+
+    // Additional information shown next to the bar, such as the number of batches
+    pub fn set_message(&self, message: &str) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(format!("{} {}", self.what, message));
+        }
+    }
+
+    // Remove the bar, and return how long the operation took
+    pub fn finish(&self) -> Duration {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        self.start.elapsed()
+    }
+
+    fn describe(&self, position: u64) -> String {
+        let amount = |n: u64| match self.unit {
+            Unit::Bytes => HumanBytes(n).to_string(),
+            Unit::Documents => format!("{} documents", n),
+        };
+        match self.total {
+            Some(total) => format!("{} of {}", amount(position), amount(total)),
+            None => amount(position),
+        }
+    }
+}
+
+// Items per second, for throughput summaries
+pub fn rate(count: usize, duration: Duration) -> f64 {
+    count as f64 / duration.as_secs_f64().max(0.001)
+}
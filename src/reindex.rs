@@ -0,0 +1,32 @@
+// An index loaded by this tool, possibly on another cluster, as the source data of a run: it's copied without
+// fetching the data again. Its documents are already transformed, and keep their ids since they're the record
+// ids of the places.
+use crate::index;
+use crate::transform::IndexedPlace;
+use anyhow::{anyhow, Context};
+use elasticsearch::Elasticsearch;
+use serde_json::json;
+use tracing::{info, instrument};
+
+#[instrument(name = "read_index", skip(es_client))]
+pub async fn read_places(es_client: &Elasticsearch, index: &str) -> anyhow::Result<Vec<IndexedPlace>> {
+    if !index::index_exists(es_client, index).await? {
+        return Err(anyhow!("Source index {} doesn't exist", index));
+    }
+
+    let mut places = Vec::new();
+    index::scroll(es_client, index, json!(true), |id, source| {
+        let place: IndexedPlace = serde_json::from_value(source)
+            .with_context(|| format!("Document {} of index {} isn't a place loaded by this tool", id, index))?;
+        if place.record_id != id {
+            return Err(anyhow!(
+                "Document {} of index {} has record id {}, its id would change when copied", id, index, place.record_id
+            ));
+        }
+        places.push(place);
+        Ok(())
+    }).await?;
+
+    info!("Read {} places from index {}", places.len(), index);
+    Ok(places)
+}
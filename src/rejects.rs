@@ -0,0 +1,84 @@
+// Source records that were left out and why, written as NDJSON with --rejects so that data-quality reports
+// can be sent to the open data team:
+//   {"dataset":"collecte-des-sapins-de-noel","record_id":"ef89fd...","stage":"transform","reason":"out_of_bbox",
+//    "message":"outside of the bounding box","record":{"datasetid":"...","recordid":"ef89fd...","fields":{...}}}
+use crate::dedup::Duplicate;
+use crate::transform::Skipped;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Transform,
+    Dedup,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reject {
+    pub dataset: String,
+    pub record_id: String,
+    pub stage: Stage,
+    // Machine-readable reason, such as "missing_location" or "duplicate"
+    pub reason: &'static str,
+    pub message: String,
+    // The source record, before its fields were mapped and transformed
+    pub record: JsonValue,
+}
+
+impl Reject {
+    pub fn skipped(dataset: &str, skipped: Skipped) -> Reject {
+        let place = skipped.place;
+        let fields = match place.raw_fields {
+            Some(raw_fields) => raw_fields,
+            None => json!(place.fields),
+        };
+        Reject {
+            dataset: dataset.to_string(),
+            record_id: place.recordid.clone(),
+            stage: Stage::Transform,
+            reason: skipped.reason.code(),
+            message: skipped.reason.to_string(),
+            record: json!({ "datasetid": place.datasetid, "recordid": place.recordid, "fields": fields }),
+        }
+    }
+
+    // The source fields are only known if they were kept in the `raw` field of the place
+    pub fn duplicate(dataset: &str, duplicate: Duplicate) -> Reject {
+        let place = duplicate.place;
+        Reject {
+            dataset: dataset.to_string(),
+            record_id: place.record_id.clone(),
+            stage: Stage::Dedup,
+            reason: "duplicate",
+            message: format!("duplicate of record {}", duplicate.original),
+            record: json!({ "datasetid": place.dataset_id, "recordid": place.record_id, "fields": place.raw }),
+        }
+    }
+}
+
+// Write the rejects to `path`. The file is only created if there are rejects, and a file left by a previous run
+// is removed otherwise, so that its existence means there's something to look at.
+pub fn write_rejects(path: &Path, rejects: &[&Reject]) -> anyhow::Result<()> {
+    if rejects.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Cannot remove the previous rejects file {}", path.display()))?;
+        }
+        return Ok(());
+    }
+
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Cannot create the rejects file {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    for reject in rejects {
+        serde_json::to_writer(&mut out, reject)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
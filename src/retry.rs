@@ -0,0 +1,219 @@
+use crate::summary;
+use crate::telemetry::timed;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use elasticsearch::http::response::Response;
+use elasticsearch::http::StatusCode;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+// How many times and how long to wait before retrying a request that failed with a transient error
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    // Total time to wait for a server that rate limits requests, which doesn't count in `max_attempts`
+    pub max_rate_limit_wait: Duration,
+}
+
+// An error that may (transient) or may not (permanent) go away if the request is retried
+#[derive(Debug)]
+pub enum Failure {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+    // A 429 response, with how long the server asked to wait before retrying, if it did
+    RateLimited(anyhow::Error, Option<Duration>),
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_rate_limit_wait: Duration::from_secs(300),
+        }
+    }
+
+    // Delay after the n-th failed attempt: exponential backoff, with a random jitter of up to 50%
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_delay
+            .checked_mul(1 << (attempt - 1).min(16))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+// Run `attempt` until it succeeds, fails with a permanent error or `policy.max_attempts` is reached, waiting
+// with an exponential backoff and some jitter between attempts. Rate limited attempts are retried after the delay
+// asked by the server, until they've waited `policy.max_rate_limit_wait` in total. The duration of each attempt
+// is recorded in the current span.
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, what: &str, mut attempt: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    let mut attempt_count = 1;
+    let mut rate_limited_count = 0;
+    let mut rate_limited_wait = Duration::ZERO;
+    loop {
+        match timed(attempt()).await {
+            Ok(result) => return Ok(result),
+            Err(Failure::RateLimited(err, retry_after)) => {
+                rate_limited_count += 1;
+                let delay = retry_after.unwrap_or_else(|| policy.delay(rate_limited_count));
+                if rate_limited_wait + delay > policy.max_rate_limit_wait {
+                    return Err(err.context(format!(
+                        "Failed to {}: still rate limited after waiting {:.0}s, and asked to wait {:.0}s more",
+                        what, rate_limited_wait.as_secs_f64(), delay.as_secs_f64()
+                    )));
+                }
+                warn!("Rate limited when trying to {}. Retrying in {:.1}s", what, delay.as_secs_f64());
+                tokio::time::delay_for(delay).await;
+                summary::add_retry();
+                rate_limited_wait += delay;
+            }
+            Err(Failure::Permanent(err)) => return Err(err.context(format!("Failed to {}", what))),
+            Err(Failure::Transient(err)) if attempt_count >= policy.max_attempts => {
+                return Err(err.context(format!("Failed to {} after {} attempts", what, attempt_count)));
+            }
+            Err(Failure::Transient(err)) => {
+                let delay = policy.delay(attempt_count);
+                warn!(
+                    "Attempt {}/{} to {} failed: {}. Retrying in {:.1}s",
+                    attempt_count, policy.max_attempts, what, err, delay.as_secs_f64()
+                );
+                tokio::time::delay_for(delay).await;
+                summary::add_retry();
+                attempt_count += 1;
+            }
+        }
+    }
+}
+
+// 429 (too many requests) and server errors are worth retrying, other errors will fail the same way again
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Classify an error status in an HTTP response. 429 responses are retried after the delay of their Retry-After
+// header.
+pub fn http_response(response: reqwest::Response) -> Result<reqwest::Response, Failure> {
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response.headers().get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        let err = anyhow!("{} returned status {}", response.url(), response.status());
+        return Err(Failure::RateLimited(err, retry_after));
+    }
+    response.error_for_status().map_err(http_failure)
+}
+
+// A Retry-After header value: a number of seconds, or an HTTP date
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means that we can retry now
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+pub fn http_failure(err: reqwest::Error) -> Failure {
+    let transient = err.is_connect() || err.is_timeout() || err.status().is_some_and(is_transient_status);
+    if transient {
+        Failure::Transient(err.into())
+    } else {
+        Failure::Permanent(err.into())
+    }
+}
+
+pub fn es_failure(err: elasticsearch::Error) -> Failure {
+    // Connection errors come from the underlying http client
+    let connect = std::error::Error::source(&err)
+        .and_then(|source| source.downcast_ref::<reqwest::Error>())
+        .is_some_and(|source| source.is_connect());
+    if connect || err.is_timeout() || err.status_code().is_some_and(is_transient_status) {
+        Failure::Transient(err.into())
+    } else {
+        Failure::Permanent(err.into())
+    }
+}
+
+// Classify an error status in an Elasticsearch response
+pub fn es_response(response: Response) -> Result<Response, Failure> {
+    let status = response.status_code();
+    if is_transient_status(status) {
+        Err(Failure::Transient(anyhow!("Elasticsearch returned status {}", status)))
+    } else {
+        response.error_for_status_code().map_err(|err| Failure::Permanent(err.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy::new(10);
+        for (attempt, expected) in [(1, 500), (2, 1000), (3, 2000), (4, 4000), (7, 30_000), (40, 30_000)] {
+            let delay = policy.delay(attempt).as_millis();
+            // With up to 50% of jitter
+            assert!(delay >= expected / 2 && delay <= expected, "attempt {}: {}ms", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn only_rate_limits_and_server_errors_are_transient() {
+        for status in [429, 500, 502, 503, 504] {
+            assert!(is_transient_status(StatusCode::from_u16(status).unwrap()), "{}", status);
+        }
+        for status in [400, 401, 403, 404, 409] {
+            assert!(!is_transient_status(StatusCode::from_u16(status).unwrap()), "{}", status);
+        }
+    }
+
+    #[test]
+    fn retry_after_is_a_delay_or_a_date() {
+        let now = "2024-01-08T06:00:00Z".parse().unwrap();
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Mon, 08 Jan 2024 06:01:30 GMT", now), Some(Duration::from_secs(90)));
+        // Dates in the past mean that the request can be retried now
+        assert_eq!(parse_retry_after("Sun, 07 Jan 2024 06:00:00 GMT", now), Some(Duration::ZERO));
+        for invalid in ["", "soon", "-1", "1.5", "2024-01-08T06:01:30Z"] {
+            assert_eq!(parse_retry_after(invalid, now), None, "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_attempts_are_retried_after_the_requested_delay() {
+        let policy = RetryPolicy { max_rate_limit_wait: Duration::from_millis(50), ..RetryPolicy::new(1) };
+        let mut attempts = 0;
+        let result = retry(&policy, "fetch data", || {
+            attempts += 1;
+            let result = if attempts < 3 {
+                Err(Failure::RateLimited(anyhow!("rate limited"), Some(Duration::from_millis(20))))
+            } else {
+                Ok(attempts)
+            };
+            async move { result }
+        }).await;
+        // Rate limited attempts don't count in the maximum number of attempts
+        assert_eq!(result.unwrap(), 3);
+
+        // Until the total wait would be more than the maximum
+        let mut attempts = 0;
+        let err = retry(&policy, "fetch data", || {
+            attempts += 1;
+            async { Err::<(), _>(Failure::RateLimited(anyhow!("rate limited"), Some(Duration::from_millis(20)))) }
+        }).await.unwrap_err();
+        assert_eq!(attempts, 3);
+        assert!(err.to_string().contains("still rate limited after waiting 0s"), "{:#}", err);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::progress::{Progress, Unit};
+use crate::retry::{retry, Failure, RetryPolicy};
+use crate::source::Source;
+use crate::summary;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{GetObjectError, GetObjectRequest, S3Client, S3};
+use tracing::info;
+
+// An object in an S3 bucket, such as an archived daily export. Credentials and region are found the standard
+// AWS way: environment variables, profile files, then container or instance metadata. AWS_ENDPOINT_URL can
+// point to an S3 compatible server.
+pub struct S3Source {
+    bucket: String,
+    key: String,
+    client: S3Client,
+    retry_policy: RetryPolicy,
+}
+
+impl S3Source {
+    // `uri` is "s3://bucket/key"
+    pub fn new(uri: &str, retry_policy: &RetryPolicy) -> anyhow::Result<S3Source> {
+        let (bucket, key) = uri.strip_prefix("s3://")
+            .and_then(|path| path.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| anyhow!("Invalid S3 location {}, expecting s3://bucket/key", uri))?;
+
+        let region = match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => Region::Custom { name: Region::default().name().to_string(), endpoint },
+            Err(_) => Region::default(),
+        };
+
+        Ok(S3Source {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            client: S3Client::new(region),
+            retry_policy: *retry_policy,
+        })
+    }
+}
+
+impl std::fmt::Display for S3Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "S3 object s3://{}/{}", self.bucket, self.key)
+    }
+}
+
+#[async_trait]
+impl Source for S3Source {
+    async fn fetch(&self) -> anyhow::Result<Vec<u8>> {
+        retry(&self.retry_policy, &format!("fetch {}", self), || async move {
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: self.key.clone(),
+                ..Default::default()
+            };
+            let output = self.client.get_object(request).await.map_err(s3_failure)?;
+            let mut body = output.body.ok_or_else(|| Failure::Permanent(anyhow!("The object has no content")))?;
+
+            let length = output.content_length.map(|length| length as u64);
+            let progress = Progress::new("Downloading", Unit::Bytes, length);
+            let mut data = Vec::with_capacity(length.unwrap_or_default() as usize);
+            while let Some(chunk) = body.try_next().await.map_err(|err| Failure::Transient(err.into()))? {
+                data.extend_from_slice(&chunk);
+                progress.inc(chunk.len() as u64);
+                summary::add_downloaded(chunk.len() as u64);
+            }
+
+            let duration = progress.finish();
+            info!("Downloaded {} bytes in {:.1}s", data.len(), duration.as_secs_f64());
+            Ok(data)
+        }).await
+    }
+}
+
+// Network errors, throttling and server errors are worth retrying. A missing object or bucket, or denied
+// access will fail the same way again.
+fn s3_failure(err: RusotoError<GetObjectError>) -> Failure {
+    let transient = match &err {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => response.status.as_u16() == 429 || response.status.is_server_error(),
+        _ => false,
+    };
+    let err = match err {
+        // The default message is the raw XML response
+        RusotoError::Unknown(response) => anyhow!("S3 returned status {}", response.status),
+        err => err.into(),
+    };
+    if transient {
+        Failure::Transient(err)
+    } else {
+        Failure::Permanent(err)
+    }
+}
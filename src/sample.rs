@@ -0,0 +1,70 @@
+// Partial runs, to test changes against a production cluster without sending all the documents
+use crate::transform::IndexedPlace;
+use sha1::{Digest, Sha1};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Partial {
+    // Keep only the first places
+    pub limit: Option<usize>,
+    // Keep about this fraction of the places, between 0 and 1, chosen with `seed`
+    pub sample: Option<f64>,
+    pub seed: u64,
+}
+
+impl std::fmt::Display for Partial {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut options = Vec::new();
+        if let Some(sample) = self.sample {
+            options.push(format!("--sample {} --seed {}", sample, self.seed));
+        }
+        if let Some(limit) = self.limit {
+            options.push(format!("--limit {}", limit));
+        }
+        f.write_str(&options.join(" "))
+    }
+}
+
+impl Partial {
+    // The sample is taken first, so that the limit applies to the sampled places
+    pub fn apply(&self, places: Vec<IndexedPlace>) -> Vec<IndexedPlace> {
+        places.into_iter()
+            .filter(|place| self.sample.is_none_or(|fraction| is_sampled(&place.record_id, fraction, self.seed)))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+// Whether a place is in the sample only depends on its record id and the seed, so that runs with the same
+// seed keep the same places, whatever their order in the source data
+fn is_sampled(record_id: &str, fraction: f64, seed: u64) -> bool {
+    let mut hasher = Sha1::new();
+    hasher.update(seed.to_be_bytes());
+    hasher.update(record_id);
+    let hash = hasher.finalize();
+
+    let mut value = [0; 8];
+    value.copy_from_slice(&hash[..8]);
+    (u64::from_be_bytes(value) as f64) < fraction * u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_have_about_the_requested_size() {
+        let ids: Vec<String> = (0..10_000).map(|i| format!("record-{}", i)).collect();
+        let sampled = ids.iter().filter(|id| is_sampled(id, 0.1, 42)).count();
+        assert!((900..1100).contains(&sampled), "{}", sampled);
+        assert!(ids.iter().all(|id| is_sampled(id, 1.0, 42)));
+        assert!(!ids.iter().any(|id| is_sampled(id, 0.0, 42)));
+    }
+
+    #[test]
+    fn samples_only_depend_on_the_seed() {
+        let ids: Vec<String> = (0..1000).map(|i| format!("record-{}", i)).collect();
+        let sample = |seed| ids.iter().filter(|id| is_sampled(id, 0.5, seed)).collect::<Vec<_>>();
+        assert_eq!(sample(7), sample(7));
+        assert_ne!(sample(7), sample(8));
+    }
+}
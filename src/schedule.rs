@@ -0,0 +1,286 @@
+// When watch mode runs the ingestion: at a fixed interval, or at the times matching a cron expression
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    // Runs start `interval` after the previous one started
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    // When to start the run following the one that started at `last_start`. Runs that took longer than the
+    // interval are followed right away by the next one.
+    pub fn next_run(&self, last_start: DateTime<Local>, now: DateTime<Local>) -> anyhow::Result<DateTime<Local>> {
+        match self {
+            Schedule::Every(interval) => {
+                let next = last_start + chrono::Duration::from_std(*interval)?;
+                Ok(next.max(now))
+            }
+            Schedule::Cron(cron) => cron.next_after(now)
+                .ok_or_else(|| anyhow!("The cron expression '{}' doesn't match any date", cron.expression)),
+        }
+    }
+}
+
+// A standard 5-field cron expression, "minute hour day-of-month month day-of-week", in local time. Fields
+// are '*', numbers, ranges and lists, with an optional step: "0 3 * * *", "*/15 8-18 * * 1-5", "0 0 1,15 * *".
+// Like cron, if both the day of month and the day of week are restricted, a day matching either one matches.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    // Bit n is set if value n matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, name: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        let step = match step.map(str::parse::<u32>) {
+            None => 1,
+            Some(Ok(step)) if step > 0 => step,
+            Some(_) => return Err(anyhow!("invalid step '{}' in {} field", part, name)),
+        };
+        let number = |value: &str| match value.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(anyhow!("invalid {} '{}', expecting {} to {}", name, value, min, max)),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // "5/10" is "5-max/10"
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(anyhow!("invalid {} range '{}'", name, range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> anyhow::Result<Cron> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if let [minutes, hours, days, months, weekdays] = fields[..] {
+            let mut weekdays_bits = parse_field(weekdays, "day of week", 0, 7)?;
+            // Both 0 and 7 are Sunday
+            if weekdays_bits & (1 << 7) != 0 {
+                weekdays_bits |= 1;
+            }
+            let cron = Cron {
+                expression: expression.to_string(),
+                minutes: parse_field(minutes, "minute", 0, 59)?,
+                hours: parse_field(hours, "hour", 0, 23)?,
+                days: parse_field(days, "day of month", 1, 31)?,
+                months: parse_field(months, "month", 1, 12)?,
+                weekdays: weekdays_bits,
+                any_day: days.starts_with('*'),
+                any_weekday: weekdays.starts_with('*'),
+            };
+            if cron.next_after(Local::now()).is_none() {
+                return Err(anyhow!("doesn't match any date"));
+            }
+            Ok(cron)
+        } else {
+            Err(anyhow!("expecting 5 fields: minute hour day-of-month month day-of-week"))
+        }
+    }
+}
+
+fn is_set(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Cron {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = is_set(self.days, date.day());
+        let weekday = is_set(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    // The first matching minute after `time`, looking up to 5 years ahead
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.naive_local().date().and_hms_opt(time.hour(), time.minute(), 0)?;
+        let mut time = start + chrono::Duration::minutes(1);
+        let limit = start + chrono::Duration::days(5 * 366);
+
+        while time < limit {
+            let date = time.date();
+            if !is_set(self.months, date.month()) {
+                let next_month = match date.month() {
+                    12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+                    month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+                };
+                time = next_month?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !is_set(self.hours, time.hour()) {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + chrono::Duration::hours(1);
+            } else if !is_set(self.minutes, time.minute()) {
+                time += chrono::Duration::minutes(1);
+            } else if let Some(local) = local_time(time) {
+                return Some(local);
+            } else {
+                // Skipped by a daylight saving time change
+                time += chrono::Duration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+// None if `time` is skipped by a daylight saving time change. Such times aren't always reported as missing, but
+// are then given the offset before the change, which makes them another local time.
+fn local_time(time: NaiveDateTime) -> Option<DateTime<Local>> {
+    let local = Local.from_local_datetime(&time).earliest()?;
+    (Local.from_utc_datetime(&local.naive_utc()).naive_local() == time).then_some(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The next run after local `time`, in Toulouse whose clocks go from 2:00 to 3:00 on the last Sunday of March.
+    // Local time zones are cached by thread once used, and each test has its own thread.
+    fn next(expression: &str, time: &str) -> String {
+        std::env::set_var("TZ", "Europe/Paris");
+        let cron: Cron = expression.parse().unwrap();
+        let time = local_time(NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()).unwrap();
+        cron.next_after(time).unwrap().to_rfc3339()
+    }
+
+    fn values(bits: u64) -> Vec<u32> {
+        (0..64).filter(|&value| is_set(bits, value)).collect()
+    }
+
+    #[test]
+    fn fields_are_steps_ranges_and_lists() {
+        let fields = [
+            ("*", 1, 12, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+            ("*/15", 0, 59, vec![0, 15, 30, 45]),
+            ("5/20", 0, 59, vec![5, 25, 45]),
+            ("8-11", 0, 23, vec![8, 9, 10, 11]),
+            ("1-10/3", 1, 31, vec![1, 4, 7, 10]),
+            ("1,15", 1, 31, vec![1, 15]),
+            ("0,30-32,50-59/5", 0, 59, vec![0, 30, 31, 32, 50, 55]),
+            ("7", 0, 7, vec![7]),
+        ];
+        for (field, min, max, expected) in fields {
+            assert_eq!(values(parse_field(field, "field", min, max).unwrap()), expected, "{}", field);
+        }
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let fields = [
+            ("60", 0, 59, "invalid minute '60', expecting 0 to 59"),
+            ("0", 1, 31, "invalid minute '0', expecting 1 to 31"),
+            ("1-13", 1, 12, "invalid minute '13', expecting 1 to 12"),
+            ("x", 0, 59, "invalid minute 'x', expecting 0 to 59"),
+            ("", 0, 59, "invalid minute '', expecting 0 to 59"),
+            ("1,", 0, 59, "invalid minute '', expecting 0 to 59"),
+            ("1-", 0, 59, "invalid minute '', expecting 0 to 59"),
+            ("*/0", 0, 59, "invalid step '*/0' in minute field"),
+            ("*/x", 0, 59, "invalid step '*/x' in minute field"),
+            ("30-10", 0, 59, "invalid minute range '30-10'"),
+        ];
+        for (field, min, max, message) in fields {
+            assert_eq!(parse_field(field, "minute", min, max).unwrap_err().to_string(), message, "{}", field);
+        }
+
+        let expressions = [
+            ("0 3 * *", "expecting 5 fields: minute hour day-of-month month day-of-week"),
+            ("0 3 * * * *", "expecting 5 fields: minute hour day-of-month month day-of-week"),
+            ("0 24 * * *", "invalid hour '24', expecting 0 to 23"),
+            ("0 3 * * 8", "invalid day of week '8', expecting 0 to 7"),
+            ("0 3 31 2 *", "doesn't match any date"),
+        ];
+        for (expression, message) in expressions {
+            assert_eq!(expression.parse::<Cron>().unwrap_err().to_string(), message, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        for expression in ["0 9 * * 0", "0 9 * * 7", "0 9 * * 6-7"] {
+            let cron: Cron = expression.parse().unwrap();
+            assert!(is_set(cron.weekdays, 0), "{}", expression);
+        }
+        // From a Saturday
+        assert_eq!(next("0 9 * * 7", "2024-01-06 12:00"), "2024-01-07T09:00:00+01:00");
+        assert_eq!(next("0 9 * * 0", "2024-01-06 12:00"), "2024-01-07T09:00:00+01:00");
+    }
+
+    #[test]
+    fn days_match_the_day_of_month_or_of_week_when_both_are_restricted() {
+        let days = [
+            // Friday 13
+            ("0 0 13 * 5", "2024-09-13", true),
+            ("0 0 13 * 5", "2024-09-20", true),
+            ("0 0 13 * 5", "2024-10-13", true),
+            ("0 0 13 * 5", "2024-09-12", false),
+            // Only one of them is restricted
+            ("0 0 13 * *", "2024-09-20", false),
+            ("0 0 13 * *", "2024-10-13", true),
+            ("0 0 * * 5", "2024-10-13", false),
+            ("0 0 * * 5", "2024-09-20", true),
+            ("0 0 */2 * *", "2024-09-13", true),
+            ("0 0 */2 * *", "2024-09-14", false),
+        ];
+        for (expression, date, matches) in days {
+            let cron: Cron = expression.parse().unwrap();
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+            assert_eq!(cron.day_matches(date), matches, "{} on {}", expression, date);
+        }
+    }
+
+    #[test]
+    fn next_runs_are_the_first_matching_minute_after() {
+        let runs = [
+            ("*/15 * * * *", "2024-01-08 06:00", "2024-01-08T06:15:00+01:00"),
+            ("*/15 * * * *", "2024-01-08 06:14", "2024-01-08T06:15:00+01:00"),
+            ("30 23 * * *", "2024-01-08 23:30", "2024-01-09T23:30:00+01:00"),
+            ("0 8-18/2 * * 1-5", "2024-01-08 18:30", "2024-01-09T08:00:00+01:00"),
+            ("0 8-18/2 * * 1-5", "2024-01-12 19:00", "2024-01-15T08:00:00+01:00"),
+            // End of months and years
+            ("0 3 1 * *", "2024-01-31 10:00", "2024-02-01T03:00:00+01:00"),
+            ("0 3 31 * *", "2024-04-01 00:00", "2024-05-31T03:00:00+02:00"),
+            ("0 0 1 1 *", "2024-12-31 23:59", "2025-01-01T00:00:00+01:00"),
+            ("0 0 29 2 *", "2024-03-01 00:00", "2028-02-29T00:00:00+01:00"),
+            ("0 6 * 12 *", "2023-12-31 07:00", "2024-12-01T06:00:00+01:00"),
+        ];
+        for (expression, time, expected) in runs {
+            assert_eq!(next(expression, time), expected, "{} after {}", expression, time);
+        }
+    }
+
+    #[test]
+    fn times_skipped_by_daylight_saving_time_are_not_run() {
+        // 2:00 to 2:59 don't exist on 2024-03-31
+        assert_eq!(next("*/15 * * * *", "2024-03-31 01:50"), "2024-03-31T03:00:00+02:00");
+        assert_eq!(next("30 2 * * *", "2024-03-30 12:00"), "2024-04-01T02:30:00+02:00");
+        assert_eq!(next("0 3 * * *", "2024-03-31 01:59"), "2024-03-31T03:00:00+02:00");
+    }
+}
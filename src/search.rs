@@ -0,0 +1,245 @@
+use crate::telemetry::timed;
+use crate::transform::{BoundingBox, IndexedPlace};
+use anyhow::anyhow;
+use elasticsearch::http::StatusCode;
+use elasticsearch::{Elasticsearch, GetParts, SearchParts};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+// Number of places requested at once by `query_places`
+const QUERY_PAGE_SIZE: usize = 1000;
+
+// A place found by a search, along with its distance to the search location in meters
+#[derive(Debug, Serialize)]
+pub struct NearbyPlace {
+    #[serde(flatten)]
+    pub place: IndexedPlace,
+    pub distance: f64,
+}
+
+// A place found by a read command, with its relevance score if the search computes one, and its distance to the
+// search location in meters for searches near a location
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub place: IndexedPlace,
+    #[serde(rename = "_score")]
+    pub score: Option<f64>,
+    pub distance: Option<f64>,
+}
+
+impl From<NearbyPlace> for SearchResult {
+    fn from(nearby: NearbyPlace) -> SearchResult {
+        // Sorting by distance doesn't compute scores
+        SearchResult { place: nearby.place, score: None, distance: Some(nearby.distance) }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    hits: Hits,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hits {
+    hits: Vec<Hit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hit {
+    #[serde(rename = "_source")]
+    source: IndexedPlace,
+    // Null for sorted searches
+    #[serde(rename = "_score", default)]
+    score: Option<f64>,
+    // Sort values, if sorted: the distance for searches near a location, where the next page starts for queries
+    #[serde(default)]
+    sort: Vec<Value>,
+}
+
+impl Hit {
+    fn into_result(self) -> SearchResult {
+        SearchResult { place: self.source, score: self.score, distance: None }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GetResponse {
+    #[serde(rename = "_source")]
+    source: IndexedPlace,
+}
+
+// A latitude (`max` = 90) or longitude (`max` = 180) in degrees
+pub fn parse_degrees(value: &str, max: f64) -> anyhow::Result<f64> {
+    let degrees: f64 = value.parse()?;
+    if !(-max..=max).contains(&degrees) {
+        return Err(anyhow!("must be between -{} and {}", max, max));
+    }
+    Ok(degrees)
+}
+
+// An Elasticsearch distance, such as "500m" or "2km"
+pub fn parse_distance(distance: &str) -> anyhow::Result<String> {
+    const UNITS: [&str; 9] = ["mi", "yd", "ft", "in", "km", "m", "cm", "mm", "nmi"];
+
+    let number = distance.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = &distance[number.len()..];
+    if !UNITS.contains(&unit) {
+        return Err(anyhow!("unit must be one of {}", UNITS.join(", ")));
+    }
+    match number.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok(distance.to_string()),
+        _ => Err(anyhow!("expecting a positive number followed by a unit, such as 500m or 2km")),
+    }
+}
+
+// Leaves out the places that are no longer in the source data, kept as inactive by --soft-delete, unless
+// `include_inactive`. Documents indexed by older versions have no `active` field, and are active.
+fn inactive_filter(include_inactive: bool) -> Value {
+    if include_inactive { json!([]) } else { json!([{ "term": { "active": false } }]) }
+}
+
+// Find the `limit` places closest to (`lat`, `lon`) within `radius` (an Elasticsearch distance like "2km")
+#[instrument(name = "search", skip(es_client), fields(duration_ms))]
+pub async fn search_near(
+    es_client: &Elasticsearch,
+    index: &str,
+    lat: f64,
+    lon: f64,
+    radius: &str,
+    limit: usize,
+    include_inactive: bool
+) -> anyhow::Result<Vec<NearbyPlace>> {
+
+    let origin = json!({ "lat": lat, "lon": lon });
+    let response = timed(es_client
+        .search(SearchParts::Index(&[index]))
+        .body(json!({
+            "size": limit,
+            "query": {
+                "bool": {
+                    "filter": {
+                        "geo_distance": { "distance": radius, "location": origin }
+                    },
+                    "must_not": inactive_filter(include_inactive)
+                }
+            },
+            "sort": [
+                { "_geo_distance": { "location": origin, "order": "asc", "unit": "m" } }
+            ]
+        }))
+        .send()).await?
+        .error_for_status_code()?
+        .json::<SearchResponse>().await?;
+
+    Ok(response.hits.hits.into_iter()
+        .map(|hit| NearbyPlace {
+            place: hit.source,
+            distance: hit.sort.first().and_then(Value::as_f64).unwrap_or_default(),
+        })
+        .collect())
+}
+
+// The places whose coverage area, computed with --compute-coverage, contains (`lat`, `lon`). There can be
+// several of them for locations on the border of two areas.
+#[instrument(name = "covering", skip(es_client), fields(duration_ms))]
+pub async fn search_covering(
+    es_client: &Elasticsearch,
+    index: &str,
+    lat: f64,
+    lon: f64,
+    include_inactive: bool
+) -> anyhow::Result<Vec<SearchResult>> {
+
+    let response = timed(es_client
+        .search(SearchParts::Index(&[index]))
+        .body(json!({
+            "query": {
+                "bool": {
+                    "filter": {
+                        "geo_shape": {
+                            "coverage": {
+                                "shape": { "type": "point", "coordinates": [lon, lat] },
+                                "relation": "intersects"
+                            }
+                        }
+                    },
+                    "must_not": inactive_filter(include_inactive)
+                }
+            }
+        }))
+        .send()).await?
+        .error_for_status_code()?
+        .json::<SearchResponse>().await?;
+
+    Ok(response.hits.hits.into_iter().map(Hit::into_result).collect())
+}
+
+// Places of `commune` (any commune if `None`) within `bbox` (anywhere if `None`), ordered by commune and street.
+// Results are fetched in pages with search_after, so that `limit` can be larger than the index's max_result_window.
+#[instrument(name = "query", skip(es_client), fields(duration_ms))]
+pub async fn query_places(
+    es_client: &Elasticsearch,
+    index: &str,
+    commune: Option<&str>,
+    bbox: Option<BoundingBox>,
+    limit: usize
+) -> anyhow::Result<Vec<SearchResult>> {
+
+    let mut filters = Vec::new();
+    if let Some(commune) = commune {
+        // The city field has a lowercase normalizer, which also applies to the term
+        filters.push(json!({ "term": { "city": commune } }));
+    }
+    if let Some(bbox) = bbox {
+        filters.push(json!({
+            "geo_bounding_box": {
+                "location": {
+                    "top_left": { "lat": bbox.max_lat, "lon": bbox.min_lon },
+                    "bottom_right": { "lat": bbox.min_lat, "lon": bbox.max_lon }
+                }
+            }
+        }));
+    }
+
+    let mut places = Vec::new();
+    let mut search_after: Option<Vec<Value>> = None;
+    while places.len() < limit {
+        let size = (limit - places.len()).min(QUERY_PAGE_SIZE);
+        let mut body = json!({
+            "size": size,
+            "query": { "bool": { "filter": filters } },
+            // The record id makes the order total, as search_after requires
+            "sort": ["city", "street.keyword", "record_id.keyword"]
+        });
+        if let Some(search_after) = search_after.take() {
+            body["search_after"] = Value::Array(search_after);
+        }
+
+        let response = timed(es_client.search(SearchParts::Index(&[index])).body(body).send()).await?
+            .error_for_status_code()?
+            .json::<SearchResponse>().await?;
+
+        let page_len = response.hits.hits.len();
+        if let Some(last) = response.hits.hits.last() {
+            search_after = Some(last.sort.clone());
+        }
+        places.extend(response.hits.hits.into_iter().map(Hit::into_result));
+        if page_len < size {
+            break;
+        }
+    }
+    Ok(places)
+}
+
+// The place with id `record_id`, if there's one
+#[instrument(name = "get", skip(es_client), fields(duration_ms))]
+pub async fn get_place(es_client: &Elasticsearch, index: &str, record_id: &str) -> anyhow::Result<Option<IndexedPlace>> {
+    let response = timed(es_client.get(GetParts::IndexId(index, record_id)).send()).await?;
+    if response.status_code() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status_code()?.json::<GetResponse>().await?;
+    Ok(Some(response.source))
+}
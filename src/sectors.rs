@@ -0,0 +1,75 @@
+// The secteurs of Toulouse, the six areas that its neighborhoods are grouped in, to tag the places of Toulouse
+// with the one they're in. The built-in outlines are simplified, a GeoJSON file with more precise ones can be
+// given with --sectors-file.
+use crate::geo::{self, GeoPoint};
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::path::Path;
+
+const BUILTIN: &str = include_str!("data/sectors.geojson");
+
+#[derive(Debug, Clone)]
+pub struct Sector {
+    pub name: String,
+    // GeoJSON polygons in (lon, lat) degrees, with their outline first and then their holes
+    polygons: Vec<Vec<Vec<(f64, f64)>>>,
+}
+
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    properties: Properties,
+    geometry: Geometry,
+}
+
+#[derive(Deserialize)]
+struct Properties {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+enum Geometry {
+    Polygon(Vec<Vec<(f64, f64)>>),
+    MultiPolygon(Vec<Vec<Vec<(f64, f64)>>>),
+}
+
+pub fn builtin() -> Vec<Sector> {
+    parse(BUILTIN).expect("invalid built-in sectors")
+}
+
+// A FeatureCollection of Polygon or MultiPolygon features, named by their "name" property
+pub fn read_sectors(path: &Path) -> anyhow::Result<Vec<Sector>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read sectors file {}", path.display()))?;
+    let sectors = parse(&text)
+        .with_context(|| format!("Invalid sectors file {}", path.display()))?;
+    if sectors.is_empty() {
+        return Err(anyhow!("Sectors file {} has no sectors", path.display()));
+    }
+    Ok(sectors)
+}
+
+fn parse(geojson: &str) -> anyhow::Result<Vec<Sector>> {
+    let collection: FeatureCollection = serde_json::from_str(geojson)?;
+    Ok(collection.features.into_iter()
+        .map(|feature| Sector {
+            name: feature.properties.name,
+            polygons: match feature.geometry {
+                Geometry::Polygon(polygon) => vec![polygon],
+                Geometry::MultiPolygon(polygons) => polygons,
+            },
+        })
+        .collect())
+}
+
+// The first of `sectors` that contains `location`: locations on the border between two sectors are in one of them
+pub fn find_sector(sectors: &[Sector], location: GeoPoint) -> Option<&Sector> {
+    sectors.iter().find(|sector| {
+        sector.polygons.iter().any(|polygon| geo::polygon_contains(polygon, location.lon, location.lat))
+    })
+}
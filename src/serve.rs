@@ -0,0 +1,218 @@
+// A read-only JSON API over the index, for frontends:
+// - GET /places?lat=43.6&lon=1.44&radius=2km&limit=5: the places closest to a location, closest first
+// - GET /places/<record_id>: a single place
+// - GET /healthz: whether Elasticsearch can be reached
+//
+// Places have the same fields as the indexed documents.
+use crate::health;
+use crate::interrupt;
+use crate::search;
+use elasticsearch::Elasticsearch;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+const DEFAULT_RADIUS: &str = "2km";
+const DEFAULT_LIMIT: usize = 5;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub addr: SocketAddr,
+    pub index: String,
+    // Origins allowed to call the API from a browser, "*" for any. Empty to disable CORS.
+    pub cors_origins: Vec<String>,
+    // How long browsers can cache preflight responses
+    pub cors_max_age: Duration,
+}
+
+struct State {
+    es_client: Elasticsearch,
+    options: ServeOptions,
+}
+
+// An error response, with a message for the caller
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> ApiError {
+        ApiError { status, message: message.into() }
+    }
+
+    // Details of Elasticsearch errors are logged, not returned
+    fn elasticsearch(err: anyhow::Error) -> ApiError {
+        error!("Elasticsearch request failed: {:#}", err);
+        ApiError::new(StatusCode::BAD_GATEWAY, "Elasticsearch request failed")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NearbyQuery {
+    lat: String,
+    lon: String,
+    radius: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct NearbyResponse {
+    places: Vec<search::NearbyPlace>,
+}
+
+// Serve the API until a signal is received. Requests in flight complete before returning.
+pub async fn serve(es_client: Elasticsearch, options: ServeOptions) -> anyhow::Result<()> {
+    let addr = options.addr;
+    let state = Arc::new(State { es_client, options });
+
+    // Connections and requests are handled concurrently, sharing the Elasticsearch client and its pool
+    let make_service = make_service_fn(move |_connection| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request)))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Listening on http://{}", server.local_addr());
+    server.with_graceful_shutdown(shutdown()).await?;
+    info!("Server stopped");
+    Ok(())
+}
+
+// Stop accepting connections on the first signal
+async fn shutdown() {
+    while !interrupt::is_interrupted() {
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+    }
+}
+
+async fn handle(state: Arc<State>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let start = Instant::now();
+    let mut response = match route(&state, &request).await {
+        Ok(response) => response,
+        Err(err) => json_response(err.status, &json!({ "error": err.message })),
+    };
+    add_cors_headers(&state.options, request.headers(), &mut response);
+
+    info!(
+        "{} {} {} {}ms",
+        request.method(), request.uri(), response.status().as_u16(), start.elapsed().as_millis()
+    );
+    Ok(response)
+}
+
+async fn route(state: &State, request: &Request<Body>) -> Result<Response<Body>, ApiError> {
+    let segments: Vec<&str> = request.uri().path().split('/').filter(|s| !s.is_empty()).collect();
+    let is_known = matches!(segments[..], ["places"] | ["places", _] | ["healthz"]);
+
+    match (request.method(), &segments[..]) {
+        (_, _) if !is_known => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+        (&Method::OPTIONS, _) => preflight(&state.options, request.headers()),
+        (&Method::GET, ["places"]) => nearby(state, request.uri().query().unwrap_or_default()).await,
+        (&Method::GET, ["places", record_id]) => {
+            let record_id = percent_decode_str(record_id).decode_utf8_lossy();
+            place(state, &record_id).await
+        }
+        (&Method::GET, ["healthz"]) => healthz(state).await,
+        _ => {
+            let body = json!({ "error": "Method not allowed" });
+            let mut response = json_response(StatusCode::METHOD_NOT_ALLOWED, &body);
+            response.headers_mut().insert(header::ALLOW, HeaderValue::from_static("GET, OPTIONS"));
+            Ok(response)
+        }
+    }
+}
+
+async fn nearby(state: &State, query: &str) -> Result<Response<Body>, ApiError> {
+    let bad_request = |message: String| ApiError::new(StatusCode::BAD_REQUEST, message);
+
+    let query: NearbyQuery = serde_urlencoded::from_str(query).map_err(|err| bad_request(err.to_string()))?;
+    let lat = search::parse_degrees(&query.lat, 90.0).map_err(|err| bad_request(format!("lat: {}", err)))?;
+    let lon = search::parse_degrees(&query.lon, 180.0).map_err(|err| bad_request(format!("lon: {}", err)))?;
+    let radius = search::parse_distance(query.radius.as_deref().unwrap_or(DEFAULT_RADIUS))
+        .map_err(|err| bad_request(format!("radius: {}", err)))?;
+    let limit = match query.limit.unwrap_or(DEFAULT_LIMIT) {
+        limit @ 1..=MAX_LIMIT => limit,
+        _ => return Err(bad_request(format!("limit: must be between 1 and {}", MAX_LIMIT))),
+    };
+
+    let places = search::search_near(&state.es_client, &state.options.index, lat, lon, &radius, limit, false).await
+        .map_err(ApiError::elasticsearch)?;
+    Ok(json_response(StatusCode::OK, &NearbyResponse { places }))
+}
+
+async fn place(state: &State, record_id: &str) -> Result<Response<Body>, ApiError> {
+    match search::get_place(&state.es_client, &state.options.index, record_id).await {
+        Ok(Some(place)) => Ok(json_response(StatusCode::OK, &place)),
+        Ok(None) => Err(ApiError::new(StatusCode::NOT_FOUND, format!("No place with id '{}'", record_id))),
+        Err(err) => Err(ApiError::elasticsearch(err)),
+    }
+}
+
+// Load balancers and orchestrators only look at the status: 503 if Elasticsearch can't serve requests
+async fn healthz(state: &State) -> Result<Response<Body>, ApiError> {
+    let response = match health::status(&state.es_client).await {
+        Ok(status) if status == "red" => {
+            json_response(StatusCode::SERVICE_UNAVAILABLE, &json!({ "status": "unavailable", "cluster": status }))
+        }
+        Ok(status) => json_response(StatusCode::OK, &json!({ "status": "ok", "cluster": status })),
+        Err(err) => {
+            error!("Elasticsearch health check failed: {:#}", err);
+            json_response(StatusCode::SERVICE_UNAVAILABLE, &json!({ "status": "unavailable" }))
+        }
+    };
+    Ok(response)
+}
+
+// The origin of the request, if it's allowed to call the API
+fn allowed_origin<'a>(options: &ServeOptions, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    options.cors_origins.iter()
+        .any(|allowed| allowed == "*" || *origin == allowed.as_str())
+        .then_some(origin)
+}
+
+fn preflight(options: &ServeOptions, headers: &HeaderMap) -> Result<Response<Body>, ApiError> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    if allowed_origin(options, headers).is_some() {
+        let response_headers = response.headers_mut();
+        response_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, OPTIONS"));
+        if let Some(requested) = headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            response_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+        }
+        response_headers.insert(header::ACCESS_CONTROL_MAX_AGE, options.cors_max_age.as_secs().into());
+    }
+    Ok(response)
+}
+
+// The allowed origin is echoed rather than using "*", so that several origins can be allowed
+fn add_cors_headers(options: &ServeOptions, request_headers: &HeaderMap, response: &mut Response<Body>) {
+    if options.cors_origins.is_empty() {
+        return;
+    }
+    let headers = response.headers_mut();
+    headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    if let Some(origin) = allowed_origin(options, request_headers) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    }
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("API responses can be serialized");
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
@@ -0,0 +1,72 @@
+use crate::index::{self, BatchOutcome};
+use crate::transform::IndexedPlace;
+use async_trait::async_trait;
+use std::io::Write;
+use std::sync::Mutex;
+
+// Where batches of documents are stored
+#[async_trait]
+pub trait Sink: Sync {
+    // Called before the first batch is sent, e.g. to create tables
+    async fn prepare(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Store `batch`, returning the documents that were rejected
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome>;
+
+    // The size of `place` in a batch, to limit the size of batches: its length in a request to the _bulk
+    // endpoint of the index
+    fn operation_len(&self, place: &IndexedPlace) -> usize {
+        index::operation_len(&index::index_action(None, &place.record_id), place)
+    }
+
+    // Called once all batches have been sent
+    async fn finish(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes documents in the bulk API format, to load them later with `curl -H 'Content-Type: application/x-ndjson'
+// --data-binary @bulk.ndjson $ES_URL/_bulk`:
+//   {"index":{"_index":"xmas-tree-recycling","_id":"ef89fd..."}}
+//   {"dataset_id":"collecte-des-sapins-de-noel","record_id":"ef89fd...",...}
+pub struct BulkFileSink {
+    index: String,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl BulkFileSink {
+    pub fn new(index: &str, out: Box<dyn Write + Send>) -> BulkFileSink {
+        BulkFileSink {
+            index: index.to_string(),
+            out: Mutex::new(out),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for BulkFileSink {
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        let mut out = self.out.lock().unwrap();
+        for place in &batch {
+            let action = index::index_action(Some(&self.index), &place.record_id);
+            // Each line, including the last one, ends with a newline as required by the bulk API
+            serde_json::to_writer(&mut *out, &action)?;
+            writeln!(out)?;
+            serde_json::to_writer(&mut *out, place)?;
+            writeln!(out)?;
+        }
+        Ok(BatchOutcome::default())
+    }
+
+    // The file can be loaded with any endpoint, so actions have the index name
+    fn operation_len(&self, place: &IndexedPlace) -> usize {
+        index::operation_len(&index::index_action(Some(&self.index), &place.record_id), place)
+    }
+
+    async fn finish(&self) -> anyhow::Result<()> {
+        self.out.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,684 @@
+use crate::cache::CachedData;
+use crate::client::{split_credentials, CertificateCheck};
+use crate::error::IngestError;
+use crate::progress::{Progress, Unit};
+use crate::s3::S3Source;
+use crate::retry::{http_failure, http_response, retry, Failure, RetryPolicy};
+use crate::summary;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, info_span, instrument, Instrument};
+
+// The data is an array of objects like this one (unused fields omitted)
+//   {
+//     "datasetid": "collecte-des-sapins-de-noel",
+//     "recordid": "ef89fdb5cbb3b397d2988b7d23c1fee5199b989c",
+//     "fields": {
+//       "commune": "TOULOUSE",
+//       "adresse": "88 all Jean Jaurès / angle rue Riquet",
+//       "geo_point_2d": [
+//         43.6089310498,
+//         1.45385907091
+//       ]
+//     }
+//   },
+
+#[derive(Debug, Deserialize)]
+pub struct SourcePlace {
+    pub datasetid: String,
+    pub recordid: String,
+    pub fields: SourceFields,
+    // A copy of `fields` as they were in the source data, see `keep_raw_fields`
+    #[serde(skip)]
+    pub raw_fields: Option<JsonValue>,
+}
+
+// Some records in the export have missing fields, so that all of them are optional. Other datasets have
+// additional fields, that are kept in `extra`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commune: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adresse: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo_point_2d: Option<(f64, f64)>, // lat, lon
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, JsonValue>,
+}
+
+// The Records API v2.1 returns pages of records like this one (unused fields omitted)
+//   {
+//     "total_count": 102,
+//     "results": [
+//       {
+//         "commune": "TOULOUSE",
+//         "adresse": "88 all Jean Jaurès / angle rue Riquet",
+//         "geo_point_2d": { "lon": 1.45385907091, "lat": 43.6089310498 }
+//       }
+//     ]
+//   }
+
+#[derive(Debug, Deserialize)]
+struct RecordsPage {
+    total_count: usize,
+    results: Vec<Record>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Record {
+    pub commune: Option<String>,
+    pub adresse: Option<String>,
+    pub geo_point_2d: Option<GeoPoint>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, JsonValue>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// Maximum page size allowed by the Records API
+const PAGE_SIZE: usize = 100;
+
+impl Record {
+    // Records API results have no record id: like the export's record ids, we use a hash of the content
+    // so that loading the same data twice doesn't create duplicates.
+    pub fn into_source_place(self, dataset_id: &str) -> SourcePlace {
+        let mut hasher = Sha1::new();
+        for field in [&self.commune, &self.adresse] {
+            hasher.update(field.as_deref().unwrap_or_default());
+            hasher.update([0]);
+        }
+        if let Some(point) = &self.geo_point_2d {
+            hasher.update(format!("{},{}", point.lat, point.lon));
+        }
+        let recordid = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+
+        SourcePlace {
+            datasetid: dataset_id.to_string(),
+            recordid,
+            fields: SourceFields {
+                commune: self.commune,
+                adresse: self.adresse,
+                geo_point_2d: self.geo_point_2d.map(|point| (point.lat, point.lon)),
+                extra: self.extra,
+            },
+            raw_fields: None,
+        }
+    }
+}
+
+// Portals ask API consumers to identify themselves
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// HTTP client for the data portal. `timeout` is for the whole request, including reading the response, and
+// `headers` are sent with all requests, such as an API key.
+pub fn http_client(
+    timeout: Duration,
+    proxy: Option<&str>,
+    certificates: &CertificateCheck,
+    headers: HeaderMap
+) -> anyhow::Result<reqwest::Client> {
+    // The JSON export is about 10 times smaller once compressed
+    let builder = reqwest::Client::builder()
+        .connect_timeout(timeout.min(Duration::from_secs(10)))
+        .timeout(timeout)
+        .gzip(true)
+        .brotli(true)
+        .user_agent(USER_AGENT)
+        .default_headers(headers);
+    let mut builder = certificates.configure(builder)?;
+    // Without an explicit proxy, the proxies of the environment are used
+    if let Some(proxy) = proxy {
+        let (url, credentials) = split_credentials(proxy)?;
+        let mut proxy = reqwest::Proxy::all(url)?;
+        if let Some((username, password)) = credentials {
+            proxy = proxy.basic_auth(&username, &password);
+        }
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+// Source records fetched from the data portal
+#[derive(Debug)]
+pub struct Fetched {
+    pub places: Vec<SourcePlace>,
+    // The server said that the cached data is still current
+    pub from_cache: bool,
+    // Whether the data differs from the cached data, if there was some
+    pub changed: Option<bool>,
+    // The data to cache, if it was downloaded
+    pub downloaded: Option<CachedData>,
+}
+
+// Fetch the Opendatasoft export at `url`. If there's a `cached` copy, the request is conditional and the
+// cached data is used if it's still current.
+#[instrument(name = "fetch", skip(client, retry_policy, cached), fields(duration_ms))]
+pub async fn fetch_places(
+    client: &reqwest::Client,
+    url: &str,
+    retry_policy: &RetryPolicy,
+    cached: Option<&CachedData>
+) -> anyhow::Result<Fetched> {
+    info!("Fetching xmas tree recycling data");
+
+    match (get(client, url, retry_policy, cached).await?, cached) {
+        (Some(downloaded), _) => Ok(Fetched {
+            places: parse_places(&downloaded.body)?,
+            from_cache: false,
+            changed: cached.map(|cached| cached.body != downloaded.body),
+            downloaded: Some(downloaded),
+        }),
+        (None, Some(cached)) => {
+            info!("Data not modified, using the cached copy");
+            Ok(Fetched { places: parse_places(&cached.body)?, from_cache: true, changed: Some(false), downloaded: None })
+        }
+        (None, None) => Err(anyhow!("Unexpected 'not modified' response for an unconditional request")),
+    }
+}
+
+// Download `url`, or return `None` if the server says that the `cached` data is still current
+async fn get(
+    client: &reqwest::Client,
+    url: &str,
+    retry_policy: &RetryPolicy,
+    cached: Option<&CachedData>
+) -> anyhow::Result<Option<CachedData>> {
+    retry(retry_policy, "fetch data", || async move {
+        let mut request = client.get(url);
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = http_response(request.send().await.map_err(http_failure)?)?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = expect_json(response).await?;
+
+        let header = |name| response.headers().get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string);
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let body = download(response).await.map_err(http_failure)?;
+        Ok(Some(CachedData { etag, last_modified, body }))
+    }).await
+}
+
+// Longest part of a response body that isn't JSON included in errors
+const MAX_BODY_IN_ERRORS: usize = 200;
+
+// The portal sometimes serves an HTML maintenance page with a 200 status: report it, with the start of the page,
+// rather than a JSON syntax error. Responses without a content type, or with a generic binary one, are parsed as
+// JSON anyway.
+async fn expect_json(response: reqwest::Response) -> Result<reqwest::Response, Failure> {
+    let content_type = match response.headers().get(reqwest::header::CONTENT_TYPE) {
+        Some(content_type) => String::from_utf8_lossy(content_type.as_bytes()).to_string(),
+        None => return Ok(response),
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    if essence.ends_with("/json") || essence.ends_with("+json") || essence == "application/octet-stream" {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    let body = response.text().await.map_err(http_failure)?;
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut start: String = body.chars().take(MAX_BODY_IN_ERRORS).collect();
+    if start.len() < body.len() {
+        start.push_str("...");
+    }
+    Err(Failure::Permanent(anyhow!(
+        "Expected JSON data from {}, but got a {} response, such as a maintenance page: {}",
+        url, content_type, start
+    )))
+}
+
+// Read the response body, showing progress for large datasets
+async fn download(mut response: reqwest::Response) -> Result<Vec<u8>, reqwest::Error> {
+    let progress = Progress::new("Downloading", Unit::Bytes, response.content_length());
+    let mut body = Vec::with_capacity(response.content_length().unwrap_or_default() as usize);
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        progress.inc(chunk.len() as u64);
+        summary::add_downloaded(chunk.len() as u64);
+    }
+
+    let duration = progress.finish();
+    info!("Downloaded {} bytes in {:.1}s", body.len(), duration.as_secs_f64());
+    Ok(body)
+}
+
+// Fetch all records from the Records API v2.1 endpoint at `url` (".../datasets/{id}/records"), page by page
+#[instrument(name = "fetch", skip(client, retry_policy))]
+pub async fn fetch_records(
+    client: &reqwest::Client,
+    url: &str,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<Vec<SourcePlace>> {
+    info!("Fetching xmas tree recycling data from the Records API");
+
+    let base_url = reqwest::Url::parse(url)?;
+    let dataset_id = base_url.path_segments()
+        .and_then(|segments| segments.skip_while(|segment| *segment != "datasets").nth(1))
+        .ok_or_else(|| anyhow!("Cannot find the dataset id in {}", url))?
+        .to_string();
+
+    let mut places = Vec::new();
+    let mut progress = None;
+    loop {
+        let mut page_url = base_url.clone();
+        page_url.query_pairs_mut()
+            .append_pair("limit", &PAGE_SIZE.to_string())
+            .append_pair("offset", &places.len().to_string());
+        let page_url = &page_url;
+
+        let offset = places.len();
+        let data = retry(retry_policy, "fetch data", || async move {
+            // Rate limits apply to each page
+            let response = http_response(client.get(page_url.clone()).send().await.map_err(http_failure)?)?;
+            let response = expect_json(response).await?;
+            let data = response.bytes().await.map_err(http_failure)?;
+            summary::add_downloaded(data.len() as u64);
+            Ok(data.to_vec())
+        })
+        .instrument(info_span!("page", offset, duration_ms = tracing::field::Empty))
+        .await?;
+
+        let page: RecordsPage = serde_json::from_slice(&data).map_err(|err| IngestError::Parse(err.into()))?;
+        let last_page = page.results.is_empty();
+        let progress = progress.get_or_insert_with(|| {
+            Progress::new("Fetching records", Unit::Documents, Some(page.total_count as u64))
+        });
+        progress.inc(page.results.len() as u64);
+        places.extend(page.results.into_iter().map(|record| record.into_source_place(&dataset_id)));
+
+        if last_page || places.len() >= page.total_count {
+            break;
+        }
+    }
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    Ok(places)
+}
+
+// Where the source data comes from. The scheme of the location decides which implementation is used, see `open`.
+#[async_trait]
+pub trait Source: std::fmt::Display + Send + Sync {
+    // The raw data
+    async fn fetch(&self) -> anyhow::Result<Vec<u8>>;
+
+    // The records of the Opendatasoft export
+    async fn fetch_places(&self) -> anyhow::Result<Vec<SourcePlace>> {
+        info!("Fetching xmas tree recycling data from {}", self);
+        let data = self.fetch().await?;
+        parse_places(&data)
+    }
+}
+
+// The metadata of a dataset, as returned by the Dataset API v1 (unused fields omitted)
+//   {
+//     "datasetid": "collecte-des-sapins-de-noel",
+//     "metas": {
+//       "modified": "2023-12-18T09:41:03+00:00",
+//       "data_processed": "2023-12-18T09:41:05+00:00",
+//       ...
+//     }
+//   }
+#[derive(Debug, Deserialize)]
+pub struct DatasetMetadata {
+    pub datasetid: String,
+    pub metas: DatasetMetas,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DatasetMetas {
+    // When the data or metadata of the dataset was last modified
+    pub modified: Option<DateTime<Utc>>,
+    // When the data was last processed by the portal
+    pub data_processed: Option<DateTime<Utc>>,
+}
+
+impl DatasetMetadata {
+    // The most recent of the modification times, if there's one
+    pub fn modified(&self) -> Option<DateTime<Utc>> {
+        self.metas.modified.max(self.metas.data_processed)
+    }
+}
+
+// Fetch the metadata of a dataset from the Dataset API v1 endpoint at `url` (".../api/datasets/1.0/{id}/")
+#[instrument(name = "metadata", skip(client, retry_policy), fields(duration_ms))]
+pub async fn fetch_metadata(
+    client: &reqwest::Client,
+    url: &str,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<DatasetMetadata> {
+    let data = retry(retry_policy, "fetch the dataset metadata", || async move {
+        let response = http_response(client.get(url).send().await.map_err(http_failure)?)?;
+        expect_json(response).await?.bytes().await.map_err(http_failure)
+    }).await?;
+    serde_json::from_slice(&data).with_context(|| format!("Invalid dataset metadata from {}", url))
+}
+
+pub fn is_http(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+// The source for `location`: an http(s) URL, an s3://bucket/key object, a file:// URL or a file path ('-' for
+// stdin)
+pub fn open(location: &str, client: &reqwest::Client, retry_policy: &RetryPolicy) -> anyhow::Result<Box<dyn Source>> {
+    if is_http(location) {
+        Ok(Box::new(UrlSource { client: client.clone(), url: location.to_string(), retry_policy: *retry_policy }))
+    } else if location.starts_with("s3://") {
+        Ok(Box::new(S3Source::new(location, retry_policy)?))
+    } else if location.starts_with("file://") {
+        let path = reqwest::Url::parse(location)?.to_file_path()
+            .map_err(|_| anyhow!("Invalid file URL {}", location))?;
+        Ok(Box::new(FileSource { path }))
+    } else if let Some((scheme, _)) = location.split_once("://") {
+        Err(anyhow!("Unsupported data location {}: '{}' is not http, https, s3 or file", location, scheme))
+    } else {
+        Ok(Box::new(FileSource { path: PathBuf::from(location) }))
+    }
+}
+
+// A file on the data portal or any web server
+pub struct UrlSource {
+    client: reqwest::Client,
+    url: String,
+    retry_policy: RetryPolicy,
+}
+
+impl std::fmt::Display for UrlSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "URL {}", self.url)
+    }
+}
+
+#[async_trait]
+impl Source for UrlSource {
+    async fn fetch(&self) -> anyhow::Result<Vec<u8>> {
+        let downloaded = get(&self.client, &self.url, &self.retry_policy, None).await
+            .with_context(|| format!("Failed to fetch {}", self))?;
+        downloaded.map(|downloaded| downloaded.body)
+            .ok_or_else(|| anyhow!("Unexpected 'not modified' response for an unconditional request"))
+    }
+}
+
+// A local file, or stdin if the path is "-"
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.path.as_os_str() == "-" {
+            f.write_str("stdin")
+        } else {
+            write!(f, "file {}", self.path.display())
+        }
+    }
+}
+
+#[async_trait]
+impl Source for FileSource {
+    async fn fetch(&self) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if self.path.as_os_str() == "-" {
+            std::io::stdin().lock().read_to_end(&mut data)
+        } else {
+            std::fs::File::open(&self.path).and_then(|mut file| file.read_to_end(&mut data))
+        }.with_context(|| format!("Failed to read {}", self))?;
+        Ok(data)
+    }
+
+    // Parsed as it's read, whatever the size of the file
+    async fn fetch_places(&self) -> anyhow::Result<Vec<SourcePlace>> {
+        read_places(&self.path)
+    }
+}
+
+// Read an Opendatasoft export from a file, or from stdin if `path` is "-"
+pub fn read_places(path: &Path) -> anyhow::Result<Vec<SourcePlace>> {
+    let mut places = Vec::new();
+    read_places_with(path, |place| {
+        places.push(place);
+        Ok(())
+    })?;
+    Ok(places)
+}
+
+// Like `parse_places_with`, from a file or stdin. The file is parsed as it's read.
+#[instrument(name = "fetch", skip_all, fields(path = %path.display()))]
+pub fn read_places_with(path: &Path, mut f: impl FnMut(SourcePlace) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    // Errors of `f` and invalid data errors are reported as is
+    let mut failed = false;
+    let f = |place| f(place).inspect_err(|_| failed = true);
+    let read_error = |what: String, failed: bool| move |err: anyhow::Error| {
+        if failed || err.is::<IngestError>() { err } else { err.context(what) }
+    };
+
+    if path.as_os_str() == "-" {
+        info!("Reading xmas tree recycling data from stdin");
+        let result = parse_places_with(std::io::stdin().lock(), f);
+        result.map_err(read_error("Failed to read data from stdin".to_string(), failed))
+    } else {
+        info!("Reading xmas tree recycling data from {}", path.display());
+        let what = format!("Failed to read data file {}", path.display());
+        let file = std::fs::File::open(path).context(what.clone())?;
+        let result = parse_places_with(BufReader::new(file), f);
+        result.map_err(read_error(what, failed))
+    }
+}
+
+pub fn parse_places(data: &[u8]) -> anyhow::Result<Vec<SourcePlace>> {
+    let mut places = Vec::new();
+    parse_places_with(data, |place| {
+        places.push(place);
+        Ok(())
+    })?;
+    Ok(places)
+}
+
+// Parse a JSON array of records from `reader`, calling `f` with each record as soon as it's parsed. Only
+// one record at a time is in memory, whatever the size of the data. Parsing stops if `f` fails.
+pub fn parse_places_with(
+    reader: impl Read,
+    f: impl FnMut(SourcePlace) -> anyhow::Result<()>
+) -> anyhow::Result<()> {
+    // Errors of `f` are kept as is, outside of the deserializer that can only carry messages
+    struct PlacesVisitor<'a, F> {
+        f: F,
+        error: &'a mut Option<anyhow::Error>,
+    }
+
+    impl<'de, F: FnMut(SourcePlace) -> anyhow::Result<()>> serde::de::Visitor<'de> for PlacesVisitor<'_, F> {
+        type Value = ();
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("an array of records")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+            while let Some(place) = seq.next_element()? {
+                if let Err(err) = (self.f)(place) {
+                    *self.error = Some(err);
+                    return Err(serde::de::Error::custom("stopped by the caller"));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let mut error = None;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = serde::Deserializer::deserialize_seq(&mut deserializer, PlacesVisitor { f, error: &mut error })
+        .and_then(|()| deserializer.end());
+    if let Some(err) = error {
+        return Err(err);
+    }
+    result.map_err(|err| {
+        // I/O errors are failures to get the data, not invalid data
+        if err.is_io() { anyhow::Error::from(err) } else { IngestError::Parse(err.into()).into() }
+    })
+}
+
+// Keep a copy of the fields of each record before they're mapped and transformed, to be stored as is
+pub fn keep_raw_fields(places: &mut [SourcePlace]) {
+    for place in places {
+        place.raw_fields = serde_json::to_value(&place.fields).ok();
+    }
+}
+
+// Fields of the source records that we don't know about, with the number of records that have them. New
+// fields are added to datasets over time, and may be worth indexing.
+pub fn unknown_fields(places: &[SourcePlace]) -> BTreeMap<String, usize> {
+    let mut fields = BTreeMap::new();
+    for place in places {
+        for name in place.fields.extra.keys() {
+            *fields.entry(name.clone()).or_default() += 1;
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn reads_an_export_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/places.json");
+        let places = read_places(&path).unwrap();
+        assert_eq!(places.len(), 12);
+        assert_eq!(places[0].fields.commune.as_deref(), Some("TOULOUSE"));
+        assert_eq!(places[0].fields.geo_point_2d, Some((43.6089310498, 1.45385907091)));
+    }
+
+    #[test]
+    fn a_missing_file_is_a_read_error() {
+        let err = read_places(Path::new("does/not/exist.json")).unwrap_err();
+        assert!(!err.is::<IngestError>());
+        assert_eq!(err.to_string(), "Failed to read data file does/not/exist.json");
+    }
+
+    #[test]
+    fn invalid_json_is_a_parse_error() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(br#"[{"datasetid": "d", "recordid": "1", "fields": {}}, {"datasetid": "#).unwrap();
+        let err = read_places(file.path()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<IngestError>(), Some(IngestError::Parse(_))), "{:?}", err);
+    }
+
+    #[test]
+    fn data_that_is_not_an_array_is_a_parse_error() {
+        let err = parse_places(br#"{"error": "dataset not found"}"#).unwrap_err();
+        assert!(matches!(err.downcast_ref::<IngestError>(), Some(IngestError::Parse(_))), "{:?}", err);
+    }
+
+    // A JSON array of `count` records, generated as it's read
+    struct SyntheticExport {
+        count: usize,
+        next: usize,
+        pending: Vec<u8>,
+        bytes_read: Arc<AtomicUsize>,
+    }
+
+    impl Read for SyntheticExport {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() && self.next <= self.count + 1 {
+                self.pending = match self.next {
+                    0 => b"[".to_vec(),
+                    n if n > self.count => b"]".to_vec(),
+                    n => {
+                        let adresse = format!("{} r de la Gare", n);
+                        let fields = json!({ "commune": "TOULOUSE", "adresse": adresse, "geo_point_2d": [43.6, 1.44] });
+                        let record = json!({ "datasetid": "d", "recordid": n.to_string(), "fields": fields });
+                        format!("{}{}", if n == 1 { "" } else { "," }, record).into_bytes()
+                    }
+                };
+                self.next += 1;
+            }
+            let len = buf.len().min(self.pending.len());
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            self.bytes_read.fetch_add(len, Ordering::Relaxed);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn large_exports_are_parsed_as_they_are_read() {
+        let bytes_read = Arc::new(AtomicUsize::new(0));
+        let export = SyntheticExport { count: 100_000, next: 0, pending: Vec::new(), bytes_read: bytes_read.clone() };
+
+        let mut count = 0;
+        let mut read_at_first_record = 0;
+        parse_places_with(std::io::BufReader::new(export), |place| {
+            count += 1;
+            if count == 1 {
+                read_at_first_record = bytes_read.load(Ordering::Relaxed);
+            }
+            assert_eq!(place.recordid, count.to_string());
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(count, 100_000);
+        // The first record was handled when only the start of the data was read
+        let total = bytes_read.load(Ordering::Relaxed);
+        assert!(total > 10_000_000, "{}", total);
+        assert!(read_at_first_record <= 8192, "{}", read_at_first_record);
+    }
+
+    #[test]
+    fn errors_of_the_callback_are_returned_as_is() {
+        #[derive(Debug)]
+        struct Stop;
+        impl std::fmt::Display for Stop {
+            fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("stop")
+            }
+        }
+        impl std::error::Error for Stop {}
+
+        let data = include_bytes!("../tests/fixtures/places.json");
+        let mut count = 0;
+        let err = parse_places_with(&data[..], |_| {
+            count += 1;
+            if count == 2 { Err(Stop.into()) } else { Ok(()) }
+        }).unwrap_err();
+        assert_eq!(count, 2);
+        assert!(err.is::<Stop>(), "{:#}", err);
+
+        // Without the context of read errors
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/places.json");
+        let err = read_places_with(&path, |_| Err(Stop.into())).unwrap_err();
+        assert!(err.is::<Stop>(), "{:#}", err);
+        assert_eq!(format!("{:#}", err), "stop");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::geo::GeoPoint;
+use crate::index::BatchOutcome;
+use crate::sink::Sink;
+use crate::transform::IndexedPlace;
+use anyhow::Context;
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Writes documents to the `places` table of an SQLite database, for scripts and desktop GIS tools such as QGIS
+// that don't need Elasticsearch. Places are identified by their record id, so loading the data again updates
+// them in place.
+pub struct SqliteSink {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path) -> anyhow::Result<SqliteSink> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Cannot open SQLite database {}", path.display()))?;
+        Ok(SqliteSink { connection: Mutex::new(connection) })
+    }
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute_batch(
+            "CREATE TABLE IF NOT EXISTS places (
+                record_id TEXT PRIMARY KEY,
+                city TEXT NOT NULL,
+                street TEXT NOT NULL,
+                lat REAL NOT NULL,
+                lon REAL NOT NULL,
+                indexed_at TEXT NOT NULL
+            )"
+        )?;
+        Ok(())
+    }
+
+    // Each batch is written in a transaction: it's much faster than a transaction per row, and an error leaves
+    // the previous batches in place
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO places (record_id, city, street, lat, lon, indexed_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (record_id) DO UPDATE SET
+                   city = excluded.city, street = excluded.street, lat = excluded.lat, lon = excluded.lon,
+                   indexed_at = excluded.indexed_at"
+            )?;
+            for place in &batch {
+                let GeoPoint { lat, lon } = place.location;
+                insert.execute(params![
+                    place.record_id, place.city, place.street, lat, lon, place.indexed_at.to_rfc3339()
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(BatchOutcome::default())
+    }
+}
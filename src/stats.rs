@@ -0,0 +1,124 @@
+use crate::transform::IndexedPlace;
+use elasticsearch::{Elasticsearch, SearchParts};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::instrument;
+
+// Number of collection places in a commune
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CityCount {
+    pub city: String,
+    pub count: u64,
+}
+
+// Largest number of communes we expect (Toulouse Métropole has 37)
+const MAX_CITIES: usize = 1000;
+
+// Count places per commune, most places first
+pub fn count_by_city(places: &[IndexedPlace]) -> Vec<CityCount> {
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for place in places {
+        *counts.entry(&place.city).or_default() += 1;
+    }
+
+    let mut counts: Vec<CityCount> = counts.into_iter()
+        .map(|(city, count)| CityCount { city: city.to_string(), count })
+        .collect();
+    sort_counts(&mut counts);
+    counts
+}
+
+// Communes that are in `before` but no longer in `after`
+pub fn dropped_cities<'a>(before: &'a [CityCount], after: &[CityCount]) -> Vec<&'a CityCount> {
+    before.iter()
+        .filter(|previous| {
+            let city = previous.city.to_lowercase();
+            !after.iter().any(|current| current.city.to_lowercase() == city)
+        })
+        .collect()
+}
+
+fn sort_counts(counts: &mut [CityCount]) {
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.city.cmp(&b.city)));
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsResponse {
+    aggregations: Aggregations,
+}
+
+#[derive(Debug, Deserialize)]
+struct Aggregations {
+    cities: TermsAggregation,
+}
+
+#[derive(Debug, Deserialize)]
+struct TermsAggregation {
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Bucket {
+    key: String,
+    doc_count: u64,
+    name: Option<TopHits>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopHits {
+    hits: Hits,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hits {
+    hits: Vec<Hit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hit {
+    #[serde(rename = "_source")]
+    source: CitySource,
+}
+
+#[derive(Debug, Deserialize)]
+struct CitySource {
+    city: String,
+}
+
+// Count places per commune in `index`, most places first. Returns an empty list if the index doesn't exist.
+#[instrument(name = "stats", skip(es_client))]
+pub async fn index_counts_by_city(es_client: &Elasticsearch, index: &str) -> anyhow::Result<Vec<CityCount>> {
+    let response = es_client
+        .search(SearchParts::Index(&[index]))
+        .body(json!({
+            "size": 0,
+            "aggs": {
+                "cities": {
+                    "terms": { "field": "city", "size": MAX_CITIES },
+                    // The city field is normalized to lower case: get the name from one of the documents
+                    "aggs": {
+                        "name": { "top_hits": { "size": 1, "_source": ["city"] } }
+                    }
+                }
+            }
+        }))
+        .send().await?;
+
+    if response.status_code().as_u16() == 404 {
+        return Ok(Vec::new());
+    }
+
+    let response = response.error_for_status_code()?.json::<StatsResponse>().await?;
+
+    let mut counts: Vec<CityCount> = response.aggregations.cities.buckets.into_iter()
+        .map(|bucket| {
+            let city = bucket.name
+                .and_then(|name| name.hits.hits.into_iter().next())
+                .map_or(bucket.key, |hit| hit.source.city);
+            CityCount { city, count: bucket.doc_count }
+        })
+        .collect();
+    sort_counts(&mut counts);
+    Ok(counts)
+}
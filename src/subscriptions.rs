@@ -0,0 +1,179 @@
+// Residents who want to know when a collection place is set up near their home, stored as percolator queries in
+// a companion index. Incremental runs with --alert match the places that are new, or that moved, against them.
+use crate::geo::{self, GeoPoint};
+use crate::index;
+use crate::retry::{http_failure, http_response, retry, RetryPolicy};
+use crate::transform::IndexedPlace;
+use chrono::{DateTime, Utc};
+use elasticsearch::params::Refresh;
+use elasticsearch::{Elasticsearch, IndexParts, SearchParts};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+pub const DEFAULT_INDEX: &str = "xmas-tree-subscriptions";
+
+// Places matched against the subscriptions in each request, and at most how many subscriptions can match them
+const PERCOLATE_BATCH: usize = 500;
+const MAX_MATCHES: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub home: GeoPoint,
+    // An Elasticsearch distance, such as "500m"
+    pub radius: String,
+    // Webhook URL, or email address
+    pub notify: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A place that was set up within the radius of a subscription
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    pub subscription: String,
+    pub notify: String,
+    pub record_id: String,
+    pub street: String,
+    pub city: String,
+    pub location: GeoPoint,
+    // From the subscriber's home, in meters
+    pub distance: f64,
+}
+
+impl std::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f, "{}, {} is {:.0} m away from subscription {} ({})",
+            self.street, self.city, self.distance, self.subscription, self.notify
+        )
+    }
+}
+
+pub fn index_definition() -> JsonValue {
+    json!({
+        "mappings": {
+            "properties": {
+                "query": { "type": "percolator" },
+                // The field of the places that the queries are about
+                "location": { "type": "geo_point" },
+                "home": { "type": "geo_point" },
+                "radius": { "type": "keyword" },
+                "notify": { "type": "keyword" },
+                "created_at": { "type": "date" }
+            }
+        }
+    })
+}
+
+// Store `subscription` in `index`, which is created if it doesn't exist yet, and return its id
+#[instrument(name = "subscribe", skip(es_client, subscription, retry_policy))]
+pub async fn subscribe(
+    es_client: &Elasticsearch,
+    index: &str,
+    subscription: &Subscription,
+    retry_policy: &RetryPolicy
+) -> anyhow::Result<String> {
+    if !index::index_exists(es_client, index).await? {
+        index::create_index(es_client, index, &index_definition(), retry_policy).await?;
+    }
+
+    let mut document = serde_json::to_value(subscription)?;
+    let home = json!({ "lat": subscription.home.lat, "lon": subscription.home.lon });
+    document["query"] = json!({ "geo_distance": { "distance": subscription.radius, "location": home } });
+    let id = Uuid::new_v4().simple().to_string();
+    es_client.index(IndexParts::IndexId(index, &id))
+        .refresh(Refresh::WaitFor)
+        .body(document)
+        .send().await?
+        .error_for_status_code()?;
+    Ok(id)
+}
+
+#[derive(Debug, Deserialize)]
+struct PercolateResponse {
+    hits: PercolateHits,
+}
+
+#[derive(Debug, Deserialize)]
+struct PercolateHits {
+    hits: Vec<PercolateHit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PercolateHit {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "_source")]
+    source: Subscription,
+    fields: PercolateFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct PercolateFields {
+    // Positions of the matching places in the request
+    #[serde(rename = "_percolator_document_slot")]
+    slots: Vec<usize>,
+}
+
+// The subscriptions of `index` that `places` are within the radius of. There are none if the index doesn't exist.
+#[instrument(name = "percolate", skip_all, fields(places = places.len()))]
+pub async fn percolate(es_client: &Elasticsearch, index: &str, places: &[IndexedPlace]) -> anyhow::Result<Vec<Alert>> {
+    if places.is_empty() || !index::index_exists(es_client, index).await? {
+        return Ok(Vec::new());
+    }
+
+    let mut alerts = Vec::new();
+    for batch in places.chunks(PERCOLATE_BATCH) {
+        let documents: Vec<JsonValue> = batch.iter().map(|place| json!({ "location": place.location })).collect();
+        let response = es_client.search(SearchParts::Index(&[index]))
+            .body(json!({
+                "size": MAX_MATCHES,
+                "_source": { "excludes": ["query"] },
+                "query": { "percolate": { "field": "query", "documents": documents } }
+            }))
+            .send().await?
+            .error_for_status_code()?
+            .json::<PercolateResponse>().await?;
+
+        if response.hits.hits.len() >= MAX_MATCHES {
+            warn!("More than {} subscriptions match, only the first ones are alerted", MAX_MATCHES);
+        }
+        for hit in response.hits.hits {
+            for slot in hit.fields.slots {
+                let place = &batch[slot];
+                alerts.push(Alert {
+                    subscription: hit.id.clone(),
+                    notify: hit.source.notify.clone(),
+                    record_id: place.record_id.clone(),
+                    street: place.street.clone(),
+                    city: place.city.clone(),
+                    location: place.location,
+                    distance: geo::distance(hit.source.home, place.location),
+                });
+            }
+        }
+    }
+    alerts.sort_by(|a, b| (&a.subscription, &a.record_id).cmp(&(&b.subscription, &b.record_id)));
+    Ok(alerts)
+}
+
+// Send `alert` to the webhook of its subscription. Returns false for subscriptions notified by email, which can't
+// be sent from here.
+pub async fn send(client: &reqwest::Client, alert: &Alert, retry_policy: &RetryPolicy) -> anyhow::Result<bool> {
+    if !is_webhook(&alert.notify) {
+        return Ok(false);
+    }
+    let body = serde_json::to_vec(alert)?;
+    retry(retry_policy, "send the alert", || {
+        let request = client.post(alert.notify.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        async move { http_response(request.send().await.map_err(http_failure)?) }
+    }).await?;
+    Ok(true)
+}
+
+pub fn is_webhook(notify: &str) -> bool {
+    notify.starts_with("http://") || notify.starts_with("https://")
+}
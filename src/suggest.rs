@@ -0,0 +1,85 @@
+use crate::telemetry::timed;
+use crate::text::{without_street_number, without_street_type};
+use crate::search::SearchResult;
+use crate::transform::IndexedPlace;
+use elasticsearch::{Elasticsearch, SearchParts};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+// Inputs of the completion field, so that an address can be found by typing its start or the start of any
+// of the words of its street name: "88 allée Jean Jaurès", "allée Jean Jaurès", "Jean Jaurès" and "Jaurès"
+pub fn suggestion_inputs(street: &str) -> Vec<String> {
+    let without_number = without_street_number(street);
+    let name = without_street_type(without_number);
+
+    let mut inputs = vec![street, without_number];
+    // Each word of the name, up to the end of the address. Words are separated by spaces, and only those
+    // that start with a letter are useful, skipping separators such as "/".
+    let mut offset = 0;
+    for word in name.split(' ') {
+        if word.starts_with(char::is_alphabetic) {
+            inputs.push(&name[offset..]);
+        }
+        offset += word.len() + 1;
+    }
+
+    inputs.retain(|input| !input.trim().is_empty());
+    inputs.dedup();
+    inputs.into_iter().map(str::to_string).collect()
+}
+
+// Completion suggester responses have a list of options for each suggestion and each input text:
+//   { "suggest": { "street": [ { "text": "jaures", "options": [ { "text": "Jean Jaurès", "_source": {...} } ] } ] } }
+#[derive(Debug, Deserialize)]
+struct SuggestResponse {
+    suggest: Suggest,
+}
+
+#[derive(Debug, Deserialize)]
+struct Suggest {
+    street: Vec<Suggestion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Suggestion {
+    options: Vec<SuggestOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SuggestOption {
+    #[serde(rename = "_source")]
+    source: IndexedPlace,
+    #[serde(rename = "_score", default)]
+    score: Option<f64>,
+}
+
+// Find up to `limit` places whose address starts with `prefix`. Accents and case are ignored.
+#[instrument(name = "suggest", skip(es_client), fields(duration_ms))]
+pub async fn suggest(
+    es_client: &Elasticsearch,
+    index: &str,
+    prefix: &str,
+    limit: usize
+) -> anyhow::Result<Vec<SearchResult>> {
+
+    let response = timed(es_client
+        .search(SearchParts::Index(&[index]))
+        .body(json!({
+            "_source": true,
+            "suggest": {
+                "street": {
+                    "prefix": prefix,
+                    "completion": { "field": "street_suggest", "size": limit }
+                }
+            }
+        }))
+        .send()).await?
+        .error_for_status_code()?
+        .json::<SuggestResponse>().await?;
+
+    Ok(response.suggest.street.into_iter()
+        .flat_map(|suggestion| suggestion.options)
+        .map(|option| SearchResult { place: option.source, score: option.score, distance: None })
+        .collect())
+}
@@ -0,0 +1,193 @@
+// Machine-readable report of an ingestion run, for schedulers and monitoring. Fields can be added, but
+// existing ones keep their name and meaning unless `schema_version` is incremented.
+//   {
+//     "schema_version": 1,
+//     "run_id": "4f0b6f9e-2c4d-4b8e-9d43-6a2f1c0e7b15",
+//     "started_at": "2026-01-02T08:00:00Z",
+//     "ended_at": "2026-01-02T08:00:03Z",
+//     "duration_ms": 3120,
+//     "bytes_downloaded": 61234,
+//     "retries": 0,
+//     "bulk_bytes": 1843210,
+//     "bulk_bytes_compressed": 160345,
+//     "datasets": [ { "dataset": "collecte-des-sapins-de-noel", "index": "xmas-tree-recycling", ... } ],
+//     "error": null
+//   }
+use crate::index::{Folding, IngestStats};
+use crate::rejects::Reject;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Process-wide counters, updated where the work happens
+static BYTES_DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static BULK_BYTES: AtomicU64 = AtomicU64::new(0);
+static BULK_BYTES_COMPRESSED: AtomicU64 = AtomicU64::new(0);
+static BULK_COMPRESSION: AtomicBool = AtomicBool::new(false);
+
+pub fn add_downloaded(bytes: u64) {
+    BYTES_DOWNLOADED.fetch_add(bytes, Ordering::Relaxed);
+}
+
+// A request that is sent again after a transient failure
+pub fn add_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+// A bulk request body of `uncompressed` bytes, sent as `sent` bytes
+pub fn add_bulk_bytes(uncompressed: u64, sent: u64, compressed: bool) {
+    BULK_BYTES.fetch_add(uncompressed, Ordering::Relaxed);
+    BULK_BYTES_COMPRESSED.fetch_add(sent, Ordering::Relaxed);
+    if compressed {
+        BULK_COMPRESSION.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub schema_version: u32,
+    // Stored in the documents of the run
+    pub run_id: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub duration_ms: u64,
+    pub bytes_downloaded: u64,
+    pub retries: u64,
+    // Size of the bulk request bodies, including retries, and what was sent if they were compressed
+    pub bulk_bytes: u64,
+    pub bulk_bytes_compressed: Option<u64>,
+    // Some datasets were only partly loaded, see their `partial` field
+    pub partial: bool,
+    // Records that were skipped or removed as duplicates, and the --rejects file they were written to
+    pub rejected: usize,
+    pub rejects_file: Option<PathBuf>,
+    pub datasets: Vec<DatasetSummary>,
+    // Datasets that failed, whose `error` field says why
+    pub failed_datasets: Vec<String>,
+    // Why the run failed, if it did
+    pub error: Option<String>,
+    #[serde(skip)]
+    start: Instant,
+}
+
+// What happened to one dataset. Counts are zero for phases that didn't run.
+#[derive(Debug, Default, Serialize)]
+pub struct DatasetSummary {
+    pub dataset: String,
+    // The index that was loaded, and the alias pointing to it in alias mode
+    pub index: Option<String>,
+    pub alias: Option<String>,
+    pub duration_ms: u64,
+    pub fetch_ms: u64,
+    pub transform_ms: u64,
+    pub bulk_ms: u64,
+    pub source_records: usize,
+    // Records without a valid location, or outside of the bounding box
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub indexed: usize,
+    // Documents that were indexed after being retried, included in `indexed`
+    pub retried: usize,
+    pub failed: usize,
+    pub deleted: usize,
+    // With --shared-index, documents of the index before and after those of the dataset were deleted
+    pub documents_before_cleanup: Option<usize>,
+    pub documents_after_cleanup: Option<usize>,
+    // Documents kept as inactive by --soft-delete, and inactive ones that are back in the source data
+    pub deactivated: usize,
+    pub reactivated: usize,
+    // Subscriptions matched by the new or moved places with --alert, and alerts POSTed to webhooks
+    pub alerts: usize,
+    pub alerts_sent: usize,
+    pub batches: usize,
+    // Only part of the places were loaded, because of --limit or --sample
+    pub partial: bool,
+    pub left_out: usize,
+    // Places of the communes left out by --commune and --exclude-commune, per commune
+    pub filtered_out: BTreeMap<String, usize>,
+    // How the folded sub-fields ignore accents, with the built-in index definition
+    pub folding: Option<Folding>,
+    pub error: Option<String>,
+    // Written to the --rejects file at the end of the run
+    #[serde(skip)]
+    pub rejects: Vec<Reject>,
+    // Of the run, which has it in its summary
+    #[serde(skip)]
+    pub run_id: String,
+}
+
+impl DatasetSummary {
+    pub fn new(dataset: &str, run_id: &str) -> DatasetSummary {
+        DatasetSummary { dataset: dataset.to_string(), run_id: run_id.to_string(), ..DatasetSummary::default() }
+    }
+
+    pub fn record_stats(&mut self, stats: &IngestStats) {
+        self.indexed = stats.indexed;
+        self.retried = stats.retried;
+        self.failed = stats.failed();
+        self.batches = stats.batches;
+    }
+}
+
+impl RunSummary {
+    pub fn start(run_id: String) -> RunSummary {
+        RunSummary {
+            schema_version: SCHEMA_VERSION,
+            run_id,
+            started_at: Utc::now(),
+            ended_at: None,
+            duration_ms: 0,
+            bytes_downloaded: 0,
+            retries: 0,
+            bulk_bytes: 0,
+            bulk_bytes_compressed: None,
+            partial: false,
+            rejected: 0,
+            rejects_file: None,
+            datasets: Vec::new(),
+            failed_datasets: Vec::new(),
+            error: None,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn finish(&mut self, error: Option<String>) {
+        self.ended_at = Some(Utc::now());
+        self.duration_ms = millis(self.start.elapsed());
+        self.bytes_downloaded = BYTES_DOWNLOADED.load(Ordering::Relaxed);
+        self.retries = RETRIES.load(Ordering::Relaxed);
+        self.bulk_bytes = BULK_BYTES.load(Ordering::Relaxed);
+        self.bulk_bytes_compressed = BULK_COMPRESSION.load(Ordering::Relaxed)
+            .then(|| BULK_BYTES_COMPRESSED.load(Ordering::Relaxed));
+        self.partial = self.datasets.iter().any(|dataset| dataset.partial);
+        self.rejected = self.datasets.iter().map(|dataset| dataset.skipped + dataset.duplicates).sum();
+        self.failed_datasets = self.datasets.iter()
+            .filter(|dataset| dataset.error.is_some())
+            .map(|dataset| dataset.dataset.clone())
+            .collect();
+        self.error = error;
+    }
+
+    // Write the summary as JSON to `path`, or to stdout if it's "-"
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        if path == Path::new("-") {
+            println!("{}", json);
+        } else {
+            let mut file = std::fs::File::create(path)?;
+            writeln!(file, "{}", json)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
@@ -0,0 +1,77 @@
+// Helpers for the tracing spans of pipeline stages, and metrics. Metrics are only recorded when built with the
+// otel feature and exported with `otel`, they're no-ops otherwise.
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::Span;
+
+// Run `future` (usually an HTTP request) and record how long it took in the `duration_ms` field of the
+// current span, if it has one
+pub async fn timed<F: Future>(future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    Span::current().record("duration_ms", start.elapsed().as_millis() as u64);
+    output
+}
+
+#[cfg(feature = "otel")]
+mod instruments {
+    use opentelemetry::metrics::{Counter, Histogram};
+    use std::sync::OnceLock;
+
+    pub struct Instruments {
+        pub indexed: Counter<u64>,
+        pub skipped: Counter<u64>,
+        pub failed: Counter<u64>,
+        pub bulk_duration: Histogram<f64>,
+    }
+
+    // Created on first use, once the exporter is set up
+    pub fn get() -> &'static Instruments {
+        static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+        INSTRUMENTS.get_or_init(|| {
+            let meter = opentelemetry::global::meter("xmas-tree-recycling");
+            Instruments {
+                indexed: meter.u64_counter("documents.indexed").with_description("Documents stored").build(),
+                skipped: meter.u64_counter("documents.skipped")
+                    .with_description("Source records without a valid location").build(),
+                failed: meter.u64_counter("documents.failed").with_description("Documents rejected").build(),
+                bulk_duration: meter.f64_histogram("bulk.duration").with_unit("ms")
+                    .with_description("Duration of bulk requests").build(),
+            }
+        })
+    }
+}
+
+pub fn add_indexed(count: usize) {
+    #[cfg(feature = "otel")]
+    instruments::get().indexed.add(count as u64, &[]);
+    #[cfg(not(feature = "otel"))]
+    let _ = count;
+}
+
+pub fn add_skipped(count: usize) {
+    #[cfg(feature = "otel")]
+    instruments::get().skipped.add(count as u64, &[]);
+    #[cfg(not(feature = "otel"))]
+    let _ = count;
+}
+
+pub fn add_failed(count: usize) {
+    #[cfg(feature = "otel")]
+    instruments::get().failed.add(count as u64, &[]);
+    #[cfg(not(feature = "otel"))]
+    let _ = count;
+}
+
+pub fn record_bulk_duration(duration: Duration) {
+    #[cfg(feature = "otel")]
+    instruments::get().bulk_duration.record(duration.as_secs_f64() * 1000.0, &[]);
+    #[cfg(not(feature = "otel"))]
+    let _ = duration;
+}
+
+// Export what hasn't been yet, before the process exits
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    crate::otel::shutdown();
+}
@@ -0,0 +1,370 @@
+// Text clean-up helpers applied to source fields
+
+// Repair text that was UTF-8 but has been decoded as Latin-1 (or its Windows-1252 superset), such as
+// "Jean JaurÃ¨s" which should be "Jean Jaurès". Text that doesn't show this pattern is returned unchanged.
+pub fn repair_mojibake(text: &str) -> String {
+    let mut text = text.to_string();
+
+    // Text that went through several bad conversions needs to be repaired several times
+    for _ in 0..3 {
+        match redecode(&text) {
+            Some(repaired) => text = repaired,
+            None => break,
+        }
+    }
+
+    text
+}
+
+// Re-encode `text` in Latin-1 and decode it as UTF-8. This only succeeds if all characters fit in one byte
+// and these bytes are valid UTF-8 multi-byte sequences, which is very unlikely for genuine Latin-1 text
+// (an accented letter is followed by a plain letter, which isn't a valid continuation byte).
+fn redecode(text: &str) -> Option<String> {
+    if text.is_ascii() {
+        return None;
+    }
+
+    let bytes = text.chars()
+        .map(latin1_byte)
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+fn latin1_byte(c: char) -> Option<u8> {
+    let code = c as u32;
+    if code <= 0xFF {
+        return Some(code as u8);
+    }
+
+    // Characters that Windows-1252 puts in the 0x80-0x9F range of Latin-1
+    let byte = match c {
+        '€' => 0x80, '‚' => 0x82, 'ƒ' => 0x83, '„' => 0x84, '…' => 0x85, '†' => 0x86, '‡' => 0x87,
+        'ˆ' => 0x88, '‰' => 0x89, 'Š' => 0x8A, '‹' => 0x8B, 'Œ' => 0x8C, 'Ž' => 0x8E,
+        '‘' => 0x91, '’' => 0x92, '“' => 0x93, '”' => 0x94, '•' => 0x95, '–' => 0x96, '—' => 0x97,
+        '˜' => 0x98, '™' => 0x99, 'š' => 0x9A, '›' => 0x9B, 'œ' => 0x9C, 'ž' => 0x9E, 'Ÿ' => 0x9F,
+        _ => return None,
+    };
+    Some(byte)
+}
+
+// Street type abbreviations used in the source addresses
+const STREET_TYPES: [(&str, &str); 7] = [
+    ("all", "allée"),
+    ("bd", "boulevard"),
+    ("av", "avenue"),
+    ("r", "rue"),
+    ("imp", "impasse"),
+    ("chem", "chemin"),
+    ("pl", "place"),
+];
+
+// Expand an abbreviated street type at the start of `street`, after the street number if any, such as
+// "88 all Jean Jaurès" which becomes "88 allée Jean Jaurès". Abbreviations must be followed by a space, so that
+// "Allemagne" is left unchanged. The case of the abbreviation is kept ("AV" becomes "AVENUE").
+pub fn expand_street_type(street: &str) -> String {
+    let start = street_number_len(street);
+    let rest = &street[start..];
+
+    let expanded = rest.split_once(' ').and_then(|(word, tail)| {
+        let lowercase = word.to_lowercase();
+        STREET_TYPES.iter()
+            .find(|(abbreviation, _)| *abbreviation == lowercase)
+            .map(|(_, street_type)| format!("{}{} {}", &street[..start], with_case_of(street_type, word), tail))
+    });
+
+    expanded.unwrap_or_else(|| street.to_string())
+}
+
+// Length of the street number at the start of `street`, including its "bis" or "ter" suffix and the
+// following space
+fn street_number_len(street: &str) -> usize {
+    let mut start = 0;
+    let mut rest = street;
+    while let Some((word, tail)) = rest.split_once(' ') {
+        let is_number = word.starts_with(|c: char| c.is_ascii_digit())
+            || (start > 0 && ["bis", "ter"].contains(&word.to_lowercase().as_str()));
+        if !is_number {
+            break;
+        }
+        start += word.len() + 1;
+        rest = tail;
+    }
+    start
+}
+
+// "88 allée Jean Jaurès" without its number: "allée Jean Jaurès"
+pub fn without_street_number(street: &str) -> &str {
+    &street[street_number_len(street)..]
+}
+
+// "allée Jean Jaurès" without its street type, abbreviated or not: "Jean Jaurès"
+pub fn without_street_type(street: &str) -> &str {
+    match street.split_once(' ') {
+        Some((word, tail)) if !tail.is_empty() => {
+            let lowercase = word.to_lowercase();
+            let is_street_type = STREET_TYPES.iter()
+                .any(|(abbreviation, street_type)| lowercase == *abbreviation || lowercase == *street_type);
+            if is_street_type { tail } else { street }
+        }
+        _ => street,
+    }
+}
+
+// Street types of the source addresses, once expanded
+const STREET_TYPE_NAMES: [&str; 20] = [
+    "allée", "avenue", "boulevard", "chemin", "cheminement", "cité", "cours", "esplanade", "impasse",
+    "lotissement", "parc", "parvis", "passage", "place", "port", "promenade", "quai", "route", "rue", "square",
+];
+
+// Components of an address such as "88 allée Jean Jaurès / angle rue Riquet". Components that can't be found
+// are missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressParts {
+    // "88", "12 bis"
+    pub house_number: Option<String>,
+    // Expanded and in lower case: "allée"
+    pub street_type: Option<String>,
+    // "Jean Jaurès"
+    pub street_name: Option<String>,
+    // What follows the first "/", usually a corner or a landmark: "angle rue Riquet"
+    pub address_note: Option<String>,
+}
+
+// Split an address into its components. Street types may be abbreviated.
+pub fn split_address(address: &str) -> AddressParts {
+    let non_empty = |text: &str| Some(text.trim()).filter(|text| !text.is_empty()).map(str::to_string);
+
+    let (street, note) = match address.split_once('/') {
+        Some((street, note)) => (street.trim(), non_empty(note)),
+        None => (address.trim(), None),
+    };
+
+    // street_number_len() includes the following space, so a lone number doesn't count
+    let number_len = street_number_len(street);
+    let (house_number, rest) = if number_len == 0 && street.starts_with(|c: char| c.is_ascii_digit()) {
+        (non_empty(street), "")
+    } else {
+        (non_empty(&street[..number_len]), street[number_len..].trim())
+    };
+
+    let (street_type, street_name) = match rest.split_once(' ') {
+        Some((word, tail)) => {
+            let lowercase = word.to_lowercase();
+            let street_type = STREET_TYPES.iter()
+                .find(|(abbreviation, _)| *abbreviation == lowercase)
+                .map(|(_, street_type)| *street_type)
+                .or_else(|| STREET_TYPE_NAMES.iter().copied().find(|street_type| *street_type == lowercase));
+            match street_type {
+                Some(street_type) => (Some(street_type.to_string()), non_empty(tail)),
+                None => (None, non_empty(rest)),
+            }
+        }
+        None => (None, non_empty(rest)),
+    };
+
+    AddressParts { house_number, street_type, street_name, address_note: note }
+}
+
+// `text` with the case of `model`: all uppercase, capitalized or lowercase
+fn with_case_of(text: &str, model: &str) -> String {
+    if model.len() > 1 && model.chars().all(|c| c.is_uppercase()) {
+        text.to_uppercase()
+    } else if model.starts_with(char::is_uppercase) {
+        let mut chars = text.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+    } else {
+        text.to_string()
+    }
+}
+
+// Words that stay in lower case in commune names, unless they start the name
+const PARTICLES: [&str; 15] = [
+    "de", "du", "des", "d", "la", "le", "les", "l", "sur", "sous", "lès", "lez", "en", "et", "aux",
+];
+
+// Convert an upper case commune name to French title case: "SAINT-ORENS-DE-GAMEVILLE" becomes
+// "Saint-Orens-de-Gameville" and "L'UNION" becomes "L'Union". Particles are kept in lower case, except at
+// the start of the name, and the word after an elided particle is capitalized ("Saint-Martin-d'Hères").
+pub fn title_case_city(city: &str) -> String {
+    let mut result = String::with_capacity(city.len());
+    let mut word = String::new();
+
+    for c in city.chars() {
+        if c == ' ' || c == '-' || c == '\'' || c == '’' {
+            push_title_case_word(&mut result, &word);
+            word.clear();
+            result.push(c);
+        } else {
+            word.push(c);
+        }
+    }
+    push_title_case_word(&mut result, &word);
+
+    result
+}
+
+fn push_title_case_word(result: &mut String, word: &str) {
+    let lowercase = word.to_lowercase();
+    if !result.is_empty() && PARTICLES.contains(&lowercase.as_str()) {
+        result.push_str(&lowercase);
+    } else {
+        let mut chars = lowercase.chars();
+        if let Some(first) = chars.next() {
+            result.extend(first.to_uppercase());
+            result.push_str(chars.as_str());
+        }
+    }
+}
+
+// Lowercase `text` and remove the accents of French letters: "Allée Jaurès" becomes "allee jaures"
+pub fn fold_accents(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.to_lowercase().chars() {
+        match c {
+            'à' | 'â' | 'ä' | 'á' => result.push('a'),
+            'é' | 'è' | 'ê' | 'ë' => result.push('e'),
+            'î' | 'ï' | 'í' => result.push('i'),
+            'ô' | 'ö' | 'ó' => result.push('o'),
+            'ù' | 'û' | 'ü' | 'ú' => result.push('u'),
+            'ÿ' => result.push('y'),
+            'ç' => result.push('c'),
+            'æ' => result.push_str("ae"),
+            'œ' => result.push_str("oe"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_address_is_repaired_and_expanded() {
+        let street = repair_mojibake("88 all Jean JaurÃ¨s");
+        assert_eq!(street, "88 all Jean Jaurès");
+        assert_eq!(expand_street_type(&street), "88 allée Jean Jaurès");
+    }
+
+    #[test]
+    fn mojibake_is_repaired() {
+        assert_eq!(repair_mojibake("JaurÃ¨s"), "Jaurès");
+        assert_eq!(repair_mojibake("allÃ©e"), "allée");
+        // Characters of the Windows-1252 range, and text that was mis-decoded twice
+        assert_eq!(repair_mojibake("rue du CÅ“ur"), "rue du Cœur");
+        assert_eq!(repair_mojibake("JaurÃƒÂ¨s"), "Jaurès");
+    }
+
+    #[test]
+    fn clean_text_is_unchanged() {
+        for text in ["88 allée Jean Jaurès", "Place du Capitole", "L'UNION", "Été à Noël", "rue du Cœur", ""] {
+            assert_eq!(repair_mojibake(text), text);
+        }
+    }
+
+    #[test]
+    fn street_types_are_expanded() {
+        let expected = [
+            ("all Jean Jaurès", "allée Jean Jaurès"),
+            ("bd de Strasbourg", "boulevard de Strasbourg"),
+            ("av du Parc", "avenue du Parc"),
+            ("r des Écoles", "rue des Écoles"),
+            ("imp des Cèdres", "impasse des Cèdres"),
+            ("chem de Bordeneuve", "chemin de Bordeneuve"),
+            ("pl de la Mairie", "place de la Mairie"),
+            // After the street number, and whatever the case of the abbreviation
+            ("12 bis Av de Toulouse", "12 bis Avenue de Toulouse"),
+            ("15 AV DE LA GARE", "15 AVENUE DE LA GARE"),
+            ("R de la Gare", "Rue de la Gare"),
+        ];
+        for (street, expanded) in expected {
+            assert_eq!(expand_street_type(street), expanded, "{}", street);
+        }
+    }
+
+    #[test]
+    fn words_that_are_not_abbreviations_are_unchanged() {
+        for street in ["Allemagne", "rue d'Allemagne", "Allée de Bellevue", "Avenue", "3 Plateau du Parc", "av", "r"] {
+            assert_eq!(expand_street_type(street), street);
+        }
+    }
+
+    #[test]
+    fn sample_communes_are_title_cased() {
+        assert_eq!(title_case_city("TOULOUSE"), "Toulouse");
+        assert_eq!(title_case_city("L'UNION"), "L'Union");
+        assert_eq!(title_case_city("SAINT-ORENS-DE-GAMEVILLE"), "Saint-Orens-de-Gameville");
+    }
+
+    #[test]
+    fn commune_names_are_title_cased() {
+        let expected = [
+            ("CASTANET-TOLOSAN", "Castanet-Tolosan"),
+            ("SAINT-MARTIN-D'HÈRES", "Saint-Martin-d'Hères"),
+            ("GAGNAC-SUR-GARONNE", "Gagnac-sur-Garonne"),
+            ("PORTET-SUR-GARONNE", "Portet-sur-Garonne"),
+            ("DRÉMIL-LAFAGE", "Drémil-Lafage"),
+            ("LABÈGE", "Labège"),
+            ("VILLENEUVE-LÈS-BOULOC", "Villeneuve-lès-Bouloc"),
+            ("LE FAUGA", "Le Fauga"),
+            ("LA SALVETAT-SAINT-GILLES", "La Salvetat-Saint-Gilles"),
+            ("LES VARENNES", "Les Varennes"),
+            ("L’UNION", "L’Union"),
+            ("toulouse", "Toulouse"),
+            ("", ""),
+        ];
+        for (city, title_case) in expected {
+            assert_eq!(title_case_city(city), title_case, "{}", city);
+        }
+    }
+
+    #[test]
+    fn addresses_are_split() {
+        // Addresses of the dataset, and variants with suffixed numbers and several slashes
+        let expected = [
+            (
+                "88 all Jean Jaurès / angle rue Riquet",
+                (Some("88"), Some("allée"), Some("Jean Jaurès"), Some("angle rue Riquet")),
+            ),
+            ("12 bd de Strasbourg", (Some("12"), Some("boulevard"), Some("de Strasbourg"), None)),
+            ("15 r de la Gare", (Some("15"), Some("rue"), Some("de la Gare"), None)),
+            ("12 r Pargaminières", (Some("12"), Some("rue"), Some("Pargaminières"), None)),
+            ("2 av du Parc", (Some("2"), Some("avenue"), Some("du Parc"), None)),
+            ("av de Toulouse", (None, Some("avenue"), Some("de Toulouse"), None)),
+            ("chem de Bordeneuve", (None, Some("chemin"), Some("de Bordeneuve"), None)),
+            ("imp des Cèdres", (None, Some("impasse"), Some("des Cèdres"), None)),
+            ("pl de la Mairie", (None, Some("place"), Some("de la Mairie"), None)),
+            ("Allée de Bellevue", (None, Some("allée"), Some("de Bellevue"), None)),
+            ("Place du Capitole", (None, Some("place"), Some("du Capitole"), None)),
+            ("Place de l'Hôtel de Ville", (None, Some("place"), Some("de l'Hôtel de Ville"), None)),
+            ("Parvis des Jacobins", (None, Some("parvis"), Some("des Jacobins"), None)),
+            ("21 bis r des Écoles", (Some("21 bis"), Some("rue"), Some("des Écoles"), None)),
+            ("3 ter chem de Bordeneuve", (Some("3 ter"), Some("chemin"), Some("de Bordeneuve"), None)),
+            ("Capitole", (None, None, Some("Capitole"), None)),
+            ("12", (Some("12"), None, None, None)),
+            (
+                "2 av du Parc / parking / entrée nord",
+                (Some("2"), Some("avenue"), Some("du Parc"), Some("parking / entrée nord")),
+            ),
+            ("  / angle rue Riquet", (None, None, None, Some("angle rue Riquet"))),
+            ("", (None, None, None, None)),
+        ];
+
+        for (address, (house_number, street_type, street_name, address_note)) in expected {
+            let parts = split_address(address);
+            assert_eq!(parts.house_number.as_deref(), house_number, "{}", address);
+            assert_eq!(parts.street_type.as_deref(), street_type, "{}", address);
+            assert_eq!(parts.street_name.as_deref(), street_name, "{}", address);
+            assert_eq!(parts.address_note.as_deref(), address_note, "{}", address);
+        }
+    }
+
+    #[test]
+    fn communes_of_the_metropole_are_title_cased() {
+        for commune in &crate::communes::COMMUNES {
+            assert_eq!(title_case_city(&commune.name.to_uppercase()), commune.name);
+        }
+    }
+}
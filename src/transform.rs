@@ -0,0 +1,764 @@
+use crate::communes;
+use crate::coverage::Polygon;
+use crate::geo::{distance, geohash, plus_code, GeoPoint};
+use crate::sectors::{find_sector, Sector};
+use crate::source::SourcePlace;
+use crate::suggest::suggestion_inputs;
+use crate::telemetry;
+use crate::text::{expand_street_type, fold_accents, repair_mojibake, split_address, title_case_city};
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use tracing::{info, instrument, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedPlace {
+    pub dataset_id: String,
+    pub record_id: String,
+    pub city: String,
+    // The commune as found in the source data
+    pub city_raw: String,
+    // Codes of the commune, if it's one of Toulouse Métropole. Missing in documents indexed by older versions.
+    #[serde(default)]
+    pub insee_code: Option<String>,
+    #[serde(default)]
+    pub postal_code: Vec<String>,
+    pub street: String,
+    // The address as found in the source data
+    pub street_raw: String,
+    // Components of `street`, when they can be found. Missing in documents indexed by older versions.
+    #[serde(default)]
+    pub house_number: Option<String>,
+    #[serde(default)]
+    pub street_type: Option<String>,
+    #[serde(default)]
+    pub street_name: Option<String>,
+    #[serde(default)]
+    pub address_note: Option<String>,
+    // Inputs of the address completion suggester
+    #[serde(default)]
+    pub street_suggest: Vec<String>,
+    pub location: GeoPoint,
+    // Location codes, to group places by map cells or share their location
+    #[serde(default)]
+    pub geohash: String,
+    #[serde(default)]
+    pub plus_code: String,
+    // From the reference point of the run, the Capitole by default, and the secteur of Toulouse places. Missing in
+    // documents indexed by older versions.
+    #[serde(default)]
+    pub distance_to_center_m: f64,
+    #[serde(default)]
+    pub sector: Option<String>,
+    // The collection season, named after its December. Missing in documents indexed by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub campaign_year: Option<i32>,
+    pub indexed_at: DateTime<Utc>,
+    // The run that stored the document, to remove it with the purge-run command. Missing in documents indexed
+    // by older versions.
+    #[serde(default)]
+    pub run_id: String,
+    // Documents of places that are no longer in the source data are kept as inactive by incremental runs with
+    // --soft-delete. Missing in documents indexed by older versions, which are active.
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed_at: Option<DateTime<Utc>>,
+    pub source_url: String,
+    // Hash of the other fields, except `indexed_at`, `run_id`, `active` and `removed_at`, to find documents that
+    // changed since the previous run. Missing in documents indexed by older versions.
+    #[serde(default)]
+    pub content_hash: String,
+    // The area closer to this place than to any other, computed with --compute-coverage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<Polygon>,
+    // The source record's fields, if requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<serde_json::Value>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+// Fields that are about the run that stored the document rather than about the place, and the hash itself
+const UNHASHED_FIELDS: [&str; 5] = ["indexed_at", "run_id", "active", "removed_at", "content_hash"];
+
+impl IndexedPlace {
+    // Hash of the document without the `UNHASHED_FIELDS`, so that fields that are added to it are part of it
+    pub fn compute_content_hash(&self) -> String {
+        let mut content = serde_json::to_value(self).expect("places can be serialized");
+        if let Some(fields) = content.as_object_mut() {
+            for field in &UNHASHED_FIELDS {
+                fields.remove(*field);
+            }
+            // [lon, lat] whatever the serialization format, so that hashes don't depend on it
+            fields.insert("location".to_string(), json!([self.location.lon, self.location.lat]));
+        }
+        let mut hasher = Sha1::new();
+        hasher.update(content.to_string());
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+// Why a source record wasn't indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SkipReason {
+    MissingLocation,
+    InvalidLocation,
+    OutOfBoundingBox,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SkipReason::MissingLocation => "missing location",
+            SkipReason::InvalidLocation => "invalid coordinates",
+            SkipReason::OutOfBoundingBox => "outside of the bounding box",
+        })
+    }
+}
+
+impl SkipReason {
+    // Machine-readable, for reject files
+    pub fn code(&self) -> &'static str {
+        match self {
+            SkipReason::MissingLocation => "missing_location",
+            SkipReason::InvalidLocation => "invalid_location",
+            SkipReason::OutOfBoundingBox => "out_of_bbox",
+        }
+    }
+}
+
+// A source record that wasn't indexed
+#[derive(Debug)]
+pub struct Skipped {
+    pub place: SourcePlace,
+    pub reason: SkipReason,
+}
+
+// An area in which all places are expected to be
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    // Roughly the area of Toulouse Métropole
+    pub const TOULOUSE_METROPOLE: BoundingBox = BoundingBox {
+        min_lon: 1.2,
+        min_lat: 43.4,
+        max_lon: 1.7,
+        max_lat: 43.8,
+    };
+
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        (self.min_lon..=self.max_lon).contains(&lon) && (self.min_lat..=self.max_lat).contains(&lat)
+    }
+}
+
+// Parses "minLon,minLat,maxLon,maxLat"
+impl FromStr for BoundingBox {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<BoundingBox> {
+        let values = s.split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("coordinates must be numbers"))?;
+
+        if let [min_lon, min_lat, max_lon, max_lat] = values[..] {
+            if min_lon > max_lon || min_lat > max_lat {
+                return Err(anyhow!("min values must be lower than max values"));
+            }
+            if !is_valid_location(min_lon, min_lat) || !is_valid_location(max_lon, max_lat) {
+                return Err(anyhow!("coordinates out of range"));
+            }
+            Ok(BoundingBox { min_lon, min_lat, max_lon, max_lat })
+        } else {
+            Err(anyhow!("expecting minLon,minLat,maxLon,maxLat"))
+        }
+    }
+}
+
+// How source records are transformed
+#[derive(Debug, Clone)]
+pub struct TransformOptions {
+    // Places outside of this area are skipped
+    pub bbox: Option<BoundingBox>,
+    // Convert upper case commune names to title case
+    pub normalize_city: bool,
+    // Number of characters of the geohash
+    pub geohash_precision: usize,
+    // The collection season the data belongs to
+    pub campaign_year: i32,
+    // Added to all documents, so that we know when, by which run and from where the data was loaded
+    pub indexed_at: DateTime<Utc>,
+    pub run_id: String,
+    pub source_url: String,
+    // Where distances to the center are measured from
+    pub center: GeoPoint,
+    pub sectors: Vec<Sector>,
+}
+
+// Trees are collected after Christmas, from late December to the end of January: a season is named after its
+// December, and data loaded during the first half of the year belongs to the previous year's season.
+pub fn campaign_year(date: DateTime<Utc>) -> i32 {
+    if date.month() >= 7 { date.year() } else { date.year() - 1 }
+}
+
+fn is_valid_location(lon: f64, lat: f64) -> bool {
+    (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat)
+}
+
+// Transform a source record into the target index format. Records without a valid location are useless on
+// a map and are skipped, while a missing address or city is indexed as an empty string. Text fields that have
+// been garbled by a bad encoding conversion are repaired, abbreviated street types are expanded and commune
+// names are converted to title case.
+pub fn transform(place: SourcePlace, options: &TransformOptions) -> Result<IndexedPlace, SkipReason> {
+    let location = check_location(&place, options)?;
+    Ok(transform_valid(place, options, location))
+}
+
+// The (lat, lon) location of a record, if it can be indexed
+pub fn check_location(place: &SourcePlace, options: &TransformOptions) -> Result<(f64, f64), SkipReason> {
+    let (lat, lon) = place.fields.geo_point_2d.ok_or(SkipReason::MissingLocation)?;
+
+    // (0, 0) is a common placeholder for unknown coordinates
+    if !is_valid_location(lon, lat) || (lon == 0.0 && lat == 0.0) {
+        return Err(SkipReason::InvalidLocation);
+    }
+    if options.bbox.is_some_and(|bbox| !bbox.contains(lon, lat)) {
+        return Err(SkipReason::OutOfBoundingBox);
+    }
+    Ok((lat, lon))
+}
+
+fn transform_valid(place: SourcePlace, options: &TransformOptions, (lat, lon): (f64, f64)) -> IndexedPlace {
+
+    let street = place.fields.adresse.as_deref()
+        .map(|street| expand_street_type(&repair_mojibake(street)))
+        .unwrap_or_default();
+
+    let city = place.fields.commune.as_deref()
+        .map(|city| {
+            let city = repair_mojibake(city);
+            if options.normalize_city { title_case_city(&city) } else { city }
+        })
+        .unwrap_or_default();
+    let commune = communes::find(&city);
+    let address = split_address(&street);
+    let location = GeoPoint::new(lat, lon);
+    // The sectors of other communes would be those of the places of Toulouse next to them
+    let sector = commune
+        .filter(|commune| commune.name == "Toulouse")
+        .and_then(|_| find_sector(&options.sectors, location))
+        .map(|sector| sector.name.clone());
+
+    let mut indexed_place = IndexedPlace {
+        dataset_id: place.datasetid,
+        record_id: place.recordid,
+        city,
+        city_raw: place.fields.commune.unwrap_or_default(),
+        insee_code: commune.map(|commune| commune.insee_code.to_string()),
+        postal_code: commune
+            .map(|commune| commune.postal_codes.iter().map(|code| code.to_string()).collect())
+            .unwrap_or_default(),
+        street_suggest: suggestion_inputs(&street),
+        street,
+        street_raw: place.fields.adresse.unwrap_or_default(),
+        house_number: address.house_number,
+        street_type: address.street_type,
+        street_name: address.street_name,
+        address_note: address.address_note,
+        location,
+        geohash: geohash(lon, lat, options.geohash_precision),
+        plus_code: plus_code(lon, lat),
+        distance_to_center_m: distance(options.center, location),
+        sector,
+        campaign_year: Some(options.campaign_year),
+        indexed_at: options.indexed_at,
+        run_id: options.run_id.clone(),
+        active: true,
+        removed_at: None,
+        source_url: options.source_url.clone(),
+        content_hash: String::new(),
+        coverage: None,
+        raw: place.raw_fields,
+    };
+    indexed_place.content_hash = indexed_place.compute_content_hash();
+    indexed_place
+}
+
+// Transform all source records, logging those that were skipped and why. Skipped records are also returned.
+#[instrument(name = "transform", skip_all, fields(records = places.len()))]
+pub fn transform_places(places: Vec<SourcePlace>, options: &TransformOptions) -> (Vec<IndexedPlace>, Vec<Skipped>) {
+    let mut indexed_places = Vec::with_capacity(places.len());
+    let mut skipped = Vec::new();
+    let mut reason_counts: HashMap<SkipReason, usize> = HashMap::new();
+    let mut unknown_communes = BTreeSet::new();
+
+    for place in places {
+        match check_location(&place, options) {
+            Ok(location) => {
+                let indexed_place = transform_valid(place, options, location);
+                if indexed_place.insee_code.is_none() && !indexed_place.city.is_empty() {
+                    unknown_communes.insert(indexed_place.city.clone());
+                }
+                indexed_places.push(indexed_place)
+            }
+            Err(reason) => {
+                let record_id = &place.recordid;
+                match place.fields.geo_point_2d {
+                    Some((lat, lon)) => warn!(record_id = %record_id, "Skipping record at ({}, {}): {}", lat, lon, reason),
+                    None => warn!(record_id = %record_id, "Skipping record: {}", reason),
+                }
+                *reason_counts.entry(reason).or_default() += 1;
+                skipped.push(Skipped { place, reason });
+            }
+        }
+    }
+
+    telemetry::add_skipped(skipped.len());
+    if !reason_counts.is_empty() {
+        let mut reasons: Vec<_> = reason_counts.into_iter().collect();
+        reasons.sort();
+        let reasons: Vec<_> = reasons.iter()
+            .map(|(reason, count)| format!("{} {}", count, reason))
+            .collect();
+        warn!("Skipped records: {}", reasons.join(", "));
+    }
+    if !unknown_communes.is_empty() {
+        let names: Vec<_> = unknown_communes.into_iter().collect();
+        warn!("Communes not in Toulouse Métropole, indexed without INSEE and postal codes: {}", names.join(", "));
+    }
+    info!("Transformed {} records", indexed_places.len());
+
+    (indexed_places, skipped)
+}
+
+// Sort places by commune, street and record id, so that exports of the same data are identical whatever the
+// order of the source records. Case and accents are ignored so that "Allée" and "allée" sort together: the exact
+// spelling and the content hash only break ties, such as those of duplicate records.
+pub fn sort_by_address(places: &mut [IndexedPlace]) {
+    places.sort_by_cached_key(|place| {
+        (
+            fold_accents(&place.city), fold_accents(&place.street),
+            place.record_id.clone(), place.city.clone(), place.street.clone(), place.content_hash.clone(),
+        )
+    });
+}
+
+// Sort places by record id, and by content hash for duplicate records
+pub fn sort_by_record_id(places: &mut [IndexedPlace]) {
+    places.sort_by(|a, b| (&a.record_id, &a.content_hash).cmp(&(&b.record_id, &b.content_hash)));
+}
+
+// Communes whose places are kept, compared ignoring case and accents: only those of `include` if it's not empty,
+// and never those of `exclude`
+#[derive(Debug, Clone, Default)]
+pub struct CommuneFilter {
+    // Folded names, and the names as they were given
+    include: BTreeMap<String, String>,
+    exclude: BTreeMap<String, String>,
+}
+
+impl CommuneFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<CommuneFilter> {
+        let names = |names: &[String]| names.iter()
+            .map(|name| (fold_accents(name.trim()), name.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let filter = CommuneFilter { include: names(include), exclude: names(exclude) };
+        if let Some(both) = filter.include.iter().find(|(key, _)| filter.exclude.contains_key(*key)) {
+            return Err(anyhow!("Commune {} can't be both included and excluded", both.1));
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn keeps(&self, city: &str) -> bool {
+        let city = fold_accents(city.trim());
+        (self.include.is_empty() || self.include.contains_key(&city)) && !self.exclude.contains_key(&city)
+    }
+}
+
+// Keep the places of the communes of `filter`, and return the number of places left out per commune
+#[instrument(name = "communes", skip_all)]
+pub fn filter_communes(
+    places: Vec<IndexedPlace>,
+    filter: &CommuneFilter
+) -> (Vec<IndexedPlace>, BTreeMap<String, usize>) {
+    let mut left_out = BTreeMap::new();
+    let mut found = BTreeSet::new();
+    let kept: Vec<IndexedPlace> = places.into_iter()
+        .filter(|place| {
+            if filter.keeps(&place.city) {
+                found.insert(fold_accents(place.city.trim()));
+                true
+            } else {
+                *left_out.entry(place.city.clone()).or_insert(0) += 1;
+                false
+            }
+        })
+        .collect();
+
+    // Most likely misspelled
+    let missing: Vec<&str> = filter.include.iter()
+        .filter(|(key, _)| !found.contains(*key))
+        .map(|(_, name)| name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        warn!("No places in the included communes {}", missing.join(", "));
+    }
+    if !left_out.is_empty() {
+        info!("Left out {} places of other communes", left_out.values().sum::<usize>());
+    }
+    (kept, left_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::parse_places;
+    use serde_json::Value as JsonValue;
+    use std::sync::{Arc, Mutex};
+
+    fn options() -> TransformOptions {
+        TransformOptions {
+            bbox: Some(BoundingBox::TOULOUSE_METROPOLE),
+            normalize_city: true,
+            geohash_precision: 7,
+            campaign_year: 2023,
+            indexed_at: "2024-01-08T06:00:00Z".parse().unwrap(),
+            run_id: "test-run".to_string(),
+            source_url: "file:places.json".to_string(),
+            center: GeoPoint::new(43.6045, 1.444),
+            sectors: Vec::new(),
+        }
+    }
+
+    fn source_place(commune: &str, adresse: &str, (lat, lon): (f64, f64)) -> SourcePlace {
+        let record = json!({
+            "datasetid": "collecte-des-sapins-de-noel",
+            "recordid": "ef89fdb5cbb3b397d2988b7d23c1fee5199b989c",
+            "fields": { "commune": commune, "adresse": adresse, "geo_point_2d": [lat, lon] }
+        });
+        serde_json::from_value(record).unwrap()
+    }
+
+    #[test]
+    fn source_coordinates_are_swapped_to_lon_lat() {
+        let place = source_place("TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469));
+        let indexed = transform(place, &options()).unwrap();
+        assert_eq!(indexed.location, GeoPoint::new(43.6044622, 1.4442469));
+
+        let document = serde_json::to_value(&indexed).unwrap();
+        assert_eq!(document["location"], json!([1.4442469, 43.6044622]));
+    }
+
+    #[test]
+    fn source_fields_are_renamed() {
+        let place = source_place("L'UNION", "r des Écoles", (43.6575302, 1.4820693));
+        let document = serde_json::to_value(transform(place, &options()).unwrap()).unwrap();
+        assert_eq!(document["dataset_id"], "collecte-des-sapins-de-noel");
+        assert_eq!(document["record_id"], "ef89fdb5cbb3b397d2988b7d23c1fee5199b989c");
+        assert_eq!(document["city"], "L'Union");
+        assert_eq!(document["city_raw"], "L'UNION");
+        assert_eq!(document["street"], "rue des Écoles");
+        assert_eq!(document["street_raw"], "r des Écoles");
+        assert_eq!(document["insee_code"], "31561");
+        for field in ["datasetid", "recordid", "fields", "commune", "adresse", "geo_point_2d"] {
+            assert!(document.get(field).is_none(), "{} is in {}", field, document);
+        }
+    }
+
+    #[test]
+    fn text_fields_are_cleaned_up() {
+        let place = source_place("SAINT-ORENS-DE-GAMEVILLE", "88 all Jean JaurÃ¨s", (43.554, 1.533));
+        let indexed = transform(place, &options()).unwrap();
+        assert_eq!(indexed.street, "88 allée Jean Jaurès");
+        assert_eq!(indexed.street_raw, "88 all Jean JaurÃ¨s");
+        assert_eq!(indexed.city, "Saint-Orens-de-Gameville");
+
+        let place = source_place("Lâ€™UNION", "all des Ã‰coles", (43.6575302, 1.4820693));
+        let indexed = transform(place, &options()).unwrap();
+        assert_eq!(indexed.street, "allée des Écoles");
+        assert_eq!(indexed.city, "L’Union");
+
+        let options = TransformOptions { normalize_city: false, ..options() };
+        let place = source_place("SAINT-ORENS-DE-GAMEVILLE", "88 all Jean JaurÃ¨s", (43.554, 1.533));
+        assert_eq!(transform(place, &options).unwrap().city, "SAINT-ORENS-DE-GAMEVILLE");
+    }
+
+    #[test]
+    fn raw_source_fields_round_trip() {
+        let data = include_bytes!("../tests/fixtures/places.json");
+        let source: Vec<serde_json::Value> = serde_json::from_slice(data).unwrap();
+        let mut places = parse_places(data).unwrap();
+        crate::source::keep_raw_fields(&mut places);
+        let (indexed, _) = transform_places(places, &options());
+        assert_eq!(indexed.len(), source.len());
+
+        for (place, record) in indexed.iter().zip(&source) {
+            let document = serde_json::to_string(place).unwrap();
+            let stored: IndexedPlace = serde_json::from_str(&document).unwrap();
+            assert_eq!(stored.raw.as_ref(), Some(&record["fields"]));
+        }
+    }
+
+    #[test]
+    fn raw_source_fields_include_unknown_fields() {
+        let record = json!({
+            "datasetid": "collecte-des-sapins-de-noel",
+            "recordid": "ef89fdb5cbb3b397d2988b7d23c1fee5199b989c",
+            "fields": {
+                "commune": "TOULOUSE", "adresse": "Place du Capitole", "geo_point_2d": [43.6044622, 1.4442469],
+                "horaires": "du 26 décembre au 31 janvier", "capacite": 40
+            }
+        });
+        let mut place: SourcePlace = serde_json::from_value(record.clone()).unwrap();
+        crate::source::keep_raw_fields(std::slice::from_mut(&mut place));
+        assert_eq!(transform(place, &options()).unwrap().raw, Some(record["fields"].clone()));
+
+        // Not kept by default
+        let place: SourcePlace = serde_json::from_value(record).unwrap();
+        let document = serde_json::to_value(transform(place, &options()).unwrap()).unwrap();
+        assert!(document.get("raw").is_none());
+    }
+
+    #[test]
+    fn content_hash_changes_with_the_geohash_precision() {
+        let place = || source_place("TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469));
+        let hash = transform(place(), &options()).unwrap().content_hash;
+        assert_eq!(transform(place(), &options()).unwrap().content_hash, hash);
+
+        let options = TransformOptions { geohash_precision: 9, ..options() };
+        assert_ne!(transform(place(), &options).unwrap().content_hash, hash);
+    }
+
+    #[test]
+    fn content_hash_changes_with_the_campaign() {
+        let place = || source_place("TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469));
+        let hash = transform(place(), &options()).unwrap().content_hash;
+
+        let options = TransformOptions { campaign_year: 2024, ..options() };
+        assert_ne!(transform(place(), &options).unwrap().content_hash, hash);
+    }
+
+    // Another value of the same type
+    fn changed(value: &JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Null => json!("changed"),
+            JsonValue::Bool(value) => json!(!value),
+            JsonValue::Number(number) => match number.as_i64() {
+                Some(number) => json!(number + 1),
+                None => json!(number.as_f64().unwrap() + 1.0),
+            },
+            JsonValue::String(value) => match value.parse::<DateTime<Utc>>() {
+                Ok(date) => json!(date + chrono::Duration::days(1)),
+                Err(_) => json!(format!("{} changed", value)),
+            },
+            JsonValue::Array(values) if values.is_empty() => json!(["changed"]),
+            JsonValue::Array(values) => {
+                let mut values = values.clone();
+                values[0] = changed(&values[0]);
+                JsonValue::Array(values)
+            }
+            JsonValue::Object(_) => json!({ "changed": true }),
+        }
+    }
+
+    #[test]
+    fn content_hash_changes_with_every_field_but_those_of_the_run() {
+        let mut place = transform(source_place("TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469)), &options())
+            .unwrap();
+        place.raw = Some(json!({ "commune": "TOULOUSE" }));
+        place.removed_at = Some("2024-01-08T06:00:00Z".parse().unwrap());
+        let document = serde_json::to_value(&place).unwrap();
+        let hash = place.compute_content_hash();
+
+        for (field, value) in document.as_object().unwrap() {
+            let mut changed_document = document.clone();
+            changed_document[field] = changed(value);
+            let changed_place: IndexedPlace = serde_json::from_value(changed_document).unwrap();
+            let unhashed = UNHASHED_FIELDS.contains(&field.as_str());
+            assert_eq!(changed_place.compute_content_hash() == hash, unhashed, "{}", field);
+        }
+    }
+
+    #[test]
+    fn content_hash_changes_with_the_center_and_sectors() {
+        let place = || source_place("TOULOUSE", "Place du Capitole", (43.6044622, 1.4442469));
+        let hash = transform(place(), &options()).unwrap().content_hash;
+
+        let options_with_center = TransformOptions { center: GeoPoint::new(43.6109, 1.4997), ..options() };
+        assert_ne!(transform(place(), &options_with_center).unwrap().content_hash, hash);
+        let options_with_sectors = TransformOptions { sectors: crate::sectors::builtin(), ..options() };
+        let indexed = transform(place(), &options_with_sectors).unwrap();
+        assert!(indexed.sector.is_some());
+        assert_ne!(indexed.content_hash, hash);
+    }
+
+    #[test]
+    fn records_without_a_valid_location_are_skipped() {
+        let places = parse_places(include_bytes!("../tests/fixtures/partial.json")).unwrap();
+        let (indexed, skipped) = transform_places(places, &options());
+
+        let ids: Vec<&str> = indexed.iter().map(|place| &place.record_id[..4]).collect();
+        assert_eq!(ids, ["3c1b", "7f4a", "9b6c"]);
+        let reasons: Vec<(&str, SkipReason)> = skipped.iter()
+            .map(|skipped| (&skipped.place.recordid[..4], skipped.reason))
+            .collect();
+        assert_eq!(reasons, [
+            ("5d2e", SkipReason::MissingLocation),
+            ("1a8e", SkipReason::InvalidLocation),
+            ("2b9f", SkipReason::MissingLocation),
+            ("4d1b", SkipReason::OutOfBoundingBox),
+        ]);
+    }
+
+    #[test]
+    fn records_without_an_address_or_commune_are_indexed_with_empty_fields() {
+        let places = parse_places(include_bytes!("../tests/fixtures/partial.json")).unwrap();
+        let (indexed, _) = transform_places(places, &options());
+
+        let no_address = &indexed[1];
+        assert_eq!((no_address.city.as_str(), no_address.street.as_str()), ("Colomiers", ""));
+        assert_eq!(no_address.street_name, None);
+        let no_commune = &indexed[2];
+        assert_eq!((no_commune.city.as_str(), no_commune.street.as_str()), ("", "Place du Capitole"));
+        assert_eq!(no_commune.insee_code, None);
+    }
+
+    #[test]
+    fn bounding_box_check_can_be_disabled() {
+        let places = parse_places(include_bytes!("../tests/fixtures/partial.json")).unwrap();
+        let (indexed, skipped) = transform_places(places, &TransformOptions { bbox: None, ..options() });
+        assert_eq!(indexed.len(), 4);
+        assert_eq!(skipped.len(), 3);
+    }
+
+    #[test]
+    fn places_are_sorted_by_address_ignoring_case_and_accents() {
+        let place = |commune, adresse, record_id: &str| {
+            let mut place = transform(source_place(commune, adresse, (43.6044622, 1.4442469)), &options()).unwrap();
+            place.record_id = record_id.to_string();
+            place
+        };
+        let mut places = vec![
+            place("TOULOUSE", "allée des Pins", "c"),
+            place("BALMA", "r des Écoles", "d"),
+            place("TOULOUSE", "Allée des Pins", "a"),
+            place("TOULOUSE", "Allée de Bellevue", "e"),
+            place("BALMA", "r des Ecoles", "b"),
+        ];
+
+        sort_by_address(&mut places);
+        let sorted: Vec<(&str, &str, &str)> = places.iter()
+            .map(|place| (place.city.as_str(), place.street.as_str(), place.record_id.as_str()))
+            .collect();
+        assert_eq!(sorted, [
+            ("Balma", "rue des Ecoles", "b"),
+            ("Balma", "rue des Écoles", "d"),
+            ("Toulouse", "Allée de Bellevue", "e"),
+            ("Toulouse", "Allée des Pins", "a"),
+            ("Toulouse", "allée des Pins", "c"),
+        ]);
+
+        sort_by_record_id(&mut places);
+        let record_ids: Vec<&str> = places.iter().map(|place| place.record_id.as_str()).collect();
+        assert_eq!(record_ids, ["a", "b", "c", "d", "e"]);
+    }
+
+    fn places_in(cities: &[&str]) -> Vec<IndexedPlace> {
+        cities.iter()
+            .map(|city| source_place(city, "Place du Capitole", (43.6044622, 1.4442469)))
+            .map(|place| transform(place, &options()).unwrap())
+            .collect()
+    }
+
+    // What `f` logs
+    fn logs(f: impl FnOnce()) -> String {
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || Buffer(writer.clone()))
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let logs = logs.lock().unwrap();
+        String::from_utf8(logs.clone()).unwrap()
+    }
+
+    #[test]
+    fn communes_cannot_be_both_included_and_excluded() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let error = CommuneFilter::new(&names(&["Balma", "Toulouse"]), &names(&[" TOULOUSE"])).err().unwrap();
+        assert_eq!(error.to_string(), "Commune Toulouse can't be both included and excluded");
+        assert!(CommuneFilter::new(&names(&["Toulouse"]), &names(&["Balma"])).is_ok());
+    }
+
+    #[test]
+    fn communes_are_matched_ignoring_case_and_accents() {
+        let include = ["drémil-lafage".to_string(), "TOULOUSE".to_string()];
+        let filter = CommuneFilter::new(&include, &[]).unwrap();
+        for city in ["Drémil-Lafage", "DREMIL-LAFAGE", "Toulouse", " toulouse "] {
+            assert!(filter.keeps(city), "{}", city);
+        }
+        assert!(!filter.keeps("Balma"));
+
+        let filter = CommuneFilter::new(&[], &["Saint-Orens-de-Gameville".to_string()]).unwrap();
+        assert!(!filter.keeps("SAINT-ORENS-DE-GAMEVILLE"));
+        assert!(filter.keeps("Toulouse"));
+    }
+
+    #[test]
+    fn places_left_out_are_counted_per_commune() {
+        let filter = CommuneFilter::new(&["Toulouse".to_string()], &[]).unwrap();
+        let (kept, left_out) = filter_communes(places_in(&["TOULOUSE", "BALMA", "L'UNION", "BALMA"]), &filter);
+        let cities: Vec<&str> = kept.iter().map(|place| place.city.as_str()).collect();
+        assert_eq!(cities, ["Toulouse"]);
+        let expected = BTreeMap::from([("Balma".to_string(), 2), ("L'Union".to_string(), 1)]);
+        assert_eq!(left_out, expected);
+
+        let filter = CommuneFilter::new(&[], &["balma".to_string()]).unwrap();
+        let (kept, left_out) = filter_communes(places_in(&["TOULOUSE", "BALMA", "L'UNION"]), &filter);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(left_out, BTreeMap::from([("Balma".to_string(), 1)]));
+    }
+
+    #[test]
+    fn included_communes_without_places_are_warned_about() {
+        let include = ["Toulouse".to_string(), "Tolouse".to_string(), "Blagnac".to_string()];
+        let filter = CommuneFilter::new(&include, &[]).unwrap();
+        let output = logs(|| {
+            filter_communes(places_in(&["TOULOUSE", "BALMA"]), &filter);
+        });
+        assert!(output.contains("No places in the included communes Blagnac, Tolouse\n"), "{}", output);
+
+        let filter = CommuneFilter::new(&include[..1], &[]).unwrap();
+        let output = logs(|| {
+            filter_communes(places_in(&["TOULOUSE", "BALMA"]), &filter);
+        });
+        assert!(!output.contains("No places in the included communes"), "{}", output);
+    }
+}
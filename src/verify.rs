@@ -0,0 +1,85 @@
+use crate::geo::GeoPoint;
+use crate::transform::IndexedPlace;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+// The fields that come from the source data. Run metadata like `indexed_at` is expected to differ.
+pub const COMPARED_FIELDS: [&str; 6] = ["dataset_id", "city", "city_raw", "street", "street_raw", "location"];
+
+// Differences between the source data and the documents in the index
+#[derive(Debug, Default, Serialize)]
+pub struct Discrepancies {
+    // Record ids of the source data that aren't in the index
+    pub missing: Vec<String>,
+    // Record ids of documents that aren't in the source data
+    pub extra: Vec<String>,
+    pub different: Vec<DifferentDocument>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DifferentDocument {
+    pub record_id: String,
+    pub fields: Vec<FieldDifference>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldDifference {
+    pub field: String,
+    pub source: JsonValue,
+    pub index: JsonValue,
+}
+
+impl Discrepancies {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} missing, {} extra, {} different", self.missing.len(), self.extra.len(), self.different.len())
+    }
+}
+
+// Compare the transformed source `places` with the `documents` of the index, keyed by record id
+pub fn compare(places: &[IndexedPlace], mut documents: HashMap<String, JsonValue>) -> anyhow::Result<Discrepancies> {
+    let mut discrepancies = Discrepancies::default();
+
+    for place in places {
+        let document = match documents.remove(&place.record_id) {
+            Some(document) => document,
+            None => {
+                discrepancies.missing.push(place.record_id.clone());
+                continue;
+            }
+        };
+
+        let expected = serde_json::to_value(place)?;
+        let fields: Vec<FieldDifference> = COMPARED_FIELDS.iter()
+            .filter(|field| !same_value(field, &expected[**field], &document[**field]))
+            .map(|field| FieldDifference {
+                field: field.to_string(),
+                source: expected[*field].clone(),
+                index: document[*field].clone(),
+            })
+            .collect();
+        if !fields.is_empty() {
+            discrepancies.different.push(DifferentDocument { record_id: place.record_id.clone(), fields });
+        }
+    }
+
+    discrepancies.extra = documents.into_keys().collect();
+    discrepancies.missing.sort();
+    discrepancies.extra.sort();
+    discrepancies.different.sort_by(|a, b| a.record_id.cmp(&b.record_id));
+    Ok(discrepancies)
+}
+
+// Locations are the same point whatever the format they were indexed with
+fn same_value(field: &str, expected: &JsonValue, actual: &JsonValue) -> bool {
+    if field == "location" {
+        if let (Ok(expected), Ok(actual)) = (GeoPoint::deserialize(expected), GeoPoint::deserialize(actual)) {
+            return expected == actual;
+        }
+    }
+    expected == actual
+}
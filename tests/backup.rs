@@ -0,0 +1,52 @@
+mod common;
+
+use common::{Index, MockElasticsearch};
+use serde_json::{json, Value as JsonValue};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use xmas_tree_recycling::backup;
+
+// More documents than the 1000 of a scroll page, to scroll through several pages
+fn large_index(es: &MockElasticsearch) -> BTreeMap<String, JsonValue> {
+    let docs: BTreeMap<String, JsonValue> = (0..2500)
+        .map(|n| {
+            let doc = json!({ "dataset_id": "collecte-des-sapins-de-noel", "street": format!("{} rue du Taur", n) });
+            (format!("place-{:04}", n), doc)
+        })
+        .collect();
+    let index = Index { docs: docs.clone(), mappings: json!({}), settings: json!({}) };
+    es.cluster().indices.insert("xmas-tree-recycling".to_string(), index);
+    docs
+}
+
+fn backup_files(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with("backup-xmas-tree-recycling-"))
+        .collect()
+}
+
+#[test]
+fn backups_have_all_documents_and_can_be_restored() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let docs = large_index(&es);
+
+    let input = common::fixture("places.json");
+    let output = common::run(es.url(), dir.path(), &["--input", input.to_str().unwrap(), "--yes", "--force-shrink"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+    // Pages of 1000, 500 and none after the first one
+    assert_eq!(es.cluster().scroll_pages, 3);
+    assert_eq!(es.cluster().cleared_scrolls, ["scroll-1"]);
+
+    let files = backup_files(dir.path());
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let backed_up: BTreeMap<String, JsonValue> = backup::read_backup(&files[0]).unwrap().into_iter().collect();
+    assert_eq!(backed_up, docs);
+
+    let restored = MockElasticsearch::start();
+    let output = common::run(restored.url(), dir.path(), &["restore", files[0].to_str().unwrap()]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(restored.cluster().docs("xmas-tree-recycling"), docs);
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::{MockElasticsearch, MockServer, Response};
+
+// The fixture, as served by the data portal
+fn portal() -> MockServer {
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    MockServer::start(move |_| Response::text(200, "application/json; charset=utf-8", &data))
+}
+
+#[test]
+fn loads_the_data_url_into_the_given_index() {
+    let es = MockElasticsearch::start();
+    let portal = portal();
+    let dir = tempfile::tempdir().unwrap();
+    let data_url = format!("{}/api/records/1.0/download?dataset=collecte-des-sapins-de-noel", portal.url);
+
+    let output = common::run(
+        &common::unused_url(), dir.path(),
+        &["--es-url", es.url(), "--data-url", &data_url, "--index", "staging-places", "--yes", "--no-backup"]
+    );
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    assert_eq!(portal.requests().len(), 1);
+    let cluster = es.cluster();
+    assert_eq!(cluster.indices.keys().collect::<Vec<_>>(), ["staging-places"]);
+    assert_eq!(cluster.indices["staging-places"].mappings["properties"]["location"]["type"], "geo_point");
+    assert_eq!(cluster.docs("staging-places").len(), 12);
+    drop(cluster);
+    assert!(es.server.requests_to("POST", "/_bulk").iter().all(|request| request.route() == "/staging-places/_bulk"));
+}
+
+#[test]
+fn an_empty_index_name_fails_before_any_request() {
+    let es = MockElasticsearch::start();
+    let portal = portal();
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = common::run(es.url(), dir.path(), &["--data-url", &portal.url, "--index", "", "--yes"]);
+    assert!(!output.status.success());
+    assert!(common::stderr(&output).contains("index name cannot be empty"), "{}", common::stderr(&output));
+    assert!(es.server.requests().is_empty());
+    assert!(portal.requests().is_empty());
+}
+
+#[test]
+fn help_describes_the_connection_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = common::run(&common::unused_url(), dir.path(), &["--help"]);
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    for option in ["--index <INDEX>", "--data-url <DATA_URL>", "--es-url <ES_URL>", "ELASTICSEARCH_URL"] {
+        assert!(help.contains(option), "{} is missing from the help:\n{}", option, help);
+    }
+}
+
+#[test]
+fn loads_the_data_from_stdin() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read(common::fixture("places.json")).unwrap();
+
+    let output = common::run_with_input(es.url(), dir.path(), &["--input", "-", "--yes", "--no-backup"], &data);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+}
+
+#[test]
+fn an_invalid_input_file_fails_before_the_index_is_touched() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("export.json"), "<html>Service unavailable</html>").unwrap();
+
+    let output = common::run(es.url(), dir.path(), &["--input", "export.json", "--yes", "--no-backup"]);
+    assert_eq!(output.status.code(), Some(11), "{}", common::stderr(&output));
+    assert!(common::stderr(&output).contains("Invalid source data"), "{}", common::stderr(&output));
+    assert!(es.cluster().indices.is_empty());
+}
+
+#[test]
+fn documents_are_stamped_with_the_indexing_time_and_source() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let input = input.to_str().unwrap();
+
+    let args = ["--input", input, "--stamp", "2024-01-08T06:00:00Z", "--yes", "--no-backup"];
+    let output = common::run(es.url(), dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let cluster = es.cluster();
+    let index = &cluster.indices["xmas-tree-recycling"];
+    assert_eq!(index.mappings["properties"]["indexed_at"]["type"], "date");
+    assert_eq!(index.mappings["properties"]["source_url"]["type"], "keyword");
+    assert_eq!(index.docs.len(), 12);
+    for doc in index.docs.values() {
+        assert_eq!(doc["indexed_at"], "2024-01-08T06:00:00Z");
+        assert_eq!(doc["source_url"], input);
+    }
+}
+
+#[test]
+fn stamped_output_is_deterministic() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let input = input.to_str().unwrap();
+
+    let mut outputs = Vec::new();
+    for file in ["first.ndjson", "second.ndjson"] {
+        let args = ["--input", input, "--stamp", "2024-01-08T06:00:00Z", "--run-id", "test-run", "--output", file];
+        let output = common::run(&common::unused_url(), dir.path(), &args);
+        assert!(output.status.success(), "{}", common::stderr(&output));
+        outputs.push(std::fs::read(dir.path().join(file)).unwrap());
+    }
+    assert!(!outputs[0].is_empty());
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn exit_codes_tell_the_failed_stage() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let input = input.to_str().unwrap();
+
+    let output = common::run(
+        &common::unused_url(), dir.path(),
+        &["--data-url", &common::unused_url(), "--max-attempts", "1", "--yes", "--no-backup"]
+    );
+    assert_eq!(output.status.code(), Some(10), "{}", common::stderr(&output));
+
+    let es = MockElasticsearch::start();
+    es.cluster().refuse_index_creation = true;
+    let output = common::run(es.url(), dir.path(), &["--input", input, "--max-attempts", "1", "--yes", "--no-backup"]);
+    assert_eq!(output.status.code(), Some(12), "{}", common::stderr(&output));
+
+    let es = MockElasticsearch::start();
+    es.cluster().rejected_documents.insert("2fa1497309da8469d56cd7b284cb9f9dab970db5".to_string());
+    let output = common::run(es.url(), dir.path(), &["--input", input, "--max-attempts", "1", "--yes", "--no-backup"]);
+    assert_eq!(output.status.code(), Some(13), "{}", common::stderr(&output));
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("2fa1497309da8469d56cd7b284cb9f9dab970db5"), "{}", stderr);
+}
+
+#[test]
+fn help_lists_the_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = common::run(&common::unused_url(), dir.path(), &["--help"]);
+    let help = String::from_utf8(output.stdout).unwrap();
+    for code in ["10  the source data could not be fetched", "13  some documents were rejected", "130 interrupted"] {
+        assert!(help.contains(code), "{} is missing from the help:\n{}", code, help);
+    }
+}
+
+#[test]
+fn include_raw_stores_the_source_fields_without_indexing_them() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let records: Vec<serde_json::Value> = serde_json::from_slice(&std::fs::read(&input).unwrap()).unwrap();
+
+    let args = ["--input", input.to_str().unwrap(), "--include-raw", "--yes", "--no-backup"];
+    let output = common::run(es.url(), dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let cluster = es.cluster();
+    let index = &cluster.indices["xmas-tree-recycling"];
+    assert_eq!(index.mappings["properties"]["raw"]["enabled"], false);
+    for record in &records {
+        let doc = index.docs.values().find(|doc| doc["record_id"] == record["recordid"]).unwrap();
+        assert_eq!(doc["raw"], record["fields"]);
+    }
+}
@@ -0,0 +1,833 @@
+// Helpers shared by the integration tests: an HTTP or HTTPS server that answers with a handler and records the
+// requests it got, an in-memory Elasticsearch cluster served by it, and access to the fixtures and the binary.
+#![allow(dead_code)]
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use xmas_tree_recycling::client::{self, ClientOptions};
+use xmas_tree_recycling::geo::{self, GeoPoint};
+use xmas_tree_recycling::source;
+use xmas_tree_recycling::transform::{self, IndexedPlace, TransformOptions};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    // With the query string
+    pub path: String,
+    // Names in lower case
+    pub headers: Vec<(String, String)>,
+    // Decompressed if the request was gzipped
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    // Without the query string
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or_default()
+    }
+
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.path.split_once('?')?;
+        serde_urlencoded::from_str::<Vec<(String, String)>>(query).ok()?
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json(&self) -> JsonValue {
+        serde_json::from_slice(&self.body).unwrap_or(JsonValue::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, body: JsonValue) -> Response {
+        Response::text(status, "application/json", &body.to_string())
+    }
+
+    pub fn text(status: u16, content_type: &str, body: &str) -> Response {
+        Response { status, headers: vec![("content-type".into(), content_type.into())], body: body.into() }
+    }
+
+    pub fn empty(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn gzip(mut self) -> Response {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&self.body).unwrap();
+        self.body = encoder.finish().unwrap();
+        self.header("content-encoding", "gzip")
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+// Serves requests on a random port of the loopback interface until the test process exits
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> MockServer {
+        MockServer::listen("http", handler, Some)
+    }
+
+    // Serves https with `certificate`
+    pub fn start_tls(
+        certificate: &SelfSignedCertificate,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static
+    ) -> MockServer {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+        acceptor.set_private_key(&certificate.key).unwrap();
+        acceptor.set_certificate(&certificate.certificate).unwrap();
+        let acceptor = acceptor.build();
+        // Clients that reject the certificate close the connection during the handshake
+        MockServer::listen("https", handler, move |stream| acceptor.accept(stream).ok())
+    }
+
+    fn listen<S: Read + Write + 'static>(
+        scheme: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+        accept: impl Fn(TcpStream) -> Option<S> + Send + Sync + 'static
+    ) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("{}://{}", scheme, listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let accept = Arc::new(accept);
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (handler, recorded, accept) = (handler.clone(), recorded.clone(), accept.clone());
+                thread::spawn(move || {
+                    if let Some(stream) = accept(stream) {
+                        serve(stream, &*handler, &recorded)
+                    }
+                });
+            }
+        });
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // The requests whose path, without the query string, ends with `suffix`
+    pub fn requests_to(&self, method: &str, suffix: &str) -> Vec<Request> {
+        self.requests().into_iter()
+            .filter(|request| request.method == method && request.route().ends_with(suffix))
+            .collect()
+    }
+}
+
+// A URL that nothing listens on, so that connections are refused
+pub fn unused_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+// A certificate for 127.0.0.1, signed by itself
+pub struct SelfSignedCertificate {
+    pub pem: Vec<u8>,
+    certificate: X509,
+    key: PKey<Private>,
+}
+
+impl SelfSignedCertificate {
+    pub fn new() -> SelfSignedCertificate {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "127.0.0.1").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+        let alt_names = SubjectAlternativeName::new().ip("127.0.0.1").build(&builder.x509v3_context(None, None));
+        builder.append_extension(alt_names.unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let certificate = builder.build();
+
+        SelfSignedCertificate { pem: certificate.to_pem().unwrap(), certificate, key }
+    }
+}
+
+fn serve(stream: impl Read + Write, handler: &Handler, recorded: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader) {
+        recorded.lock().unwrap().push(request.clone());
+        let response = handler(&request);
+        // The request was read entirely, so nothing is left in the buffer
+        if write_response(reader.get_mut(), &request, &response).is_err() {
+            break;
+        }
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next()?.to_string(), parts.next()?.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+    let header = |name: &str| headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.clone());
+
+    let mut body = Vec::new();
+    if header("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).ok()?;
+            let size = usize::from_str_radix(size.trim().split(';').next()?, 16).ok()?;
+            // Each chunk, including the last empty one, is followed by a newline
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = header("content-length") {
+        body = vec![0; length.parse().ok()?];
+        reader.read_exact(&mut body).ok()?;
+    }
+    if header("content-encoding").as_deref() == Some("gzip") {
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut decoded).ok()?;
+        body = decoded;
+    }
+    Some(Request { method, path, headers, body })
+}
+
+fn write_response(out: &mut impl Write, request: &Request, response: &Response) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\ncontent-length: {}\r\n", response.status, response.body.len());
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    out.write_all(head.as_bytes())?;
+    if request.method != "HEAD" {
+        out.write_all(&response.body)?;
+    }
+    out.flush()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    pub docs: BTreeMap<String, JsonValue>,
+    pub mappings: JsonValue,
+    pub settings: JsonValue,
+}
+
+// The state of an in-memory cluster, with enough of the APIs for ingestion runs. Failures can be injected.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub indices: BTreeMap<String, Index>,
+    pub aliases: BTreeMap<String, BTreeSet<String>>,
+    // Of the root endpoint
+    pub version: String,
+    pub distribution: Option<String>,
+    // Plugins installed on the node
+    pub plugins: Vec<String>,
+    // Number of bulk requests received so far, and the ones that fail with a 500, counted from 1
+    pub bulk_requests: usize,
+    pub failing_bulk_requests: BTreeSet<usize>,
+    // Documents rejected with a mapper_parsing_exception
+    pub rejected_documents: BTreeSet<String>,
+    // Index creations fail with a 400
+    pub refuse_index_creation: bool,
+    // Deletions of these indices fail with a 403, as without the privileges
+    pub refused_index_deletions: BTreeSet<String>,
+    // The number of pages returned by scroll requests, after the first one, and the scrolls that were cleared
+    pub scroll_pages: usize,
+    pub cleared_scrolls: Vec<String>,
+    // The page size and the hits left of open scrolls
+    scrolls: BTreeMap<String, (usize, Vec<JsonValue>)>,
+}
+
+impl Default for Cluster {
+    fn default() -> Cluster {
+        Cluster {
+            indices: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            version: "7.17.18".to_string(),
+            distribution: None,
+            plugins: Vec::new(),
+            bulk_requests: 0,
+            failing_bulk_requests: BTreeSet::new(),
+            rejected_documents: BTreeSet::new(),
+            refuse_index_creation: false,
+            refused_index_deletions: BTreeSet::new(),
+            scroll_pages: 0,
+            cleared_scrolls: Vec::new(),
+            scrolls: BTreeMap::new(),
+        }
+    }
+}
+
+fn error(status: u16, error_type: &str, reason: &str) -> Response {
+    Response::json(status, json!({ "error": { "type": error_type, "reason": reason }, "status": status }))
+}
+
+impl Cluster {
+    // Concrete indices of a comma-separated list of indices, aliases and wildcard patterns
+    pub fn resolve(&self, names: &str) -> Vec<String> {
+        let mut indices = Vec::new();
+        for name in names.split(',') {
+            if let Some(prefix) = name.strip_suffix('*') {
+                indices.extend(self.indices.keys().filter(|index| index.starts_with(prefix)).cloned());
+            } else if let Some(aliased) = self.aliases.get(name).filter(|aliased| !aliased.is_empty()) {
+                indices.extend(aliased.iter().filter(|index| self.indices.contains_key(*index)).cloned());
+            } else if self.indices.contains_key(name) {
+                indices.push(name.to_string());
+            }
+        }
+        indices
+    }
+
+    pub fn docs(&self, name: &str) -> BTreeMap<String, JsonValue> {
+        self.resolve(name).iter().flat_map(|index| self.indices[index].docs.clone()).collect()
+    }
+
+    pub fn handle(&mut self, request: &Request) -> Response {
+        let route = request.route().to_string();
+        let parts: Vec<&str> = route.split('/').filter(|part| !part.is_empty()).collect();
+        let method = request.method.as_str();
+        match (method, &parts[..]) {
+            (_, []) => {
+                let mut version = json!({ "number": self.version });
+                if let Some(distribution) = &self.distribution {
+                    version["distribution"] = json!(distribution);
+                }
+                Response::json(200, json!({ "name": "node-1", "cluster_name": "mock", "version": version }))
+            }
+            (_, ["_cluster", "health", ..]) => {
+                Response::json(200, json!({ "status": "green", "number_of_nodes": 1 }))
+            }
+            (_, ["_cluster", "settings"]) => {
+                Response::json(200, json!({ "persistent": {}, "transient": {}, "defaults": {} }))
+            }
+            (_, ["_nodes", "stats", ..]) => Response::json(200, json!({
+                "nodes": { "n1": { "name": "node-1", "fs": { "total": {
+                    "total_in_bytes": 1_000_000_000u64, "available_in_bytes": 800_000_000u64
+                } } } }
+            })),
+            (_, ["_nodes", ..]) => {
+                let plugins: Vec<JsonValue> = self.plugins.iter().map(|name| json!({ "name": name })).collect();
+                Response::json(200, json!({ "nodes": { "n1": { "plugins": plugins } } }))
+            }
+            ("POST", ["_aliases"]) => self.update_aliases(&request.json()),
+            (_, ["_alias", alias]) => {
+                let indices = self.resolve(alias);
+                if !self.aliases.contains_key(*alias) || indices.is_empty() {
+                    return error(404, "aliases_not_found_exception", "alias missing");
+                }
+                let body: serde_json::Map<_, _> = indices.into_iter()
+                    .map(|index| (index, json!({ "aliases": { *alias: {} } })))
+                    .collect();
+                Response::json(200, JsonValue::Object(body))
+            }
+            (_, ["_search", "scroll"]) => self.scroll(method, &request.json()),
+            ("HEAD", [name]) => Response::empty(if self.resolve(name).is_empty() { 404 } else { 200 }),
+            ("PUT", [name]) => self.create_index(name, &request.json()),
+            ("DELETE", [name]) => {
+                if self.refused_index_deletions.contains(*name) {
+                    return error(403, "security_exception", "action [indices:admin/delete] is unauthorized");
+                }
+                let indices = self.resolve(name);
+                if indices.is_empty() {
+                    return error(404, "index_not_found_exception", "no such index");
+                }
+                for index in indices {
+                    self.indices.remove(&index);
+                    for aliased in self.aliases.values_mut() {
+                        aliased.remove(&index);
+                    }
+                }
+                Response::json(200, json!({ "acknowledged": true }))
+            }
+            ("GET", [name]) => {
+                let indices = self.resolve(name);
+                if indices.is_empty() && !name.contains('*') {
+                    return error(404, "index_not_found_exception", "no such index");
+                }
+                let body: serde_json::Map<_, _> = indices.into_iter()
+                    .map(|index| {
+                        let aliases: serde_json::Map<_, _> = self.aliases.iter()
+                            .filter(|(_, aliased)| aliased.contains(&index))
+                            .map(|(alias, _)| (alias.clone(), json!({})))
+                            .collect();
+                        let definition = &self.indices[&index];
+                        let body = json!({
+                            "aliases": aliases, "mappings": definition.mappings, "settings": definition.settings
+                        });
+                        (index, body)
+                    })
+                    .collect();
+                Response::json(200, JsonValue::Object(body))
+            }
+            (_, [name, "_bulk"]) => self.bulk(name, &request.text()),
+            ("GET", [name, "_doc", id]) => {
+                let doc = self.resolve(name).first().and_then(|index| self.indices[index].docs.get(*id).cloned());
+                match doc {
+                    Some(source) => Response::json(200, json!({ "_id": id, "found": true, "_source": source })),
+                    None => Response::json(404, json!({ "_id": id, "found": false })),
+                }
+            }
+            (_, [name, "_doc", id]) => {
+                let index = self.resolve(name).first().cloned().unwrap_or_else(|| name.to_string());
+                self.indices.entry(index).or_default().docs.insert(id.to_string(), request.json());
+                Response::json(201, json!({ "_id": id, "result": "created" }))
+            }
+            ("PUT", [name, "_settings"]) => {
+                for index in self.resolve(name) {
+                    merge(&mut self.indices.get_mut(&index).unwrap().settings, request.json());
+                }
+                Response::json(200, json!({ "acknowledged": true }))
+            }
+            (_, [name, "_clone", target]) => {
+                let Some(source) = self.resolve(name).first().map(|index| self.indices[index].clone()) else {
+                    return error(404, "index_not_found_exception", "no such index");
+                };
+                if self.indices.contains_key(*target) {
+                    return error(400, "resource_already_exists_exception", "index already exists");
+                }
+                self.indices.insert(target.to_string(), source);
+                Response::json(200, json!({ "acknowledged": true, "index": target }))
+            }
+            (_, [_, "_refresh"]) => Response::json(200, json!({ "_shards": { "failed": 0 } })),
+            (_, [name, "_count"]) => {
+                if self.resolve(name).is_empty() {
+                    return error(404, "index_not_found_exception", "no such index");
+                }
+                let query = request.json().get("query").cloned().unwrap_or(JsonValue::Null);
+                let count = self.docs(name).values().filter(|doc| matches(&query, doc)).count();
+                Response::json(200, json!({ "count": count }))
+            }
+            ("GET", [name, "_mapping"]) => {
+                let body: serde_json::Map<_, _> = self.resolve(name).into_iter()
+                    .map(|index| {
+                        let mappings = self.indices[&index].mappings.clone();
+                        (index, json!({ "mappings": mappings }))
+                    })
+                    .collect();
+                Response::json(200, JsonValue::Object(body))
+            }
+            (_, [name, "_mapping"]) => {
+                for index in self.resolve(name) {
+                    merge(&mut self.indices.get_mut(&index).unwrap().mappings, request.json());
+                }
+                Response::json(200, json!({ "acknowledged": true }))
+            }
+            (_, [name, "_search"]) => self.search(name, request),
+            (_, [name, "_delete_by_query"]) => {
+                let query = request.json().get("query").cloned().unwrap_or(JsonValue::Null);
+                let mut deleted = 0;
+                for index in self.resolve(name) {
+                    let docs = &mut self.indices.get_mut(&index).unwrap().docs;
+                    let before = docs.len();
+                    docs.retain(|_, doc| !matches(&query, doc));
+                    deleted += before - docs.len();
+                }
+                Response::json(200, json!({ "deleted": deleted, "version_conflicts": 0, "failures": [] }))
+            }
+            _ => error(400, "mock_exception", &format!("unsupported request {} {}", method, request.path)),
+        }
+    }
+
+    fn create_index(&mut self, name: &str, definition: &JsonValue) -> Response {
+        if self.refuse_index_creation {
+            return error(400, "mapper_parsing_exception", "Failed to parse mapping [_doc]");
+        }
+        if self.indices.contains_key(name) {
+            return error(400, "resource_already_exists_exception", "index already exists");
+        }
+        let index = Index {
+            docs: BTreeMap::new(),
+            mappings: definition.get("mappings").cloned().unwrap_or_else(|| json!({})),
+            settings: definition.get("settings").cloned().unwrap_or_else(|| json!({})),
+        };
+        self.indices.insert(name.to_string(), index);
+        if let Some(aliases) = definition.get("aliases").and_then(JsonValue::as_object) {
+            for alias in aliases.keys() {
+                self.aliases.entry(alias.clone()).or_default().insert(name.to_string());
+            }
+        }
+        Response::json(200, json!({ "acknowledged": true, "index": name }))
+    }
+
+    fn update_aliases(&mut self, body: &JsonValue) -> Response {
+        for action in body["actions"].as_array().into_iter().flatten() {
+            let Some((kind, target)) = action.as_object().and_then(|action| action.iter().next()) else { continue };
+            let index = target["index"].as_str().unwrap_or_default().to_string();
+            let alias = target["alias"].as_str().unwrap_or_default().to_string();
+            match kind.as_str() {
+                "add" => {
+                    self.aliases.entry(alias).or_default().insert(index);
+                }
+                "remove" => {
+                    if let Some(aliased) = self.aliases.get_mut(&alias) {
+                        aliased.remove(&index);
+                    }
+                }
+                "remove_index" => {
+                    self.indices.remove(&index);
+                }
+                _ => return error(400, "mock_exception", &format!("unsupported alias action {}", kind)),
+            }
+        }
+        Response::json(200, json!({ "acknowledged": true }))
+    }
+
+    fn bulk(&mut self, name: &str, body: &str) -> Response {
+        self.bulk_requests += 1;
+        if self.failing_bulk_requests.contains(&self.bulk_requests) {
+            return error(500, "mock_exception", "bulk request failed");
+        }
+
+        let lines: Vec<JsonValue> = body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mut lines = lines.into_iter();
+        let (mut items, mut errors) = (Vec::new(), false);
+        while let Some(action) = lines.next() {
+            let (kind, target) = action.as_object().unwrap().iter().next().unwrap();
+            let (kind, target) = (kind.clone(), target.clone());
+            let id = target["_id"].as_str().unwrap_or_default().to_string();
+            let index_name = target["_index"].as_str().unwrap_or(name).to_string();
+            let index = self.resolve(&index_name).first().cloned().unwrap_or(index_name);
+            let docs = &mut self.indices.entry(index).or_default().docs;
+            match kind.as_str() {
+                "delete" => {
+                    let status = if docs.remove(&id).is_some() { 200 } else { 404 };
+                    items.push(json!({ "delete": { "_id": id, "status": status } }));
+                }
+                "update" => {
+                    let update = lines.next().unwrap();
+                    match docs.get_mut(&id) {
+                        Some(doc) => {
+                            merge(doc, update["doc"].clone());
+                            items.push(json!({ "update": { "_id": id, "status": 200 } }));
+                        }
+                        None => items.push(json!({ "update": { "_id": id, "status": 404, "error": {
+                            "type": "document_missing_exception", "reason": "document missing"
+                        } } })),
+                    }
+                }
+                _ => {
+                    let source = lines.next().unwrap();
+                    if self.rejected_documents.contains(&id) {
+                        errors = true;
+                        items.push(json!({ kind: { "_id": id, "status": 400, "error": {
+                            "type": "mapper_parsing_exception", "reason": "failed to parse field [location]"
+                        } } }));
+                    } else {
+                        let status = if docs.insert(id.clone(), source).is_some() { 200 } else { 201 };
+                        items.push(json!({ kind: { "_id": id, "status": status } }));
+                    }
+                }
+            }
+        }
+        Response::json(200, json!({ "took": 3, "errors": errors, "items": items }))
+    }
+
+    fn search(&mut self, name: &str, request: &Request) -> Response {
+        if self.resolve(name).is_empty() {
+            return error(404, "index_not_found_exception", "no such index");
+        }
+        let body = request.json();
+        let query = body.get("query").cloned().unwrap_or(JsonValue::Null);
+        let mut hits: Vec<JsonValue> = Vec::new();
+        for index in self.resolve(name) {
+            for (id, doc) in &self.indices[&index].docs {
+                if matches(&query, doc) {
+                    hits.push(json!({ "_index": index, "_id": id, "_score": 1.0, "_source": doc, "sort": [id] }));
+                }
+            }
+        }
+        hits.sort_by(|a, b| a["_id"].as_str().cmp(&b["_id"].as_str()));
+        // Closest first, with the distance in meters as the sort value
+        let by_distance = &body["sort"][0]["_geo_distance"];
+        if let Some(origin) = by_distance.get("location").and_then(point) {
+            for hit in &mut hits {
+                let distance = point(&hit["_source"]["location"]).map_or(f64::INFINITY, |at| geo::distance(origin, at));
+                hit["sort"] = json!([distance]);
+            }
+            hits.sort_by(|a, b| a["sort"][0].as_f64().partial_cmp(&b["sort"][0].as_f64()).unwrap());
+        }
+        let total = hits.len();
+        if let Some(after) = body["search_after"][0].as_str() {
+            hits.retain(|hit| hit["_id"].as_str().is_some_and(|id| id > after));
+        }
+
+        let mut aggregations = serde_json::Map::new();
+        for (name, aggregation) in body["aggs"].as_object().into_iter().flatten() {
+            if let Some(field) = aggregation["terms"]["field"].as_str() {
+                let field = field.split('.').next().unwrap_or_default();
+                let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+                for hit in &hits {
+                    if let Some(value) = hit["_source"][field].as_str() {
+                        *counts.entry(value.to_string()).or_default() += 1;
+                    }
+                }
+                let mut buckets: Vec<_> = counts.into_iter().collect();
+                buckets.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                let buckets: Vec<_> = buckets.into_iter()
+                    .map(|(key, count)| json!({ "key": key, "doc_count": count }))
+                    .collect();
+                aggregations.insert(name.clone(), json!({ "buckets": buckets }));
+            }
+        }
+
+        let size = body["size"].as_u64().unwrap_or(10) as usize;
+        let rest = hits.split_off(size.min(hits.len()));
+        let mut response = json!({
+            "took": 1,
+            "hits": { "total": { "value": total, "relation": "eq" }, "hits": hits },
+            "aggregations": aggregations,
+        });
+        if request.query("scroll").is_some() {
+            let id = format!("scroll-{}", self.scrolls.len() + 1);
+            self.scrolls.insert(id.clone(), (size, rest));
+            response["_scroll_id"] = json!(id);
+        }
+        Response::json(200, response)
+    }
+
+    fn scroll(&mut self, method: &str, body: &JsonValue) -> Response {
+        if method == "DELETE" {
+            let ids: Vec<String> = body["scroll_id"].as_array().into_iter().flatten()
+                .filter_map(|id| id.as_str().map(str::to_string))
+                .collect();
+            let freed = ids.iter().filter(|id| self.scrolls.remove(*id).is_some()).count();
+            self.cleared_scrolls.extend(ids);
+            return Response::json(200, json!({ "succeeded": true, "num_freed": freed }));
+        }
+        let id = body["scroll_id"].as_str().unwrap_or_default().to_string();
+        let Some((size, rest)) = self.scrolls.get_mut(&id) else {
+            return error(404, "search_context_missing_exception", "No search context found");
+        };
+        let hits: Vec<JsonValue> = rest.drain(..(*size).min(rest.len())).collect();
+        self.scroll_pages += 1;
+        Response::json(200, json!({ "_scroll_id": id, "hits": { "total": { "value": 0 }, "hits": hits } }))
+    }
+}
+
+// The few queries used on documents: match_all, term, terms and bool
+fn matches(query: &JsonValue, doc: &JsonValue) -> bool {
+    let field = |name: &str| doc[name.trim_end_matches(".keyword")].clone();
+    let Some((kind, body)) = query.as_object().and_then(|query| query.iter().next()) else { return true };
+    let all = |clauses: &JsonValue| match clauses {
+        JsonValue::Array(clauses) => clauses.iter().all(|clause| matches(clause, doc)),
+        JsonValue::Null => true,
+        clause => matches(clause, doc),
+    };
+    match kind.as_str() {
+        "match_all" => true,
+        "term" => body.as_object().unwrap().iter().all(|(name, value)| {
+            let value = value.get("value").unwrap_or(value);
+            field(name) == *value
+        }),
+        "terms" => body.as_object().unwrap().iter().all(|(name, values)| {
+            values.as_array().is_some_and(|values| values.contains(&field(name)))
+        }),
+        "geo_distance" => {
+            let radius = body["distance"].as_str().and_then(|distance| match distance.strip_suffix("km") {
+                Some(km) => km.parse::<f64>().ok().map(|km| km * 1000.0),
+                None => distance.trim_end_matches('m').parse().ok(),
+            });
+            let origin = body.as_object().unwrap().iter().find(|(name, _)| *name != "distance");
+            match (radius, origin) {
+                (Some(radius), Some((name, origin))) => match (point(origin), point(&field(name))) {
+                    (Some(origin), Some(at)) => geo::distance(origin, at) <= radius,
+                    _ => false,
+                },
+                _ => false,
+            }
+        }
+        "bool" => {
+            let should = match &body["should"] {
+                JsonValue::Array(clauses) if !clauses.is_empty() => clauses.iter().any(|clause| matches(clause, doc)),
+                _ => true,
+            };
+            let must_not = match &body["must_not"] {
+                JsonValue::Array(clauses) => clauses.iter().any(|clause| matches(clause, doc)),
+                JsonValue::Null => false,
+                clause => matches(clause, doc),
+            };
+            all(&body["must"]) && all(&body["filter"]) && should && !must_not
+        }
+        _ => true,
+    }
+}
+
+// A location in any of the formats of geo_point fields
+fn point(value: &JsonValue) -> Option<GeoPoint> {
+    serde_json::from_value(value.clone()).ok()
+}
+
+fn merge(target: &mut JsonValue, value: JsonValue) {
+    match (target, value) {
+        (JsonValue::Object(target), JsonValue::Object(value)) => {
+            for (key, value) in value {
+                merge(target.entry(key).or_insert(JsonValue::Null), value);
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+// A mock cluster, whose state can be checked and changed by tests
+pub struct MockElasticsearch {
+    pub server: MockServer,
+    cluster: Arc<Mutex<Cluster>>,
+}
+
+impl MockElasticsearch {
+    pub fn start() -> MockElasticsearch {
+        MockElasticsearch::with_cluster(Cluster::default())
+    }
+
+    pub fn with_cluster(cluster: Cluster) -> MockElasticsearch {
+        let cluster = Arc::new(Mutex::new(cluster));
+        let handled = cluster.clone();
+        let server = MockServer::start(move |request| handled.lock().unwrap().handle(request));
+        MockElasticsearch { server, cluster }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.server.url
+    }
+
+    pub fn cluster(&self) -> MutexGuard<'_, Cluster> {
+        self.cluster.lock().unwrap()
+    }
+
+    // The ids of the index operations of each bulk request, in order
+    pub fn bulk_ids(&self) -> Vec<Vec<String>> {
+        self.server.requests_to("POST", "/_bulk").iter()
+            .map(|request| {
+                request.text().lines()
+                    .filter_map(|line| serde_json::from_str::<JsonValue>(line).ok())
+                    .filter_map(|line| line["index"]["_id"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+pub fn es_client(url: &str) -> elasticsearch::Elasticsearch {
+    client::create_client(&ClientOptions { urls: vec![url.to_string()], ..Default::default() }).unwrap()
+}
+
+// The options of a run on the 2023 campaign, at a fixed time
+pub fn transform_options() -> TransformOptions {
+    TransformOptions {
+        bbox: Some(transform::BoundingBox::TOULOUSE_METROPOLE),
+        normalize_city: true,
+        geohash_precision: 7,
+        campaign_year: 2023,
+        indexed_at: "2024-01-08T06:00:00Z".parse().unwrap(),
+        run_id: "test-run".to_string(),
+        source_url: "file:places.json".to_string(),
+        center: GeoPoint::new(43.6045, 1.444),
+        sectors: Vec::new(),
+    }
+}
+
+// The places of a fixture, once transformed
+pub fn places(name: &str) -> Vec<IndexedPlace> {
+    let records = source::read_places(&fixture(name)).unwrap();
+    transform::transform_places(records, &transform_options()).0
+}
+
+// Run the command line tool in `dir` against the cluster at `es_url`, with progress bars and colors disabled
+pub fn run(es_url: &str, dir: &Path, args: &[&str]) -> Output {
+    command(es_url, dir, args).output().unwrap()
+}
+
+// The same, with `input` as stdin
+pub fn run_with_input(es_url: &str, dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = command(es_url, dir, args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn command(es_url: &str, dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_xmas-tree-recycling"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("ELASTICSEARCH_URL", es_url)
+        .env("NO_COLOR", "1")
+        .env_remove("ELASTICSEARCH_CLOUD_ID")
+        .env_remove("ELASTICSEARCH_API_KEY")
+        .env_remove("BULK_SIZE")
+        .env_remove("BULK_BYTES");
+    command
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
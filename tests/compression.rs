@@ -0,0 +1,60 @@
+mod common;
+
+use common::{Cluster, MockServer};
+use serde_json::Value as JsonValue;
+use std::sync::{Arc, Mutex};
+
+// A cluster that compresses its responses for clients that accept it
+fn compressing_cluster() -> (MockServer, Arc<Mutex<Cluster>>) {
+    let cluster = Arc::new(Mutex::new(Cluster::default()));
+    let handled = cluster.clone();
+    let es = MockServer::start(move |request| {
+        let response = handled.lock().unwrap().handle(request);
+        let accepts_gzip = request.header("accept-encoding").is_some_and(|encodings| encodings.contains("gzip"));
+        if accepts_gzip && request.method != "HEAD" { response.gzip() } else { response }
+    });
+    (es, cluster)
+}
+
+fn load(es: &MockServer, compress: &str) -> JsonValue {
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let args = [
+        "--input", input.to_str().unwrap(), "--compress", compress, "--summary-json", "summary.json",
+        "--bulk-size", "5", "--yes", "--no-backup",
+    ];
+    let output = common::run(&es.url, dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    serde_json::from_slice(&std::fs::read(dir.path().join("summary.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn bulk_requests_are_compressed_and_compressed_responses_are_decoded() {
+    let (es, cluster) = compressing_cluster();
+
+    let summary = load(&es, "true");
+    let bulk_requests = es.requests_to("POST", "/_bulk");
+    assert_eq!(bulk_requests.len(), 3);
+    for request in &bulk_requests {
+        assert_eq!(request.header("content-encoding"), Some("gzip"));
+    }
+    assert!(es.requests().iter().all(|request| request.header("accept-encoding").is_some_and(|e| e.contains("gzip"))));
+    assert_eq!(cluster.lock().unwrap().docs("xmas-tree-recycling").len(), 12);
+
+    // The mock decompresses the bodies it got
+    let uncompressed: usize = bulk_requests.iter().map(|request| request.body.len()).sum();
+    assert_eq!(summary["bulk_bytes"], uncompressed);
+    let compressed = summary["bulk_bytes_compressed"].as_u64().unwrap();
+    assert!(compressed > 0 && compressed < uncompressed as u64 / 2, "{}", summary);
+}
+
+#[test]
+fn bulk_requests_can_be_sent_uncompressed() {
+    let (es, cluster) = compressing_cluster();
+
+    let summary = load(&es, "false");
+    assert!(es.requests_to("POST", "/_bulk").iter().all(|request| request.header("content-encoding").is_none()));
+    assert!(summary["bulk_bytes"].as_u64().unwrap() > 0);
+    assert!(summary["bulk_bytes_compressed"].is_null(), "{}", summary);
+    assert_eq!(cluster.lock().unwrap().docs("xmas-tree-recycling").len(), 12);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{Cluster, MockElasticsearch};
+
+fn opensearch() -> MockElasticsearch {
+    let mut cluster = Cluster::default();
+    cluster.version = "2.11.0".to_string();
+    cluster.distribution = Some("opensearch".to_string());
+    MockElasticsearch::with_cluster(cluster)
+}
+
+#[test]
+fn loads_the_data_into_opensearch() {
+    let es = opensearch();
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+
+    let args = ["--engine", "opensearch", "--input", input.to_str().unwrap(), "--yes", "--no-backup"];
+    let output = common::run(es.url(), dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let cluster = es.cluster();
+    let index = &cluster.indices["xmas-tree-recycling"];
+    assert_eq!(index.mappings["properties"]["location"]["type"], "geo_point");
+    assert_eq!(index.docs.len(), 12);
+    drop(cluster);
+    assert!(!es.server.requests_to("POST", "/_bulk").is_empty());
+}
+
+#[test]
+fn opensearch_is_refused_without_the_engine_option() {
+    let es = opensearch();
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+
+    let output = common::run(es.url(), dir.path(), &["--input", input.to_str().unwrap(), "--yes", "--no-backup"]);
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("The server is OpenSearch 2.11.0, but Elasticsearch was expected"), "{}", stderr);
+    assert!(es.cluster().indices.is_empty());
+}
+
+#[test]
+fn old_elasticsearch_versions_are_refused_unless_forced() {
+    let mut cluster = Cluster::default();
+    cluster.version = "6.8.23".to_string();
+    let es = MockElasticsearch::with_cluster(cluster);
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+
+    let args = ["--input", input.to_str().unwrap(), "--yes", "--no-backup"];
+    let output = common::run(es.url(), dir.path(), &args);
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("Elasticsearch 6.8.23 is older than the minimum supported version 7.10.0"), "{}", stderr);
+    assert!(es.cluster().indices.is_empty());
+
+    let output = common::run(es.url(), dir.path(), &[&args[..], &["--force"]].concat());
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+}
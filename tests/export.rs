@@ -0,0 +1,168 @@
+mod common;
+
+use geojson::{feature, GeoJson};
+use serde_json::json;
+use std::collections::HashMap;
+use xmas_tree_recycling::html::{self, MapOptions};
+use xmas_tree_recycling::source;
+
+#[test]
+fn exports_a_geojson_feature_collection() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let args = ["export", "--format", "geojson", "--output", "places.geojson", "--input", input.to_str().unwrap()];
+    let output = common::run(&common::unused_url(), dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let text = std::fs::read_to_string(dir.path().join("places.geojson")).unwrap();
+    let collection = match text.parse::<GeoJson>().unwrap() {
+        GeoJson::FeatureCollection(collection) => collection,
+        other => panic!("not a FeatureCollection: {}", other),
+    };
+
+    // Source coordinates are [lat, lon], and must be found as is in the [lon, lat] GeoJSON points
+    let records = source::read_places(&input).unwrap();
+    let mut source_points: HashMap<String, [f64; 2]> = records.iter()
+        .map(|record| {
+            let (lat, lon) = record.fields.geo_point_2d.unwrap();
+            (record.recordid.clone(), [lon, lat])
+        })
+        .collect();
+
+    assert_eq!(collection.features.len(), 12);
+    for feature in &collection.features {
+        let record_id = feature.property("record_id").and_then(|id| id.as_str()).unwrap();
+        assert_eq!(feature.id, Some(feature::Id::String(record_id.to_string())));
+        match &feature.geometry.as_ref().unwrap().value {
+            geojson::Value::Point(coordinates) => {
+                assert_eq!(coordinates[..], source_points.remove(record_id).unwrap()[..], "{}", record_id)
+            }
+            other => panic!("not a Point: {:?}", other),
+        }
+        assert_eq!(feature.property("dataset_id").and_then(|id| id.as_str()), Some("collecte-des-sapins-de-noel"));
+        for property in ["street", "city"] {
+            assert!(feature.property(property).is_some_and(|value| value.is_string()), "{}", property);
+        }
+    }
+    assert!(source_points.is_empty());
+}
+
+#[test]
+fn exports_csv_that_parses_back_to_the_transformed_places() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = common::fixture("places.json");
+    let args = ["export", "--format", "csv", "--bom", "--output", "places.csv", "--input", input.to_str().unwrap()];
+    let output = common::run(&common::unused_url(), dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let data = std::fs::read(dir.path().join("places.csv")).unwrap();
+    let data = data.strip_prefix("\u{feff}".as_bytes()).expect("no byte order mark");
+    let mut reader = csv::Reader::from_reader(data);
+    assert_eq!(reader.headers().unwrap(), vec!["record_id", "city", "street", "lat", "lon"]);
+    let mut rows: Vec<Vec<String>> = reader.records()
+        .map(|record| record.unwrap().iter().map(str::to_string).collect())
+        .collect();
+    rows.sort();
+
+    let mut expected: Vec<Vec<String>> = common::places("places.json").iter()
+        .map(|place| vec![
+            place.record_id.clone(), place.city.clone(), place.street.clone(),
+            place.location.lat.to_string(), place.location.lon.to_string(),
+        ])
+        .collect();
+    expected.sort();
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn exports_do_not_depend_on_the_order_of_the_source_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read(common::fixture("places.json")).unwrap();
+    let mut records: Vec<serde_json::Value> = serde_json::from_slice(&data).unwrap();
+
+    // The records in their order, reversed, and rotated
+    let mut inputs = Vec::new();
+    for (name, shuffle) in [("as-is", 0), ("reversed", 1), ("rotated", 2)] {
+        match shuffle {
+            1 => records.reverse(),
+            2 => records.rotate_left(5),
+            _ => {}
+        }
+        let path = dir.path().join(format!("{}.json", name));
+        std::fs::write(&path, serde_json::to_vec(&records).unwrap()).unwrap();
+        inputs.push(path);
+    }
+
+    for format in ["geojson", "csv", "kml", "gpx"] {
+        for sort in ["address", "record-id"] {
+            let exports: Vec<Vec<u8>> = inputs.iter()
+                .map(|input| {
+                    let args = [
+                        "export", "--format", format, "--sort", sort, "--output", "-",
+                        "--stamp", "2024-01-08T06:00:00Z", "--input", input.to_str().unwrap(),
+                    ];
+                    let output = common::run(&common::unused_url(), dir.path(), &args);
+                    assert!(output.status.success(), "{}", common::stderr(&output));
+                    output.stdout
+                })
+                .collect();
+            assert!(!exports[0].is_empty());
+            assert!(exports.iter().all(|export| *export == exports[0]), "{} sorted by {}", format, sort);
+        }
+    }
+}
+
+// The GeoJSON data block of an HTML map
+fn map_data(map: &str) -> serde_json::Value {
+    let start = r#"<script type="application/geo+json" id="places">"#;
+    let (_, data) = map.split_once(start).expect("no data block");
+    let (data, _) = data.split_once("</script>").unwrap();
+    serde_json::from_str(data).unwrap()
+}
+
+#[test]
+fn html_maps_embed_the_places() {
+    let places = common::places("places.json");
+    for offline in [false, true] {
+        let mut map = Vec::new();
+        html::write_map(&places, &mut map, MapOptions { offline, cluster_above: 100 }).unwrap();
+        let map = String::from_utf8(map).unwrap();
+
+        let data = map_data(&map);
+        assert_eq!(data["type"], "FeatureCollection");
+        let features = data["features"].as_array().unwrap();
+        assert_eq!(features.len(), places.len());
+        for (feature, place) in features.iter().zip(&places) {
+            assert_eq!(feature["properties"]["street"], place.street.as_str());
+            assert_eq!(feature["properties"]["city"], place.city.as_str());
+            assert_eq!(feature["geometry"]["coordinates"], json!([place.location.lon, place.location.lat]));
+        }
+        assert!(!map.contains("{{"), "{}", map);
+        assert!(!map.contains("markercluster"), "{}", map);
+        assert_eq!(map.contains("unpkg.com/leaflet@"), !offline);
+    }
+}
+
+#[test]
+fn html_maps_cluster_markers_above_a_number_of_places() {
+    let places = common::places("places.json");
+    for (cluster_above, clustered) in [(places.len(), false), (places.len() - 1, true)] {
+        let mut map = Vec::new();
+        html::write_map(&places, &mut map, MapOptions { offline: false, cluster_above }).unwrap();
+        let map = String::from_utf8(map).unwrap();
+        assert_eq!(map.contains("leaflet.markercluster.js"), clustered);
+        assert_eq!(map_data(&map)["features"].as_array().unwrap().len(), places.len());
+    }
+}
+
+#[test]
+fn html_map_data_cannot_close_its_script_element() {
+    let mut places = common::places("places.json");
+    places[0].street = "</script><script>alert('xss')</script><!--".to_string();
+    let mut map = Vec::new();
+    html::write_map(&places, &mut map, MapOptions { offline: true, cluster_above: 100 }).unwrap();
+    let map = String::from_utf8(map).unwrap();
+
+    assert!(!map.contains("alert('xss')</script>"));
+    assert_eq!(map_data(&map)["features"][0]["properties"]["street"], places[0].street.as_str());
+}
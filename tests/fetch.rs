@@ -0,0 +1,67 @@
+mod common;
+
+use common::{MockServer, Response};
+use reqwest::header::HeaderMap;
+use serde_json::json;
+use std::time::Duration;
+use xmas_tree_recycling::client::CertificateCheck;
+use xmas_tree_recycling::error::IngestError;
+use xmas_tree_recycling::retry::RetryPolicy;
+use xmas_tree_recycling::source;
+
+fn http_client() -> reqwest::Client {
+    source::http_client(Duration::from_secs(10), None, &CertificateCheck::Default, HeaderMap::new()).unwrap()
+}
+
+#[tokio::test]
+async fn html_pages_are_reported_with_their_start() {
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n  <head><title>Maintenance</title></head>\n  <body>{}</body>\n</html>",
+        "Le portail est en maintenance. ".repeat(20)
+    );
+    let portal = MockServer::start(move |_| Response::text(200, "text/html; charset=utf-8", &page));
+
+    let err = source::fetch_places(&http_client(), &portal.url, &RetryPolicy::new(3), None).await.unwrap_err();
+    let message = format!("{:#}", err);
+    assert!(message.contains(&format!(
+        "Expected JSON data from {}/, but got a text/html; charset=utf-8 response, such as a maintenance page: \
+         <!DOCTYPE html> <html> <head><title>Maintenance</title></head> <body>Le portail est en maintenance.",
+        portal.url
+    )), "{}", message);
+    assert!(message.ends_with("..."), "{}", message);
+    assert!(!err.is::<IngestError>(), "{}", message);
+    // Retrying won't help
+    assert_eq!(portal.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn json_and_binary_content_types_are_parsed() {
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    for content_type in ["application/json; charset=utf-8", "application/geo+json", "application/octet-stream"] {
+        let data = data.clone();
+        let portal = MockServer::start(move |_| Response::text(200, content_type, &data));
+        let fetched = source::fetch_places(&http_client(), &portal.url, &RetryPolicy::new(1), None).await.unwrap();
+        assert_eq!(fetched.places.len(), 12, "{}", content_type);
+    }
+}
+
+#[tokio::test]
+async fn large_compressed_responses_are_decoded() {
+    let records: Vec<_> = (0..20_000)
+        .map(|n| json!({
+            "datasetid": "collecte-des-sapins-de-noel",
+            "recordid": n.to_string(),
+            "fields": { "commune": "TOULOUSE", "adresse": "Place du Capitole", "geo_point_2d": [43.6044622, 1.4442469] }
+        }))
+        .collect();
+    let data = serde_json::to_string(&records).unwrap();
+    let size = data.len();
+    let portal = MockServer::start(move |_| Response::text(200, "application/json", &data).gzip());
+
+    let fetched = source::fetch_places(&http_client(), &portal.url, &RetryPolicy::new(1), None).await.unwrap();
+    assert_eq!(fetched.places.len(), 20_000);
+    assert_eq!(fetched.places[19_999].recordid, "19999");
+    assert_eq!(fetched.downloaded.unwrap().body.len(), size);
+    let accept_encoding = portal.requests()[0].header("accept-encoding").map(str::to_string);
+    assert!(accept_encoding.as_deref().unwrap_or_default().contains("gzip"), "{:?}", accept_encoding);
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use async_trait::async_trait;
+use common::{Cluster, MockElasticsearch, MockServer, Response};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use xmas_tree_recycling::index::{self, BatchOutcome, Batcher, BulkOptions, ElasticsearchSink};
+use xmas_tree_recycling::retry::RetryPolicy;
+use xmas_tree_recycling::sink::{BulkFileSink, Sink};
+use xmas_tree_recycling::transform::IndexedPlace;
+
+const OPTIONS: BulkOptions = BulkOptions { bulk_size: 5, bulk_bytes: 5_000_000, concurrency: 2 };
+
+#[tokio::test]
+async fn indexing_the_same_data_twice_does_not_duplicate_documents() {
+    let es = MockElasticsearch::start();
+    let es_client = common::es_client(es.url());
+    let sink = ElasticsearchSink {
+        es_client: &es_client,
+        index: "places",
+        retry_policy: RetryPolicy::new(1),
+        wait_for_refresh: false,
+        compress: false,
+    };
+    let places = common::places("places.json");
+
+    for _ in 0..2 {
+        let stats = index::index_places(&sink, "collecte-des-sapins-de-noel", places.clone().into_iter(), &OPTIONS)
+            .await.unwrap();
+        assert_eq!(stats.indexed, places.len());
+    }
+
+    let record_ids: HashSet<String> = places.iter().map(|place| place.record_id.clone()).collect();
+    let payloads = es.bulk_ids();
+    // 12 places in batches of 5, twice
+    assert_eq!(payloads.len(), 6);
+    for ids in &payloads {
+        let unique: HashSet<&String> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len(), "duplicate ids in a bulk request: {:?}", ids);
+        assert!(ids.iter().all(|id| record_ids.contains(id)));
+    }
+    let sent: Vec<&String> = payloads.iter().flatten().collect();
+    assert_eq!(sent.len(), 2 * places.len());
+    assert_eq!(es.cluster().docs("places").len(), places.len());
+}
+
+#[test]
+fn folded_sub_fields_use_the_icu_plugin_when_installed() {
+    let input = common::fixture("places.json");
+    for (plugins, filter, folding) in [
+        (vec![], json!(["lowercase", "asciifolding"]), "ascii"),
+        (vec!["analysis-icu"], json!(["icu_folding"]), "icu"),
+    ] {
+        let mut cluster = Cluster::default();
+        cluster.plugins = plugins.into_iter().map(str::to_string).collect();
+        let es = MockElasticsearch::with_cluster(cluster);
+        let dir = tempfile::tempdir().unwrap();
+
+        let args = ["--input", input.to_str().unwrap(), "--summary-json", "summary.json", "--yes", "--no-backup"];
+        let output = common::run(es.url(), dir.path(), &args);
+        assert!(output.status.success(), "{}", common::stderr(&output));
+
+        let cluster = es.cluster();
+        let index = &cluster.indices["xmas-tree-recycling"];
+        assert_eq!(index.settings["analysis"]["analyzer"]["folded"]["filter"], filter);
+        for field in ["street", "city"] {
+            assert_eq!(index.mappings["properties"][field]["fields"]["folded"]["analyzer"], "folded", "{}", field);
+        }
+        let summary = std::fs::read(dir.path().join("summary.json")).unwrap();
+        let summary: JsonValue = serde_json::from_slice(&summary).unwrap();
+        assert_eq!(summary["datasets"][0]["folding"], folding);
+    }
+}
+
+// Measures documents like the Elasticsearch sink, and stores nothing
+struct NullSink;
+
+#[async_trait]
+impl Sink for NullSink {
+    async fn send(&self, _batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        Ok(BatchOutcome::default())
+    }
+}
+
+fn batch(sink: &dyn Sink, places: &[IndexedPlace], options: &BulkOptions) -> Vec<Vec<IndexedPlace>> {
+    let mut batcher = Batcher::new(options, sink);
+    let mut batches: Vec<_> = places.iter().cloned().filter_map(|place| batcher.push(place)).collect();
+    batches.extend(batcher.take());
+    batches
+}
+
+// The places of the fixture, with streets from 0 to about 500 characters
+fn places_of_varying_sizes() -> Vec<IndexedPlace> {
+    let mut places = common::places("places.json");
+    for (n, place) in places.iter_mut().enumerate() {
+        place.street = "rue ".repeat(n * 37 % 127);
+    }
+    places
+}
+
+#[test]
+fn batches_have_at_most_bulk_size_documents() {
+    let places = common::places("places.json");
+    let sizes: Vec<usize> = batch(&NullSink, &places, &OPTIONS).iter().map(Vec::len).collect();
+    assert_eq!(sizes, [5, 5, 2]);
+    assert_eq!(index::count_batches(&places, &OPTIONS), 3);
+}
+
+#[test]
+fn batches_have_at_most_bulk_bytes() {
+    let places = places_of_varying_sizes();
+    let options = BulkOptions { bulk_size: 100, bulk_bytes: 3000, ..OPTIONS };
+    let batches = batch(&NullSink, &places, &options);
+    assert!(batches.len() > 2, "{}", batches.len());
+
+    // Documents are in their order, and batches are filled as much as possible
+    let ids: Vec<&str> = batches.iter().flatten().map(|place| place.record_id.as_str()).collect();
+    assert_eq!(ids, places.iter().map(|place| place.record_id.as_str()).collect::<Vec<_>>());
+    let len = |batch: &[IndexedPlace]| batch.iter().map(|place| NullSink.operation_len(place)).sum::<usize>();
+    for (batch, next) in batches.iter().zip(batches.iter().skip(1)) {
+        assert!(len(batch) <= options.bulk_bytes, "{}", len(batch));
+        assert!(len(batch) + NullSink.operation_len(&next[0]) > options.bulk_bytes);
+    }
+    assert_eq!(index::count_batches(&places, &options), batches.len());
+}
+
+#[test]
+fn documents_larger_than_bulk_bytes_are_sent_alone() {
+    let mut places = common::places("places.json");
+    places[4].street = "rue ".repeat(2000);
+    let options = BulkOptions { bulk_size: 100, bulk_bytes: 4000, ..OPTIONS };
+    assert!(NullSink.operation_len(&places[4]) > options.bulk_bytes);
+
+    let batches = batch(&NullSink, &places, &options);
+    let oversized = batches.iter().position(|batch| batch.iter().any(|place| place.street.len() > 1000)).unwrap();
+    assert_eq!(batches[oversized].len(), 1);
+    assert_eq!(batches.iter().flatten().count(), places.len());
+    assert_eq!(index::count_batches(&places, &options), batches.len());
+
+    // Also when it's the first or the only one
+    assert_eq!(batch(&NullSink, &places[4..], &options)[0].len(), 1);
+    assert_eq!(batch(&NullSink, &places[4..5], &options).len(), 1);
+}
+
+#[tokio::test]
+async fn bulk_files_are_measured_as_written() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let sink = BulkFileSink::new("xmas-tree-recycling", Box::new(file.reopen().unwrap()));
+
+    let mut written = 0;
+    for place in places_of_varying_sizes() {
+        let len = sink.operation_len(&place);
+        // The action line has the index name, unlike requests to the _bulk endpoint of the index
+        assert_eq!(len, NullSink.operation_len(&place) + r#","_index":"xmas-tree-recycling""#.len());
+        sink.send(vec![place]).await.unwrap();
+        sink.finish().await.unwrap();
+        written += len;
+        assert_eq!(std::fs::metadata(file.path()).unwrap().len() as usize, written);
+    }
+}
+
+#[tokio::test]
+async fn only_missing_indices_do_not_exist() {
+    for (status, exists) in [(200, Some(true)), (404, Some(false)), (401, None), (403, None)] {
+        let es = MockServer::start(move |_| Response::empty(status));
+        let es_client = common::es_client(&es.url);
+        let result = index::index_exists(&es_client, "places").await;
+        match exists {
+            Some(exists) => assert_eq!(result.unwrap(), exists, "{}", status),
+            None => {
+                let message = result.unwrap_err().to_string();
+                assert!(message.starts_with("Cannot check if index places exists"), "{}: {}", status, message);
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+mod common;
+
+use common::{Index, MockElasticsearch};
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+fn load(es: &MockElasticsearch, dir: &Path, args: &[&str]) -> std::process::Output {
+    let input = common::fixture("places.json");
+    let common_args = ["--input", input.to_str().unwrap(), "--yes", "--no-backup"];
+    common::run(es.url(), dir, &[&common_args[..], args].concat())
+}
+
+fn summary(dir: &Path) -> JsonValue {
+    serde_json::from_slice(&std::fs::read(dir.join("summary.json")).unwrap()).unwrap()
+}
+
+// The names of the staging indices of the cluster
+fn staging_indices(es: &MockElasticsearch) -> Vec<String> {
+    es.cluster().indices.keys().filter(|name| name.contains("-staging-")).cloned().collect()
+}
+
+// An index shared with other datasets, with the documents of `datasets` and the `location` mapping
+fn shared_index(
+    es: &MockElasticsearch,
+    location: JsonValue,
+    datasets: &[(&str, usize)]
+) -> BTreeMap<String, JsonValue> {
+    let mut docs = BTreeMap::new();
+    for &(dataset, count) in datasets {
+        for n in 0..count {
+            let street = format!("{} rue du Taur", n);
+            let doc = json!({ "dataset_id": dataset, "street": street, "location": [1.44, 43.6] });
+            docs.insert(format!("{}-{}", dataset, n), doc);
+        }
+    }
+    let mappings = json!({ "properties": { "location": location, "dataset_id": { "type": "keyword" } } });
+    let index = Index { docs: docs.clone(), mappings, settings: json!({}) };
+    es.cluster().indices.insert("xmas-tree-recycling".to_string(), index);
+    docs
+}
+
+#[test]
+fn partial_runs_cannot_replace_the_index_contents() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &[]).status.success());
+
+    for partial in [&["--limit", "3"][..], &["--sample", "0.5"]] {
+        let output = load(&es, dir.path(), partial);
+        assert!(!output.status.success());
+        let stderr = common::stderr(&output);
+        assert!(stderr.contains("only stores part of the data"), "{}", stderr);
+        assert!(stderr.contains("--i-know-this-is-partial"), "{}", stderr);
+        assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+    }
+}
+
+#[test]
+fn partial_runs_replace_the_index_contents_when_acknowledged() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial", "--summary-json", "summary.json"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 3);
+    let summary = summary(dir.path());
+    assert_eq!(summary["partial"], true);
+    assert_eq!(summary["datasets"][0]["partial"], true);
+}
+
+#[test]
+fn partial_runs_can_add_to_the_index() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = load(&es, dir.path(), &["--limit", "3", "--upsert"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 3);
+}
+
+#[test]
+fn samples_with_the_same_seed_have_the_same_places() {
+    let sample = |seed: &str| {
+        let es = MockElasticsearch::start();
+        let dir = tempfile::tempdir().unwrap();
+        let output = load(&es, dir.path(), &["--sample", "0.5", "--seed", seed, "--upsert"]);
+        assert!(output.status.success(), "{}", common::stderr(&output));
+        let ids: Vec<String> = es.cluster().docs("xmas-tree-recycling").keys().cloned().collect();
+        ids
+    };
+
+    let first = sample("42");
+    assert!(!first.is_empty() && first.len() < 12, "{:?}", first);
+    assert_eq!(sample("42"), first);
+    assert_ne!(sample("1"), first);
+}
+
+#[test]
+fn staged_loads_replace_the_index_once_loaded() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+
+    let output = load(&es, dir.path(), &["--staged"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+    assert_eq!(staging_indices(&es), Vec::<String>::new());
+}
+
+#[test]
+fn failed_staged_loads_leave_the_index_untouched() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+    let docs = es.cluster().docs("xmas-tree-recycling");
+
+    // The second of the 3 bulk requests of the staged load fails
+    let failing = es.cluster().bulk_requests + 2;
+    es.cluster().failing_bulk_requests.insert(failing);
+    let output = load(&es, dir.path(), &["--staged", "--bulk-size", "5", "--concurrency", "1", "--max-attempts", "1"]);
+    assert!(!output.status.success());
+    assert_eq!(es.cluster().docs("xmas-tree-recycling"), docs);
+
+    // Loading stops at the failed request: the staging index has the documents of the first one
+    let staging = staging_indices(&es);
+    assert_eq!(staging.len(), 1, "{:?}", staging);
+    assert!(staging[0].starts_with("xmas-tree-recycling-staging-"));
+    assert_eq!(es.cluster().docs(&staging[0]).len(), 5);
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains(&format!("Loaded data is in index {}", staging[0])), "{}", stderr);
+}
+
+#[test]
+fn staged_loads_with_rejected_documents_leave_the_index_untouched() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+    let docs = es.cluster().docs("xmas-tree-recycling");
+
+    let rejected = common::places("places.json").pop().unwrap().record_id;
+    es.cluster().rejected_documents.insert(rejected);
+    let output = load(&es, dir.path(), &["--staged", "--bulk-size", "5"]);
+    assert!(!output.status.success());
+    assert_eq!(es.cluster().docs("xmas-tree-recycling"), docs);
+
+    let staging = staging_indices(&es);
+    assert_eq!(staging.len(), 1, "{:?}", staging);
+    assert_eq!(es.cluster().docs(&staging[0]).len(), 11);
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains(&format!("Loaded data is in index {}", staging[0])), "{}", stderr);
+}
+
+#[test]
+fn index_creation_failures_leave_the_existing_data_intact() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+    let docs = es.cluster().docs("xmas-tree-recycling");
+    let indices: Vec<String> = es.cluster().indices.keys().cloned().collect();
+
+    // The index is replaced in place, or by a copy of a staging index
+    es.cluster().refuse_index_creation = true;
+    let expected = [
+        (&[][..], "Cannot create index xmas-tree-recycling, its existing data is intact"),
+        (&["--staged"], "no existing data was changed"),
+    ];
+    for (args, message) in expected {
+        let output = load(&es, dir.path(), &[&["--max-attempts", "1"], args].concat());
+        assert_eq!(output.status.code(), Some(12), "{}", common::stderr(&output));
+        let stderr = common::stderr(&output);
+        assert!(stderr.contains(message), "{}", stderr);
+        assert!(stderr.contains("400 Bad Request"), "{}", stderr);
+
+        assert_eq!(es.cluster().docs("xmas-tree-recycling"), docs);
+        assert_eq!(es.cluster().indices.keys().cloned().collect::<Vec<_>>(), indices);
+    }
+}
+
+#[test]
+fn failed_loads_are_resumed_from_their_checkpoint() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join(".ingest-checkpoint.json");
+    // 5 bulk requests of 2 documents, the third of which fails
+    let args = ["--limit", "10", "--i-know-this-is-partial", "--bulk-size", "2", "--concurrency", "1"];
+    es.cluster().failing_bulk_requests.insert(3);
+    let output = load(&es, dir.path(), &[&args[..], &["--max-attempts", "1"]].concat());
+    assert!(!output.status.success());
+    let stored: BTreeSet<String> = es.bulk_ids()[..2].concat().into_iter().collect();
+    assert_eq!(stored.len(), 4);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").into_keys().collect::<BTreeSet<_>>(), stored);
+
+    let saved: JsonValue = serde_json::from_slice(&std::fs::read(&checkpoint).unwrap()).unwrap();
+    assert_eq!(saved["dataset"], "collecte-des-sapins-de-noel");
+    assert_eq!(saved["index"], "xmas-tree-recycling");
+    assert_eq!(saved["stored"].as_object().unwrap().keys().cloned().collect::<BTreeSet<_>>(), stored);
+
+    // Not into another index, or with other data
+    let output = load(&es, dir.path(), &[&args[..], &["--resume", "--index", "sapins"]].concat());
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("The checkpoint is for dataset collecte-des-sapins-de-noel in index xmas-tree-recycling, \
+        not dataset collecte-des-sapins-de-noel in index sapins"), "{}", stderr);
+    let partial = common::fixture("partial.json");
+    let partial_args = ["--input", partial.to_str().unwrap(), "--yes", "--no-backup", "--resume"];
+    let output = common::run(es.url(), dir.path(), &[&args[..], &partial_args].concat());
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("The checkpoint is for source data"), "{}", stderr);
+    assert!(checkpoint.exists());
+
+    // The resumed run only sends the places that weren't stored, to the same index, and removes the checkpoint
+    let requests = es.bulk_ids().len();
+    let output = load(&es, dir.path(), &[&args[..], &["--resume"]].concat());
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let resumed: Vec<String> = es.bulk_ids()[requests..].concat();
+    assert_eq!(resumed.len(), 6);
+    assert!(resumed.iter().all(|id| !stored.contains(id)), "{:?}", resumed);
+    let docs = es.cluster().docs("xmas-tree-recycling");
+    assert_eq!(docs.len(), 10);
+    assert!(docs.values().all(|doc| doc["run_id"] == saved["run_id"]), "{:?}", docs);
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn index_deletion_failures_leave_the_existing_data_intact() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+    let docs = es.cluster().docs("xmas-tree-recycling");
+
+    es.cluster().refused_index_deletions.insert("xmas-tree-recycling".to_string());
+    let output = load(&es, dir.path(), &["--max-attempts", "1"]);
+    assert_eq!(output.status.code(), Some(12), "{}", common::stderr(&output));
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("Cannot delete index xmas-tree-recycling, its existing data is intact"), "{}", stderr);
+    assert!(stderr.contains("403 Forbidden"), "{}", stderr);
+    assert!(!stderr.contains("data is lost"), "{}", stderr);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling"), docs);
+}
+
+#[test]
+fn staged_loads_leave_the_index_untouched_when_it_cannot_be_deleted() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &["--limit", "3", "--i-know-this-is-partial"]).status.success());
+    let docs = es.cluster().docs("xmas-tree-recycling");
+
+    es.cluster().refused_index_deletions.insert("xmas-tree-recycling".to_string());
+    let output = load(&es, dir.path(), &["--staged"]);
+    assert_eq!(output.status.code(), Some(12), "{}", common::stderr(&output));
+    let stderr = common::stderr(&output);
+    let staging = staging_indices(&es);
+    assert_eq!(staging.len(), 1, "{:?}", staging);
+    let message = format!(
+        "Cannot delete index xmas-tree-recycling, its existing data is intact. Loaded data is in index {}", staging[0]
+    );
+    assert!(stderr.contains(&message), "{}", stderr);
+    assert!(!stderr.contains("already exists"), "{}", stderr);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling"), docs);
+
+    // Writes to the staging index are allowed again
+    let cluster = es.cluster();
+    assert_eq!(cluster.docs(&staging[0]).len(), 12);
+    let settings = &cluster.indices[&staging[0]].settings;
+    assert!(settings["index.blocks.write"].is_null(), "{}", settings);
+}
+
+#[test]
+fn soft_deleted_places_are_deactivated_and_reactivated() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    assert!(load(&es, dir.path(), &[]).status.success());
+
+    let args = ["--incremental", "--soft-delete", "--summary-json", "summary.json"];
+    let output = load(&es, dir.path(), &[&args[..], &["--limit", "11", "--i-know-this-is-partial"]].concat());
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let docs = es.cluster().docs("xmas-tree-recycling");
+    assert_eq!(docs.len(), 12);
+    let inactive: Vec<&String> = docs.iter().filter(|(_, doc)| doc["active"] == false).map(|(id, _)| id).collect();
+    assert_eq!(inactive.len(), 1, "{:?}", inactive);
+    let removed = inactive[0].clone();
+    assert!(docs[&removed]["removed_at"].is_string(), "{}", docs[&removed]);
+    assert_eq!(docs.values().filter(|doc| doc.get("removed_at").is_some()).count(), 1);
+    let dataset = &summary(dir.path())["datasets"][0];
+    assert_eq!((&dataset["deactivated"], &dataset["reactivated"]), (&1.into(), &0.into()), "{}", dataset);
+
+    let output = load(&es, dir.path(), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let docs = es.cluster().docs("xmas-tree-recycling");
+    assert_eq!(docs[&removed]["active"], true);
+    assert!(docs[&removed].get("removed_at").is_none(), "{}", docs[&removed]);
+    let dataset = &summary(dir.path())["datasets"][0];
+    assert_eq!((&dataset["deactivated"], &dataset["reactivated"]), (&0.into(), &1.into()), "{}", dataset);
+}
+
+#[test]
+fn shared_index_loads_only_replace_the_documents_of_their_dataset() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let seeded = shared_index(&es, json!({ "type": "geo_point" }), &[("other", 3), ("collecte-des-sapins-de-noel", 2)]);
+
+    let output = load(&es, dir.path(), &["--shared-index", "--summary-json", "summary.json"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let docs = es.cluster().docs("xmas-tree-recycling");
+    let others: BTreeMap<_, _> = docs.iter().filter(|(_, doc)| doc["dataset_id"] == "other").collect();
+    let seeded_others: BTreeMap<_, _> = seeded.iter().filter(|(id, _)| id.starts_with("other-")).collect();
+    assert_eq!(others, seeded_others);
+    assert_eq!(docs.len(), 3 + 12);
+    assert!(!docs.contains_key("collecte-des-sapins-de-noel-0"));
+    let dataset = &summary(dir.path())["datasets"][0];
+    assert_eq!(dataset["documents_before_cleanup"], 5, "{}", dataset);
+    assert_eq!(dataset["documents_after_cleanup"], 3, "{}", dataset);
+}
+
+#[test]
+fn shared_indices_get_a_location_mapping_if_they_have_none() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    shared_index(&es, JsonValue::Null, &[("other", 3)]);
+
+    let output = load(&es, dir.path(), &["--shared-index"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let mappings = es.cluster().indices["xmas-tree-recycling"].mappings.clone();
+    assert_eq!(mappings["properties"]["location"]["type"], "geo_point", "{}", mappings);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 3 + 12);
+}
+
+#[test]
+fn shared_indices_with_another_location_type_are_left_untouched() {
+    let es = MockElasticsearch::start();
+    let dir = tempfile::tempdir().unwrap();
+    let seeded = shared_index(&es, json!({ "type": "keyword" }), &[("other", 3), ("collecte-des-sapins-de-noel", 2)]);
+
+    let output = load(&es, dir.path(), &["--shared-index"]);
+    assert_eq!(output.status.code(), Some(12));
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("Field location of index xmas-tree-recycling is a keyword, not a geo_point"), "{}", stderr);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling"), seeded);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use async_trait::async_trait;
+use common::MockElasticsearch;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use xmas_tree_recycling::index::{BatchOutcome, BulkOptions, ElasticsearchSink};
+use xmas_tree_recycling::pipeline::Pipeline;
+use xmas_tree_recycling::retry::RetryPolicy;
+use xmas_tree_recycling::sink::Sink;
+use xmas_tree_recycling::source::SourcePlace;
+use xmas_tree_recycling::transform::IndexedPlace;
+use xmas_tree_recycling::{source, transform};
+
+const OPTIONS: BulkOptions = BulkOptions { bulk_size: 2, bulk_bytes: 5_000_000, concurrency: 2 };
+
+#[tokio::test]
+async fn loads_a_file_into_the_cluster() {
+    let es = MockElasticsearch::start();
+    let es_client = common::es_client(es.url());
+    let sink = ElasticsearchSink {
+        es_client: &es_client,
+        index: "places",
+        retry_policy: RetryPolicy::new(1),
+        wait_for_refresh: false,
+        compress: false,
+    };
+    let pipeline = Pipeline { sink: &sink, dataset: "collecte-des-sapins-de-noel", options: OPTIONS };
+
+    let path = common::fixture("partial.json");
+    let options = common::transform_options();
+    let stats = pipeline
+        .run(move |send| source::read_places_with(&path, send), |place| transform::transform(place, &options).ok())
+        .await
+        .unwrap();
+
+    // Records without a location, at (0, 0) or out of the bounding box are skipped
+    assert_eq!(stats.indexed, 3);
+    assert_eq!(stats.batches, 2);
+    assert!(stats.failures.is_empty());
+    assert!(es.bulk_ids().iter().all(|ids| ids.len() <= 2));
+
+    let cluster = es.cluster();
+    let docs = cluster.docs("places");
+    assert_eq!(docs.len(), 3);
+    let complete = docs.values().find(|doc| doc["record_id"].as_str().unwrap().starts_with("3c1b")).unwrap();
+    assert_eq!(complete["dataset_id"], "collecte-des-sapins-de-noel");
+    assert_eq!(complete["location"].as_array().unwrap().len(), 2);
+    assert!(complete["location"][0].as_f64().unwrap() < complete["location"][1].as_f64().unwrap());
+    assert_eq!(complete["run_id"], json!("test-run"));
+}
+
+// A sink that takes some time to store each batch, and fails when asked to
+struct SlowSink {
+    stored: Arc<AtomicUsize>,
+    fail_at: Option<usize>,
+}
+
+#[async_trait]
+impl Sink for SlowSink {
+    async fn send(&self, batch: Vec<IndexedPlace>) -> anyhow::Result<BatchOutcome> {
+        tokio::time::delay_for(Duration::from_millis(5)).await;
+        let stored = self.stored.fetch_add(batch.len(), Ordering::SeqCst) + batch.len();
+        match self.fail_at {
+            Some(fail_at) if stored >= fail_at => anyhow::bail!("sink failure"),
+            _ => Ok(BatchOutcome::default()),
+        }
+    }
+}
+
+// A record in the Capitole square
+fn record(n: usize) -> SourcePlace {
+    serde_json::from_value(json!({
+        "datasetid": "collecte-des-sapins-de-noel",
+        "recordid": n.to_string(),
+        "fields": { "commune": "TOULOUSE", "adresse": "Place du Capitole", "geo_point_2d": [43.6044622, 1.4442469] }
+    })).unwrap()
+}
+
+// Run a pipeline with `count` records into `sink`, returning the result and the maximum number of records
+// that were parsed but not stored yet
+async fn run_slow(count: usize, sink: &SlowSink) -> (anyhow::Result<usize>, usize) {
+    let parsed = Arc::new(AtomicUsize::new(0));
+    let max_pending = Arc::new(AtomicUsize::new(0));
+    let (stored, parser_parsed, parser_max_pending) = (sink.stored.clone(), parsed.clone(), max_pending.clone());
+    let parse = move |send: &mut dyn FnMut(SourcePlace) -> anyhow::Result<()>| {
+        for n in 0..count {
+            let parsed = parser_parsed.fetch_add(1, Ordering::SeqCst) + 1;
+            parser_max_pending.fetch_max(parsed - stored.load(Ordering::SeqCst), Ordering::SeqCst);
+            send(record(n))?;
+        }
+        Ok(())
+    };
+
+    let options = common::transform_options();
+    let pipeline = Pipeline { sink, dataset: "collecte-des-sapins-de-noel", options: OPTIONS };
+    let result = pipeline.run(parse, |place| transform::transform(place, &options).ok()).await;
+    (result.map(|stats| stats.indexed), max_pending.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn slow_sinks_slow_down_parsing() {
+    let sink = SlowSink { stored: Arc::new(AtomicUsize::new(0)), fail_at: None };
+    let (indexed, max_pending) = run_slow(1000, &sink).await;
+    assert_eq!(indexed.unwrap(), 1000);
+
+    // Records waiting to be transformed, a batch being filled, batches waiting to be stored and as many as can
+    // be stored in parallel, and the records in the hands of the parser and of the transform stage. Channels
+    // have an extra slot for their sender.
+    let BulkOptions { bulk_size, concurrency, .. } = OPTIONS;
+    let max_in_memory = (bulk_size + 1) + bulk_size + (concurrency + 1) * bulk_size + concurrency * bulk_size + 2;
+    assert!(max_pending <= max_in_memory, "{}", max_pending);
+}
+
+#[tokio::test]
+async fn sink_errors_stop_the_pipeline() {
+    let sink = SlowSink { stored: Arc::new(AtomicUsize::new(0)), fail_at: Some(10) };
+    let (indexed, _) = run_slow(10_000, &sink).await;
+    assert_eq!(indexed.unwrap_err().to_string(), "sink failure");
+    assert!(sink.stored.load(Ordering::SeqCst) < 100);
+}
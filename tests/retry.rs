@@ -0,0 +1,235 @@
+mod common;
+
+use common::{Cluster, MockElasticsearch, MockServer, Request, Response};
+use reqwest::header::HeaderMap;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use xmas_tree_recycling::client::{self, CertificateCheck, ClientOptions};
+use xmas_tree_recycling::index::{self, ElasticsearchSink};
+use xmas_tree_recycling::retry::RetryPolicy;
+use xmas_tree_recycling::sink::Sink;
+use xmas_tree_recycling::source;
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 5,
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+        max_rate_limit_wait: Duration::from_secs(1),
+    }
+}
+
+// Fails the first two requests with `status`, then answers with `handler`
+fn failing_twice(
+    status: u16,
+    handler: impl Fn(&Request) -> Response + Send + Sync + 'static
+) -> MockServer {
+    let count = AtomicUsize::new(0);
+    MockServer::start(move |request| {
+        if count.fetch_add(1, Ordering::SeqCst) < 2 {
+            Response::json(status, json!({ "error": "unavailable", "status": status }))
+        } else {
+            handler(request)
+        }
+    })
+}
+
+fn http_client() -> reqwest::Client {
+    source::http_client(Duration::from_secs(10), None, &CertificateCheck::Default, HeaderMap::new()).unwrap()
+}
+
+#[tokio::test]
+async fn data_fetch_is_retried_after_server_errors() {
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    let portal = failing_twice(503, move |_| Response::text(200, "application/json", &data));
+
+    let fetched = source::fetch_places(&http_client(), &portal.url, &policy(), None).await.unwrap();
+    assert_eq!(fetched.places.len(), 12);
+    assert_eq!(portal.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn data_fetch_is_not_retried_after_client_errors() {
+    let portal = MockServer::start(|_| Response::json(404, json!({ "error": "dataset not found" })));
+
+    let err = source::fetch_places(&http_client(), &portal.url, &policy(), None).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("404"), "{:#}", err);
+    assert_eq!(portal.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn data_fetch_gives_up_after_the_maximum_number_of_attempts() {
+    let portal = MockServer::start(|_| Response::json(502, json!({ "error": "bad gateway" })));
+
+    let err = source::fetch_places(&http_client(), &portal.url, &policy(), None).await.unwrap_err();
+    assert!(err.to_string().contains("after 5 attempts"), "{:#}", err);
+    assert_eq!(portal.requests().len(), 5);
+}
+
+// Rate limits the first request, asking to retry after `retry_after`, then answers with `handler`
+fn rate_limited_once(
+    retry_after: String,
+    handler: impl Fn(&Request) -> Response + Send + Sync + 'static
+) -> MockServer {
+    let count = AtomicUsize::new(0);
+    MockServer::start(move |request| {
+        if count.fetch_add(1, Ordering::SeqCst) == 0 {
+            Response::text(429, "text/plain", "Too many requests").header("retry-after", &retry_after)
+        } else {
+            handler(request)
+        }
+    })
+}
+
+#[tokio::test]
+async fn data_fetch_waits_for_the_rate_limit_delay() {
+    // A number of seconds, and an HTTP date, which only has a precision of a second
+    for http_date in [false, true] {
+        let retry_after = if http_date {
+            (chrono::Utc::now() + chrono::Duration::seconds(2)).format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+        } else {
+            "1".to_string()
+        };
+        let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+        let portal = rate_limited_once(retry_after.clone(), move |_| Response::text(200, "application/json", &data));
+
+        let policy = RetryPolicy { max_rate_limit_wait: Duration::from_secs(5), ..policy() };
+        let start = std::time::Instant::now();
+        let fetched = source::fetch_places(&http_client(), &portal.url, &policy, None).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(900), "{}: {:?}", retry_after, start.elapsed());
+        assert_eq!(fetched.places.len(), 12);
+        assert_eq!(portal.requests().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn data_fetch_fails_when_the_rate_limit_delay_is_too_long() {
+    let portal = rate_limited_once("60".to_string(), |_| Response::text(200, "application/json", "[]"));
+
+    let start = std::time::Instant::now();
+    let err = source::fetch_places(&http_client(), &portal.url, &policy(), None).await.unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(err.to_string().contains("still rate limited after waiting 0s, and asked to wait 60s more"), "{:#}", err);
+    assert_eq!(portal.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn rate_limits_apply_to_each_page_of_records() {
+    // Pages of a single record, the second one being rate limited
+    let count = AtomicUsize::new(0);
+    let portal = MockServer::start(move |request| {
+        let offset: usize = request.query("offset").unwrap().parse().unwrap();
+        if offset == 1 && count.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Response::text(429, "text/plain", "Too many requests").header("retry-after", "1");
+        }
+        let results = [("TOULOUSE", "Place du Capitole"), ("BALMA", "av de Toulouse")]
+            .iter()
+            .skip(offset)
+            .map(|(commune, adresse)| json!({
+                "commune": commune, "adresse": adresse, "geo_point_2d": { "lon": 1.4442469, "lat": 43.6044622 }
+            }))
+            .take(1)
+            .collect::<Vec<_>>();
+        Response::json(200, json!({ "total_count": 2, "results": results }))
+    });
+
+    let url = format!("{}/api/explore/v2.1/catalog/datasets/collecte-des-sapins-de-noel/records", portal.url);
+    let start = std::time::Instant::now();
+    let places = source::fetch_records(&http_client(), &url, &policy()).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(900), "{:?}", start.elapsed());
+    assert_eq!(places.len(), 2);
+    let offsets: Vec<Option<String>> = portal.requests().iter().map(|request| request.query("offset")).collect();
+    assert_eq!(offsets, [Some("0".to_string()), Some("1".to_string()), Some("1".to_string())]);
+}
+
+#[tokio::test]
+async fn index_creation_is_retried_when_the_cluster_is_overloaded() {
+    let cluster = Mutex::new(Cluster::default());
+    let es = failing_twice(429, move |request| cluster.lock().unwrap().handle(request));
+    let es_client = common::es_client(&es.url);
+
+    index::create_index(&es_client, "places", &json!({}), &policy()).await.unwrap();
+    assert_eq!(es.requests_to("PUT", "/places").len(), 3);
+}
+
+#[tokio::test]
+async fn mapping_errors_are_not_retried() {
+    let es = MockServer::start(|_| Response::json(400, json!({
+        "error": { "type": "mapper_parsing_exception", "reason": "No handler for type [geo_pint]" }, "status": 400
+    })));
+    let es_client = common::es_client(&es.url);
+
+    assert!(index::create_index(&es_client, "places", &json!({}), &policy()).await.is_err());
+    assert_eq!(es.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn bulk_requests_are_retried_after_server_errors() {
+    let cluster = std::sync::Arc::new(Mutex::new(Cluster::default()));
+    let handled = cluster.clone();
+    let es = failing_twice(503, move |request| handled.lock().unwrap().handle(request));
+    let es_client = common::es_client(&es.url);
+    let sink = ElasticsearchSink {
+        es_client: &es_client, index: "places", retry_policy: policy(), wait_for_refresh: false, compress: false,
+    };
+
+    let outcome = sink.send(common::places("places.json")).await.unwrap();
+    assert!(outcome.failures.is_empty());
+    assert_eq!(es.requests_to("POST", "/_bulk").len(), 3);
+    assert_eq!(cluster.lock().unwrap().docs("places").len(), 12);
+}
+
+#[tokio::test]
+async fn unreachable_clusters_are_retried() {
+    let es_client = common::es_client(&common::unused_url());
+
+    let err = index::create_index(&es_client, "places", &json!({}), &policy()).await.unwrap_err();
+    assert!(err.to_string().contains("after 5 attempts"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn requests_fail_over_to_the_next_node() {
+    let es = MockElasticsearch::start();
+    // The login of the second URL is used for all nodes
+    let live = es.url().replace("http://", "http://elastic:changeme@");
+    let options = ClientOptions { urls: vec![common::unused_url(), live], ..Default::default() };
+    let es_client = client::create_client(&options).unwrap();
+    let sink = ElasticsearchSink {
+        es_client: &es_client, index: "places", retry_policy: policy(), wait_for_refresh: false, compress: false,
+    };
+
+    index::create_index(&es_client, "places", &json!({}), &policy()).await.unwrap();
+    for _ in 0..3 {
+        let outcome = sink.send(common::places("places.json")).await.unwrap();
+        assert!(outcome.failures.is_empty());
+    }
+
+    assert_eq!(es.cluster().docs("places").len(), 12);
+    let requests = es.server.requests();
+    assert_eq!(requests.len(), 4);
+    for request in &requests {
+        assert_eq!(request.header("authorization"), Some("Basic ZWxhc3RpYzpjaGFuZ2VtZQ=="));
+    }
+}
+
+#[tokio::test]
+async fn requests_are_sent_to_each_node_in_turn() {
+    let cluster = std::sync::Arc::new(Mutex::new(Cluster::default()));
+    let nodes: Vec<MockServer> = (0..2)
+        .map(|_| {
+            let cluster = cluster.clone();
+            MockServer::start(move |request| cluster.lock().unwrap().handle(request))
+        })
+        .collect();
+    let options = ClientOptions { urls: nodes.iter().map(|node| node.url.clone()).collect(), ..Default::default() };
+    let es_client = client::create_client(&options).unwrap();
+
+    for index in ["a", "b", "c", "d"] {
+        index::create_index(&es_client, index, &json!({}), &policy()).await.unwrap();
+    }
+    assert_eq!(nodes[0].requests().len(), 2);
+    assert_eq!(nodes[1].requests().len(), 2);
+    assert_eq!(cluster.lock().unwrap().indices.len(), 4);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{Index, MockElasticsearch, MockServer, Response};
+use serde_json::{json, Value as JsonValue};
+
+// A cluster with the documents of the fixture
+fn loaded_cluster() -> MockElasticsearch {
+    let es = MockElasticsearch::start();
+    let docs = common::places("places.json").into_iter()
+        .map(|place| (place.record_id.clone(), serde_json::to_value(place).unwrap()))
+        .collect();
+    es.cluster().indices.insert(
+        "xmas-tree-recycling".to_string(), Index { docs, mappings: json!({}), settings: json!({}) }
+    );
+    es
+}
+
+// A geocoder that finds every address at (`lon`, `lat`)
+fn geocoder(label: &str, score: f64, lon: f64, lat: f64) -> MockServer {
+    let feature = json!({
+        "type": "Feature",
+        "geometry": { "type": "Point", "coordinates": [lon, lat] },
+        "properties": { "label": label, "score": score, "type": "housenumber", "citycode": "31555" }
+    });
+    MockServer::start(move |_| Response::json(200, json!({ "type": "FeatureCollection", "features": [feature] })))
+}
+
+fn search_address(es: &str, geocoder: &MockServer, address: &str) -> std::process::Output {
+    let dir = tempfile::tempdir().unwrap();
+    let args = ["search", "address", address, "--geocoder-url", &geocoder.url, "--radius", "1km", "--format", "json"];
+    common::run(es, dir.path(), &args)
+}
+
+#[test]
+fn lists_the_places_closest_to_the_geocoded_address() {
+    let es = loaded_cluster();
+    let geocoder = geocoder("Place du Capitole 31000 Toulouse", 0.96, 1.4442469, 43.6044622);
+
+    let output = search_address(es.url(), &geocoder, "place du capitole, toulouse");
+    assert!(output.status.success(), "{}", common::stderr(&output));
+
+    let request = &geocoder.requests()[0];
+    assert_eq!(request.query("q").as_deref(), Some("place du capitole, toulouse"));
+    assert_eq!(request.query("limit").as_deref(), Some("1"));
+
+    let results: Vec<JsonValue> = serde_json::from_slice(&output.stdout).unwrap();
+    let streets: Vec<&str> = results.iter().map(|result| result["street"].as_str().unwrap()).collect();
+    assert_eq!(streets, [
+        "Place du Capitole",
+        "12 boulevard de Strasbourg",
+        "Quai de la Daurade",
+        "88 allée Jean Jaurès / angle rue Riquet",
+    ]);
+    let distances: Vec<f64> = results.iter().map(|result| result["distance"].as_f64().unwrap()).collect();
+    assert!(distances[0] < 1.0 && distances.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", distances);
+}
+
+#[test]
+fn unknown_addresses_are_reported() {
+    let geocoder = MockServer::start(|_| Response::json(200, json!({ "type": "FeatureCollection", "features": [] })));
+
+    let output = search_address(&common::unused_url(), &geocoder, "1 rue qui n'existe pas");
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("Address '1 rue qui n'existe pas' not found"), "{}", stderr);
+}
+
+#[test]
+fn addresses_found_with_a_low_score_are_reported() {
+    let geocoder = geocoder("Rue de Toulouse 31700 Blagnac", 0.31, 1.3980, 43.6380);
+
+    let output = search_address(&common::unused_url(), &geocoder, "12 rue du Taur");
+    assert!(!output.status.success());
+    let stderr = common::stderr(&output);
+    let message = "the closest match is 'Rue de Toulouse 31700 Blagnac' with a low score of 0.31";
+    assert!(stderr.contains(message), "{}", stderr);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Load `fixture` into the places.db database of `dir`, at the `stamp` indexing time
+fn load(dir: &Path, fixture: &str, stamp: &str) {
+    let input = common::fixture(fixture);
+    let args = ["--input", input.to_str().unwrap(), "--sink", "sqlite", "--output", "places.db", "--stamp", stamp];
+    let output = common::run(&common::unused_url(), dir, &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+}
+
+// The rows of the places table, by record id
+fn read_rows(dir: &Path) -> HashMap<String, (String, String, f64, f64, String)> {
+    let connection = Connection::open(dir.join("places.db")).unwrap();
+    let mut query = connection.prepare("SELECT record_id, city, street, lat, lon, indexed_at FROM places").unwrap();
+    let rows = query
+        .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))))
+        .unwrap();
+    rows.map(Result::unwrap).collect()
+}
+
+#[test]
+fn places_are_stored_in_an_sqlite_database() {
+    let dir = tempfile::tempdir().unwrap();
+    load(dir.path(), "places.json", "2024-01-08T06:00:00Z");
+
+    let rows = read_rows(dir.path());
+    let places = common::places("places.json");
+    assert_eq!(rows.len(), places.len());
+    for place in places {
+        let (city, street, lat, lon, indexed_at) = &rows[&place.record_id];
+        assert_eq!((city, street), (&place.city, &place.street), "{}", place.record_id);
+        assert_eq!((*lat, *lon), (place.location.lat, place.location.lon), "{}", place.record_id);
+        assert_eq!(indexed_at, "2024-01-08T06:00:00+00:00");
+    }
+}
+
+#[test]
+fn loading_again_updates_the_places() {
+    let dir = tempfile::tempdir().unwrap();
+    load(dir.path(), "places.json", "2024-01-08T06:00:00Z");
+    load(dir.path(), "places.json", "2024-01-09T06:00:00Z");
+
+    let rows = read_rows(dir.path());
+    assert_eq!(rows.len(), common::places("places.json").len());
+    assert!(rows.values().all(|(_, _, _, _, indexed_at)| indexed_at == "2024-01-09T06:00:00+00:00"));
+
+    // Other places are added to the existing ones
+    load(dir.path(), "partial.json", "2024-01-10T06:00:00Z");
+    let rows = read_rows(dir.path());
+    assert_eq!(rows.len(), common::places("places.json").len() + common::places("partial.json").len());
+    for place in common::places("partial.json") {
+        assert_eq!(rows[&place.record_id].4, "2024-01-10T06:00:00+00:00");
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{Cluster, MockServer, Response, SelfSignedCertificate};
+use reqwest::header::HeaderMap;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+use xmas_tree_recycling::client::{self, CertificateCheck, ClientOptions};
+use xmas_tree_recycling::index;
+use xmas_tree_recycling::retry::RetryPolicy;
+use xmas_tree_recycling::source;
+
+fn cluster(certificate: &SelfSignedCertificate) -> MockServer {
+    let cluster = Mutex::new(Cluster::default());
+    MockServer::start_tls(certificate, move |request| cluster.lock().unwrap().handle(request))
+}
+
+fn es_client(url: &str, certificates: CertificateCheck) -> elasticsearch::Elasticsearch {
+    let options = ClientOptions { urls: vec![url.to_string()], certificates, ..Default::default() };
+    client::create_client(&options).unwrap()
+}
+
+async fn create_index(es_client: &elasticsearch::Elasticsearch) -> anyhow::Result<()> {
+    index::create_index(es_client, "places", &json!({}), &RetryPolicy::new(1)).await
+}
+
+#[tokio::test]
+async fn self_signed_certificates_are_rejected_by_default() {
+    let es = cluster(&SelfSignedCertificate::new());
+
+    let err = create_index(&es_client(&es.url, CertificateCheck::Default)).await.unwrap_err();
+    assert!(format!("{:?}", err).contains("certificate verify failed"), "{:?}", err);
+    assert!(es.requests().is_empty());
+}
+
+#[tokio::test]
+async fn the_ca_certificate_is_trusted() {
+    let certificate = SelfSignedCertificate::new();
+    let es = cluster(&certificate);
+
+    let pem = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(pem.path(), &certificate.pem).unwrap();
+    let certificates = CertificateCheck::load_ca_cert(pem.path()).unwrap();
+    create_index(&es_client(&es.url, certificates)).await.unwrap();
+    assert_eq!(es.requests_to("PUT", "/places").len(), 1);
+
+    // Another self-signed certificate isn't
+    let other = cluster(&SelfSignedCertificate::new());
+    let certificates = CertificateCheck::load_ca_cert(pem.path()).unwrap();
+    assert!(create_index(&es_client(&other.url, certificates)).await.is_err());
+}
+
+#[tokio::test]
+async fn certificates_are_not_checked_when_insecure() {
+    let es = cluster(&SelfSignedCertificate::new());
+
+    create_index(&es_client(&es.url, CertificateCheck::Insecure)).await.unwrap();
+    assert_eq!(es.requests_to("PUT", "/places").len(), 1);
+}
+
+#[tokio::test]
+async fn the_data_portal_client_trusts_the_ca_certificate() {
+    let certificate = SelfSignedCertificate::new();
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    let portal = MockServer::start_tls(&certificate, move |_| Response::text(200, "application/json", &data));
+    let http_client = |certificates| {
+        source::http_client(Duration::from_secs(10), None, &certificates, HeaderMap::new()).unwrap()
+    };
+    let retry_policy = RetryPolicy::new(1);
+
+    let default = http_client(CertificateCheck::Default);
+    assert!(source::fetch_places(&default, &portal.url, &retry_policy, None).await.is_err());
+    let certificates = CertificateCheck::CaCert(certificate.pem.clone());
+    let fetched = source::fetch_places(&http_client(certificates), &portal.url, &retry_policy, None).await.unwrap();
+    assert_eq!(fetched.places.len(), 12);
+}
+
+#[test]
+fn invalid_ca_certificates_are_reported_before_connecting() {
+    let pem = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(pem.path(), "not a certificate").unwrap();
+    let err = CertificateCheck::load_ca_cert(pem.path()).unwrap_err();
+    assert_eq!(err.to_string(), format!("Invalid CA certificate {}", pem.path().display()));
+}
+
+#[test]
+fn ca_certificate_and_insecure_cannot_be_used_together() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("ca.pem"), SelfSignedCertificate::new().pem).unwrap();
+
+    let output = common::run(&common::unused_url(), dir.path(), &["--ca-cert", "ca.pem", "--insecure", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(common::stderr(&output).contains("cannot be used with"), "{}", common::stderr(&output));
+}
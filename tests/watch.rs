@@ -0,0 +1,136 @@
+mod common;
+
+use common::{MockElasticsearch, MockServer, Response};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Fails the first request, then serves the fixture with an ETag and answers conditional requests with "not modified"
+fn portal() -> MockServer {
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    let count = AtomicUsize::new(0);
+    MockServer::start(move |request| {
+        if count.fetch_add(1, Ordering::SeqCst) == 0 {
+            Response::text(503, "text/plain", "Service unavailable")
+        } else if request.header("if-none-match") == Some("\"v1\"") {
+            Response::empty(304)
+        } else {
+            Response::text(200, "application/json", &data).header("etag", "\"v1\"")
+        }
+    })
+}
+
+#[test]
+fn runs_until_the_maximum_number_of_runs() {
+    let es = MockElasticsearch::start();
+    let portal = portal();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+
+    let args = [
+        "--data-url", &portal.url, "--watch", "10ms", "--max-runs", "3", "--max-attempts", "1",
+        "--cache-dir", cache.to_str().unwrap(), "--yes", "--no-backup",
+    ];
+    let output = common::run(es.url(), dir.path(), &args);
+    let stderr = common::stderr(&output);
+    // The first run failed, the second one loaded the data, and the third one had nothing to do
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(portal.requests().len(), 3);
+    assert!(stderr.contains("Stopping after 3 runs"), "{}", stderr);
+    assert_eq!(es.bulk_ids().iter().flatten().count(), 12);
+    assert_eq!(es.cluster().docs("xmas-tree-recycling").len(), 12);
+}
+
+#[test]
+fn the_last_run_gives_the_exit_code() {
+    let portal = MockServer::start(|_| Response::text(503, "text/plain", "Service unavailable"));
+    let dir = tempfile::tempdir().unwrap();
+
+    let args = ["--data-url", &portal.url, "--watch", "10ms", "--max-runs", "2", "--max-attempts", "1", "--no-cache"];
+    let output = common::run(&common::unused_url(), dir.path(), &args);
+    assert_eq!(output.status.code(), Some(10), "{}", common::stderr(&output));
+    assert_eq!(portal.requests().len(), 2);
+}
+
+#[test]
+fn max_runs_requires_a_schedule() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = common::run(&common::unused_url(), dir.path(), &["--max-runs", "2", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(2), "{}", common::stderr(&output));
+}
+
+// The Dataset API endpoint, with a modification time that can be changed
+fn metadata(dataset_id: &'static str, modified: Arc<Mutex<String>>) -> MockServer {
+    MockServer::start(move |_| {
+        let modified = modified.lock().unwrap().clone();
+        Response::json(200, json!({
+            "datasetid": dataset_id,
+            "metas": { "modified": modified, "title": "Collecte des sapins de Noël" }
+        }))
+    })
+}
+
+#[test]
+fn runs_are_skipped_when_the_dataset_was_not_modified_since_the_last_load() {
+    let es = MockElasticsearch::start();
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    let portal = MockServer::start(move |_| Response::text(200, "application/json", &data));
+    let modified = Arc::new(Mutex::new("2023-12-18T09:41:05+00:00".to_string()));
+    let metadata = metadata("collecte-des-sapins-de-noel", modified.clone());
+    let dir = tempfile::tempdir().unwrap();
+    let run = |extra: &[&str]| {
+        let args = ["--data-url", &portal.url, "--metadata-url", &metadata.url, "--no-cache", "--yes", "--no-backup"];
+        let output = common::run(es.url(), dir.path(), &[&args[..], extra].concat());
+        assert!(output.status.success(), "{}", common::stderr(&output));
+        common::stderr(&output)
+    };
+
+    // The index doesn't exist yet
+    run(&[]);
+    assert_eq!(portal.requests().len(), 1);
+    assert_eq!(metadata.requests().len(), 0);
+    let bulk_requests = es.bulk_ids().len();
+
+    // Modified before the index was loaded
+    let stderr = run(&[]);
+    let message = "Source unchanged since 2023-12-18T09:41:05Z, and index xmas-tree-recycling was loaded at";
+    assert!(stderr.contains(message), "{}", stderr);
+    assert!(stderr.contains("nothing to do (use --ignore-unmodified to load it anyway)"), "{}", stderr);
+    assert_eq!(portal.requests().len(), 1);
+    assert_eq!(metadata.requests().len(), 1);
+    assert_eq!(es.bulk_ids().len(), bulk_requests);
+
+    // Unless told to ignore it, --force only bypasses the version check
+    run(&["--force"]);
+    assert_eq!(portal.requests().len(), 1);
+    assert_eq!(metadata.requests().len(), 2);
+    run(&["--ignore-unmodified"]);
+    assert_eq!(portal.requests().len(), 2);
+    assert_eq!(metadata.requests().len(), 2);
+
+    // Modified after the index was loaded
+    *modified.lock().unwrap() = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+    let stderr = run(&[]);
+    assert!(!stderr.contains("Source unchanged"), "{}", stderr);
+    assert_eq!(portal.requests().len(), 3);
+    assert_eq!(metadata.requests().len(), 3);
+}
+
+#[test]
+fn runs_are_not_skipped_when_the_metadata_is_not_the_dataset_one() {
+    let es = MockElasticsearch::start();
+    let data = std::fs::read_to_string(common::fixture("places.json")).unwrap();
+    let portal = MockServer::start(move |_| Response::text(200, "application/json", &data));
+    let metadata = metadata("another-dataset", Arc::new(Mutex::new("2023-12-18T09:41:05+00:00".to_string())));
+    let dir = tempfile::tempdir().unwrap();
+
+    let args = ["--data-url", &portal.url, "--metadata-url", &metadata.url, "--no-cache", "--yes", "--no-backup"];
+    for _ in 0..2 {
+        let output = common::run(es.url(), dir.path(), &args);
+        assert!(output.status.success(), "{}", common::stderr(&output));
+    }
+    let stderr = common::stderr(&common::run(es.url(), dir.path(), &args));
+    assert!(stderr.contains("Cannot check when the source data was last modified"), "{}", stderr);
+    assert!(stderr.contains("has the metadata of dataset another-dataset"), "{}", stderr);
+    assert_eq!(portal.requests().len(), 3);
+}